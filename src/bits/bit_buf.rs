@@ -1,7 +1,10 @@
-mod sanity;
+use error;
+mod check;
 
+/// Bits that were read from the input but not consumed yet, the first bit in
+/// the least significant position.
 pub struct BitBuf {
-  buf: u32,
+  buf: u64,
   bits: uint,
 }
 
@@ -11,26 +14,51 @@ impl BitBuf {
   }
 
   #[inline]
-  pub fn shift_bits(&mut self, bits: uint) -> u32 {
-    sanity!(bits <= self.bits);
-    let ret = self.buf & !(!0 << bits);
-    self.buf = self.buf >> bits;
+  pub fn shift_bits(&mut self, bits: uint) -> Result<u64, error::Error> {
+    check!(bits <= self.bits, error::NotEnoughBits(bits, self.bits));
+    let ret = self.buf & low_mask(bits);
+    self.buf = if bits < 64 { self.buf >> bits } else { 0 };
     self.bits = self.bits - bits;
-    ret
+    Ok(ret)
   }
 
   #[inline]
-  pub fn unshift_bits(&mut self, bits: uint, data: u32) {
-    sanity!(bits + self.bits <= 32);
-    self.buf = (self.buf << bits) | data;
+  pub fn unshift_bits(&mut self, bits: uint, data: u64)
+    -> Result<(), error::Error>
+  {
+    check!(bits + self.bits <= 64, error::BitBufOverflow(bits + self.bits));
+    self.buf = if bits < 64 { (self.buf << bits) | data } else { data };
     self.bits = self.bits + bits;
+    Ok(())
   }
 
   #[inline]
-  pub fn push_byte(&mut self, byte: u8) {
-    sanity!(self.bits + 8 <= 32);
-    self.buf = self.buf | (byte as u32 << self.bits);
+  pub fn push_byte(&mut self, byte: u8) -> Result<(), error::Error> {
+    check!(self.bits + 8 <= 64, error::BitBufOverflow(self.bits + 8));
+    self.buf = self.buf | (byte as u64 << self.bits);
     self.bits = self.bits + 8;
+    Ok(())
+  }
+
+  /// Pushes the bytes of the little-endian `word` until the buffer has more
+  /// than 56 bits and returns the number of bytes pushed.
+  #[inline]
+  pub fn push_word(&mut self, word: u64) -> Result<uint, error::Error> {
+    check!(self.bits <= 56, error::BitBufOverflow(self.bits + 8));
+    let bytes = (64 - self.bits) / 8;
+    self.buf = self.buf | (word << self.bits);
+    self.bits = self.bits + 8 * bytes;
+    self.buf = self.buf & low_mask(self.bits);
+    Ok(bytes)
+  }
+
+  /// Drops the bits after the first `bits` bits.
+  #[inline]
+  pub fn truncate_bits(&mut self, bits: uint) -> Result<(), error::Error> {
+    check!(bits <= self.bits, error::NotEnoughBits(bits, self.bits));
+    self.buf = self.buf & low_mask(bits);
+    self.bits = bits;
+    Ok(())
   }
 
   #[inline]
@@ -39,3 +67,8 @@ impl BitBuf {
     self.bits = 0;
   }
 }
+
+#[inline]
+fn low_mask(bits: uint) -> u64 {
+  if bits < 64 { !(!0u64 << bits) } else { !0u64 }
+}
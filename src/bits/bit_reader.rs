@@ -1,9 +1,16 @@
 use std::cmp;
 use BitBuf;
-mod sanity;
-
+use error;
+mod check;
+
+/// Reads bits from the least significant one (as in RFC 1951). The bits are
+/// loaded from the chunk into a 64-bit `BitBuf` several bytes at once; bytes
+/// that were loaded but not consumed are given back to the chunk when the
+/// reader is closed, so that the rest of the chunk starts right after the
+/// consumed bits.
 pub struct BitReader<'self> {
-  priv rest_bytes: &'self [u8],
+  priv chunk: &'self [u8],
+  priv pos: uint,
   priv bit_buf: BitBuf,
 }
 
@@ -12,101 +19,202 @@ pub struct BitReader<'self> {
 // destroys `self`, but returns `&'self`)
 impl<'self> BitReader<'self> {
   pub fn new<'a>(bit_buf: BitBuf, chunk: &'a [u8]) -> BitReader<'a> {
-    BitReader { rest_bytes: chunk, bit_buf: bit_buf }
+    BitReader { chunk: chunk, pos: 0, bit_buf: bit_buf }
   }
 
-  pub fn close_to_buf(self) -> BitBuf {
-    let rest_bytes = self.rest_bytes;
-    let mut bit_buf = self.bit_buf;
+  pub fn close_to_buf(self) -> Result<BitBuf, error::Error> {
+    let BitReader { chunk, pos, bit_buf } = self;
+    let mut bit_buf = bit_buf;
 
-    sanity!(rest_bytes.len() * 8 + bit_buf.bits <= 16);
-    for rest_bytes.iter().advance |&byte| {
-      bit_buf.push_byte(byte);
+    let total = (chunk.len() - pos) * 8 + bit_buf.bits;
+    check!(total <= 64, error::BitBufOverflow(total));
+    for chunk.slice(pos, chunk.len()).iter().advance |&byte| {
+      try!(bit_buf.push_byte(byte));
     }
-    bit_buf
+    Ok(bit_buf)
   }
 
-  pub fn close_to_rest(self) -> &'self [u8] {
-    sanity!(self.bit_buf.bits < 8);
-    self.rest_bytes
+  pub fn close_to_rest(self) -> Result<&'self [u8], error::Error> {
+    let mut reader = self;
+    reader.give_back();
+    check!(reader.bit_buf.bits < 8, error::BufferedBits(reader.bit_buf.bits));
+    Ok(reader.chunk.slice(reader.pos, reader.chunk.len()))
   }
 
   pub fn close(self) -> (BitBuf, &'self [u8]) {
-    let BitReader { bit_buf, rest_bytes } = self;
-    (bit_buf, rest_bytes)
+    let mut reader = self;
+    reader.give_back();
+    let BitReader { chunk, pos, bit_buf } = reader;
+    (bit_buf, chunk.slice(pos, chunk.len()))
+  }
+
+  /// Number of bytes of the chunk that were consumed, not counting the whole
+  /// bytes that were loaded into the buffer but not read yet.
+  pub fn consumed(&self) -> uint {
+    self.pos - cmp::min(self.bit_buf.bits / 8, self.pos)
   }
 
   pub fn has_bits(&self, bits: uint) -> bool {
-    sanity!(bits <= 16);
-    if self.rest_bytes.len() >= 2 {
-      true
-    } else {
-      bits <= self.rest_bytes.len() * 8 + self.bit_buf.bits
-    }
+    bits <= self.bit_buf.bits || bits <= self.available_bits()
+  }
+
+  pub fn available_bits(&self) -> uint {
+    (self.chunk.len() - self.pos) * 8 + self.bit_buf.bits
   }
 
   pub fn has_bytes(&self, bytes: uint) -> bool {
-    bytes <= self.bit_buf.bits / 8 + self.rest_bytes.len()
+    bytes <= self.bit_buf.bits / 8 + (self.chunk.len() - self.pos)
   }
 
   pub fn skip_to_byte(&mut self) {
-    sanity!(self.bit_buf.bits < 8);
-    self.bit_buf.clear();
+    let partial = self.bit_buf.bits % 8;
+    self.bit_buf.shift_bits(partial);
   }
 
-  priv fn read_bits(&mut self, bits: uint) -> u32 {
-    sanity!(bits <= 16);
-    while self.bit_buf.bits < bits {
-      self.bit_buf.push_byte(*self.rest_bytes.head());
-      self.rest_bytes = self.rest_bytes.tail();
+  // Loads as many whole bytes as fit into the buffer, eight at once if the
+  // chunk has enough of them. Afterwards the buffer has at least 57 bits
+  // unless the chunk is exhausted.
+  #[inline]
+  priv fn refill(&mut self) -> Result<(), error::Error> {
+    let chunk = self.chunk;
+    if self.bit_buf.bits <= 56 && self.pos + 8 <= chunk.len() {
+      let p = self.pos;
+      let word = (chunk[p] as u64) | (chunk[p + 1] as u64 << 8)
+        | (chunk[p + 2] as u64 << 16) | (chunk[p + 3] as u64 << 24)
+        | (chunk[p + 4] as u64 << 32) | (chunk[p + 5] as u64 << 40)
+        | (chunk[p + 6] as u64 << 48) | (chunk[p + 7] as u64 << 56);
+      let bytes = try!(self.bit_buf.push_word(word));
+      self.pos = p + bytes;
+    } else {
+      while self.bit_buf.bits <= 56 && self.pos < chunk.len() {
+        try!(self.bit_buf.push_byte(chunk[self.pos]));
+        self.pos = self.pos + 1;
+      }
     }
+    Ok(())
+  }
+
+  // Returns the whole bytes that were loaded from the chunk but not consumed.
+  priv fn give_back(&mut self) {
+    let bytes = cmp::min(self.bit_buf.bits / 8, self.pos);
+    let bits = self.bit_buf.bits - 8 * bytes;
+    self.bit_buf.truncate_bits(bits);
+    self.pos = self.pos - bytes;
+  }
 
+  #[inline]
+  priv fn read_bits(&mut self, bits: uint) -> Result<u64, error::Error> {
+    check!(bits <= 57, error::TooManyBits(bits, 57));
+    if self.bit_buf.bits < bits {
+      try!(self.refill());
+    }
     self.bit_buf.shift_bits(bits)
   }
 
-  pub fn read_bits8(&mut self, bits: uint) -> u8 {
-    sanity!(bits <= 8);
-    self.read_bits(bits) as u8
+  /// Returns the next `bits` bits without consuming them.
+  pub fn peek_bits16(&self, bits: uint) -> Result<u16, error::Error> {
+    check!(bits <= 16, error::TooManyBits(bits, 16));
+    Ok(try!(self.peek_bits32(bits)) as u16)
+  }
+
+  pub fn peek_bits32(&self, bits: uint) -> Result<u32, error::Error> {
+    check!(bits <= 32, error::TooManyBits(bits, 32));
+    check!(bits <= self.available_bits(),
+      error::NotEnoughBits(bits, self.available_bits()));
+    let mut data = self.bit_buf.buf;
+    let mut have = self.bit_buf.bits;
+    let mut i = self.pos;
+    while have < bits {
+      data = data | (self.chunk[i] as u64 << have);
+      have = have + 8;
+      i = i + 1;
+    }
+
+    Ok((data & !(!0u64 << bits)) as u32)
+  }
+
+  pub fn read_bits8(&mut self, bits: uint) -> Result<u8, error::Error> {
+    check!(bits <= 8, error::TooManyBits(bits, 8));
+    Ok(try!(self.read_bits(bits)) as u8)
+  }
+
+  pub fn read_bits16(&mut self, bits: uint) -> Result<u16, error::Error> {
+    check!(bits <= 16, error::TooManyBits(bits, 16));
+    Ok(try!(self.read_bits(bits)) as u16)
+  }
+
+  pub fn read_bits32(&mut self, bits: uint) -> Result<u32, error::Error> {
+    check!(bits <= 32, error::TooManyBits(bits, 32));
+    Ok(try!(self.read_bits(bits)) as u32)
   }
 
-  pub fn read_bits16(&mut self, bits: uint) -> u16 {
-    sanity!(bits <= 16);
-    self.read_bits(bits) as u16
+  /// Reads up to 57 bits at once, which the buffer always holds after a
+  /// refill (if the input has them).
+  pub fn read_bits64(&mut self, bits: uint) -> Result<u64, error::Error> {
+    self.read_bits(bits)
   }
 
-  pub fn read_rev_bits8(&mut self, bits: uint) -> u8 {
+  pub fn read_rev_bits8(&mut self, bits: uint) -> Result<u8, error::Error> {
     // TODO: this could surely be optimized
-    sanity!(bits <= 8);
+    check!(bits <= 8, error::TooManyBits(bits, 8));
+    check!(bits <= self.available_bits(),
+      error::NotEnoughBits(bits, self.available_bits()));
     let mut res: u8 = 0;
     for bits.times {
-      let bit = self.read_bits8(1);
+      let bit = try!(self.read_bits8(1));
       res = (res << 1) | bit;
     }
-    res
+    Ok(res)
+  }
+
+  pub fn unread_bits8(&mut self, bits: uint, data: u8)
+    -> Result<(), error::Error>
+  {
+    check!(bits <= 8, error::TooManyBits(bits, 8));
+    self.unread_bits(bits, data as u64)
+  }
+
+  pub fn unread_bits16(&mut self, bits: uint, data: u16)
+    -> Result<(), error::Error>
+  {
+    check!(bits <= 16, error::TooManyBits(bits, 16));
+    self.unread_bits(bits, data as u64)
   }
 
-  pub fn unread_bits8(&mut self, bits: uint, data: u8) {
-    sanity!(bits <= 8);
-    self.bit_buf.unshift_bits(bits, data as u32);
+  pub fn unread_bits32(&mut self, bits: uint, data: u32)
+    -> Result<(), error::Error>
+  {
+    check!(bits <= 32, error::TooManyBits(bits, 32));
+    self.unread_bits(bits, data as u64)
   }
 
-  pub fn unread_bits16(&mut self, bits: uint, data: u16) {
-    sanity!(bits <= 16);
-    self.bit_buf.unshift_bits(bits, data as u32);
+  priv fn unread_bits(&mut self, bits: uint, data: u64)
+    -> Result<(), error::Error>
+  {
+    if self.bit_buf.bits + bits > 64 {
+      self.give_back();
+    }
+    self.bit_buf.unshift_bits(bits, data)
   }
 
-  pub fn read_u16(&mut self) -> u16 {
+  pub fn read_u16(&mut self) -> Result<u16, error::Error> {
     self.read_bits16(16)
   }
 
-  pub fn read_byte_chunk(&mut self, limit: uint) -> &'self [u8] {
-    sanity!(self.bit_buf.bits == 0);
-    let len = cmp::min(limit, self.rest_bytes.len());
-    let chunk = self.rest_bytes.slice(0, len);
-    let rest = self.rest_bytes.slice(len, self.rest_bytes.len());
+  pub fn read_u32(&mut self) -> Result<u32, error::Error> {
+    self.read_bits32(32)
+  }
+
+  pub fn read_byte_chunk(&mut self, limit: uint)
+    -> Result<&'self [u8], error::Error>
+  {
+    self.give_back();
+    check!(self.bit_buf.bits == 0, error::BufferedBits(self.bit_buf.bits));
+    let len = cmp::min(limit, self.chunk.len() - self.pos);
+    let chunk = self.chunk.slice(self.pos, self.pos + len);
 
-    self.rest_bytes = rest;
-    chunk
+    self.pos = self.pos + len;
+    Ok(chunk)
   }
 }
 
@@ -118,6 +226,7 @@ mod test {
 
   use BitReader;
   use BitBuf;
+  use error;
 
   #[test]
   fn test_with_buf_carry() {
@@ -125,15 +234,15 @@ mod test {
 
     {
       let mut reader = BitReader::new(bit_buf, &[0b11110010, 0b101001_10]);
-      reader.read_bits16(10);
+      reader.read_bits16(10).unwrap();
       assert!(reader.has_bits(6) && !reader.has_bits(7));
 
-      bit_buf = reader.close_to_buf();
+      bit_buf = reader.close_to_buf().unwrap();
     }
 
     {
       let mut reader = BitReader::new(bit_buf, &[0b10_010010]);
-      assert_eq!(reader.read_bits16(12), 0b010010_101001);
+      assert_eq!(reader.read_bits16(12), Ok(0b010010_101001));
       assert!(reader.has_bits(2) && !reader.has_bits(3));
     }
   }
@@ -144,13 +253,13 @@ mod test {
 
     {
       let mut reader = BitReader::new(bit_buf, &[0b11_110010, 0b10100110]);
-      reader.read_bits16(6);
-      bit_buf = reader.close_to_buf();
+      reader.read_bits16(6).unwrap();
+      bit_buf = reader.close_to_buf().unwrap();
     };
 
     {
       let mut reader = BitReader::new(bit_buf, &[0b100_10010]);
-      assert_eq!(reader.read_bits16(15), 0b10010_10100110_11);
+      assert_eq!(reader.read_bits16(15), Ok(0b10010_10100110_11));
       assert!(reader.has_bits(3) && !reader.has_bits(4));
     };
   }
@@ -162,17 +271,17 @@ mod test {
     {
       let mut reader = BitReader::new(bit_buf, 
         &[0b11001_010, 0b111010_11, 0b01000110]);
-      assert_eq!(reader.read_bits8(3), 0b010);
-      assert_eq!(reader.read_bits8(7), 0b11_11001);
-      bit_buf = reader.close_to_buf();
+      assert_eq!(reader.read_bits8(3), Ok(0b010));
+      assert_eq!(reader.read_bits8(7), Ok(0b11_11001));
+      bit_buf = reader.close_to_buf().unwrap();
     };
 
     {
       let mut reader = BitReader::new(bit_buf,
         &[0b1001_0111, 0b0011_1011]);
-      assert_eq!(reader.read_bits8(6), 0b111010);
-      assert_eq!(reader.read_bits16(12), 0b0111_01000110);
-      assert_eq!(reader.read_bits8(8), 0b1011_1001);
+      assert_eq!(reader.read_bits8(6), Ok(0b111010));
+      assert_eq!(reader.read_bits16(12), Ok(0b0111_01000110));
+      assert_eq!(reader.read_bits8(8), Ok(0b1011_1001));
     };
   }
 
@@ -181,20 +290,20 @@ mod test {
     {
       let mut reader = BitReader::new(BitBuf::new(),
         &[0b10001_100, 0b01011_101]);
-      assert_eq!(reader.read_bits8(3), 0b100);
+      assert_eq!(reader.read_bits8(3), Ok(0b100));
       assert!(reader.has_bits(13) && !reader.has_bits(14));
-      assert_eq!(reader.read_bits8(8), 0b101_10001);
-      assert_eq!(reader.read_bits8(5), 0b01011);
+      assert_eq!(reader.read_bits8(8), Ok(0b101_10001));
+      assert_eq!(reader.read_bits8(5), Ok(0b01011));
       assert!(!reader.has_bits(1));
     };
 
     {
       let mut reader = BitReader::new(BitBuf::new(),
         &[0b10001_100, 0b010_11101, 0b10011101, 0b001_11001]);
-      assert_eq!(reader.read_bits8(3), 0b100);
-      assert_eq!(reader.read_bits16(10), 0b11101_10001);
+      assert_eq!(reader.read_bits8(3), Ok(0b100));
+      assert_eq!(reader.read_bits16(10), Ok(0b11101_10001));
       assert!(reader.has_bits(16));
-      assert_eq!(reader.read_bits16(16), 0b11001_10011101_010);
+      assert_eq!(reader.read_bits16(16), Ok(0b11001_10011101_010));
       assert!(reader.has_bits(3) && !reader.has_bits(4));
     };
   }
@@ -204,17 +313,17 @@ mod test {
     {
       let mut reader = BitReader::new(BitBuf::new(),
         &[0b111_01101, 0b01_011100]);
-      assert_eq!(reader.read_bits8(5), 0b01101);
+      assert_eq!(reader.read_bits8(5), Ok(0b01101));
       reader.skip_to_byte();
-      assert_eq!(reader.read_bits8(6), 0b011100);
+      assert_eq!(reader.read_bits8(6), Ok(0b011100));
     };
 
     {
       let mut reader = BitReader::new(BitBuf::new(), &[0b11101101, 0b01011100]);
       reader.skip_to_byte();
-      assert_eq!(reader.read_bits8(8), 0b11101101);
+      assert_eq!(reader.read_bits8(8), Ok(0b11101101));
       reader.skip_to_byte();
-      assert_eq!(reader.read_bits8(8), 0b01011100);
+      assert_eq!(reader.read_bits8(8), Ok(0b01011100));
       reader.skip_to_byte();
     };
   }
@@ -223,42 +332,64 @@ mod test {
   fn test_has_bytes() {
     let mut reader = BitReader::new(BitBuf::new(),
       &[0b1_1101101, 10, 20, 30]);
-    reader.read_bits8(7);
+    reader.read_bits8(7).unwrap();
     reader.skip_to_byte();
     assert!(reader.has_bytes(3) && !reader.has_bytes(4));
-    reader.read_bits16(16);
+    reader.read_bits16(16).unwrap();
     assert!(reader.has_bytes(1) && !reader.has_bytes(2));
-    reader.read_bits8(8);
+    reader.read_bits8(8).unwrap();
     assert!(reader.has_bytes(0) && !reader.has_bytes(1));
   }
 
+  #[test]
+  fn test_available_bits() {
+    let mut reader = BitReader::new(BitBuf::new(), &[10, 20, 30]);
+    assert_eq!(reader.available_bits(), 24);
+    reader.read_bits8(5).unwrap();
+    assert_eq!(reader.available_bits(), 19);
+    reader.unread_bits8(2, 0b10).unwrap();
+    assert_eq!(reader.available_bits(), 21);
+  }
+
+  #[test]
+  fn test_peek_bits() {
+    let mut reader = BitReader::new(BitBuf::new(),
+      &[0b1100_1010, 0b0110_0101, 0b1111_0000]);
+    reader.read_bits8(3).unwrap();
+    assert_eq!(reader.peek_bits16(11), Ok(0b100101_11001));
+    assert_eq!(reader.peek_bits16(0), Ok(0));
+    assert_eq!(reader.read_bits16(11), Ok(0b100101_11001));
+    assert_eq!(reader.peek_bits16(10), Ok(0b11110000_01));
+    assert_eq!(reader.available_bits(), 10);
+  }
+
   #[test]
   fn test_read_u16() {
     let mut reader = BitReader::new(BitBuf::new(),
       &[10, 0b11101101, 0b11001010, 20, 0b00010100, 0b10011100]);
-    reader.read_bits8(3);
+    reader.read_bits8(3).unwrap();
     reader.skip_to_byte();
-    assert_eq!(reader.read_u16(), 0b11001010_11101101);
-    reader.read_bits16(8);
-    assert_eq!(reader.read_u16(), 0b10011100_00010100);
+    assert_eq!(reader.read_u16(), Ok(0b11001010_11101101));
+    reader.read_bits16(8).unwrap();
+    assert_eq!(reader.read_u16(), Ok(0b10011100_00010100));
   }
 
   #[test]
   fn test_read_byte_chunk() {
     let mut reader = BitReader::new(BitBuf::new(),
       &[2,3,5,7,11,13,17,19,23,29]);
-    reader.read_bits8(5);
+    reader.read_bits8(5).unwrap();
     reader.skip_to_byte();
-    assert_eq!(reader.read_byte_chunk(6), &[3,5,7,11,13,17]);
-    assert_eq!(reader.read_byte_chunk(6), &[19,23,29]);
+    assert_eq!(reader.read_byte_chunk(6), Ok(&[3,5,7,11,13,17]));
+    assert_eq!(reader.read_byte_chunk(6), Ok(&[19,23,29]));
   }
 
   #[test]
   fn test_read_rev_bits() {
     let mut reader = BitReader::new(BitBuf::new(),
       &[0b1001_0111, 0b10100_010]);
-    assert_eq!(reader.read_rev_bits8(4), 0b1110);
-    assert_eq!(reader.read_rev_bits8(7), 0b1001_010);
+    assert_eq!(reader.read_rev_bits8(4), Ok(0b1110));
+    assert_eq!(reader.read_rev_bits8(7), Ok(0b1001_010));
   }
 
   #[test]
@@ -268,25 +399,108 @@ mod test {
     {
       let mut reader = BitReader::new(bit_buf,
         &[0b11_01_0001, 0b01101_110]);
-      assert_eq!(reader.read_bits8(4), 0b0001);
-      reader.unread_bits8(4, 0b0001);
-      assert_eq!(reader.read_bits8(6), 0b01_0001);
-      reader.unread_bits8(4, 0b01_00);
-      assert_eq!(reader.read_bits16(9), 0b110_11_01_00);
-      bit_buf = reader.close_to_buf();
+      assert_eq!(reader.read_bits8(4), Ok(0b0001));
+      reader.unread_bits8(4, 0b0001).unwrap();
+      assert_eq!(reader.read_bits8(6), Ok(0b01_0001));
+      reader.unread_bits8(4, 0b01_00).unwrap();
+      assert_eq!(reader.read_bits16(9), Ok(0b110_11_01_00));
+      bit_buf = reader.close_to_buf().unwrap();
     };
 
     {
       let mut reader = BitReader::new(bit_buf,
         &[0b1_10_01110, 0b10_100110, 0b001100_11]);
-      assert_eq!(reader.read_bits16(10), 0b01110_01101);
-      reader.unread_bits16(10, 0b01110_01101);
-      assert_eq!(reader.read_bits16(12), 0b10_01110_01101);
-      reader.unread_bits16(3, 0b100);
-      assert_eq!(reader.read_bits16(10), 0b100110_1100);
+      assert_eq!(reader.read_bits16(10), Ok(0b01110_01101));
+      reader.unread_bits16(10, 0b01110_01101).unwrap();
+      assert_eq!(reader.read_bits16(12), Ok(0b10_01110_01101));
+      reader.unread_bits16(3, 0b100).unwrap();
+      assert_eq!(reader.read_bits16(10), Ok(0b100110_1100));
+    };
+  }
+
+  #[test]
+  fn test_read_wide_bits() {
+    let bytes = [0x01u8, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef,
+      0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32, 0x10];
+    let mut reader = BitReader::new(BitBuf::new(), bytes);
+    assert_eq!(reader.read_bits8(4), Ok(0x1));
+    assert!(reader.has_bits(57));
+    assert_eq!(reader.read_bits64(57), Ok(0xfc_dab8_9674_5230));
+    assert_eq!(reader.read_bits32(32), Ok(0xc5d6_e7f7));
+    assert_eq!(reader.peek_bits32(27), Ok(0x192_a3b4));
+    assert_eq!(reader.available_bits(), 35);
+  }
+
+  #[test]
+  fn test_read_u32_across_chunks() {
+    let mut bit_buf = BitBuf::new();
+
+    {
+      let mut reader = BitReader::new(bit_buf, &[0xaa, 0x78, 0x56]);
+      assert_eq!(reader.read_bits8(8), Ok(0xaa));
+      assert!(reader.has_bits(16) && !reader.has_bits(17));
+      bit_buf = reader.close_to_buf().unwrap();
+    };
+
+    {
+      let mut reader = BitReader::new(bit_buf, &[0x34, 0x12, 0xbb]);
+      assert_eq!(reader.read_u32(), Ok(0x1234_5678));
+      assert_eq!(reader.close_to_rest(), Ok(&[0xbb]));
     };
   }
 
+  #[test]
+  fn test_close_gives_back_bytes() {
+    let bytes = [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+    let mut reader = BitReader::new(BitBuf::new(), bytes);
+    assert_eq!(reader.read_bits16(12), Ok(0x201));
+    reader.skip_to_byte();
+    let (bit_buf, rest) = reader.close();
+    assert_eq!(bit_buf.bits, 0);
+    assert_eq!(rest, &[3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+
+    let mut reader = BitReader::new(BitBuf::new(), bytes);
+    assert_eq!(reader.read_bits8(3), Ok(1));
+    reader.unread_bits8(3, 1).unwrap();
+    assert_eq!(reader.read_bits16(16), Ok(0x201));
+    reader.unread_bits16(16, 0x201).unwrap();
+    let (bit_buf, rest) = reader.close();
+    assert_eq!(bit_buf.bits, 0);
+    assert_eq!(rest.len(), bytes.len());
+  }
+
+  #[test]
+  fn test_read_errors() {
+    let mut reader = BitReader::new(BitBuf::new(), &[0b1010_0101, 0xff]);
+    assert_eq!(reader.read_bits8(9), Err(error::TooManyBits(9, 8)));
+    assert_eq!(reader.read_bits64(58), Err(error::TooManyBits(58, 57)));
+    assert_eq!(reader.read_bits8(3), Ok(0b101));
+    assert_eq!(reader.peek_bits16(14), Err(error::NotEnoughBits(14, 13)));
+    assert_eq!(reader.read_bits16(14), Err(error::NotEnoughBits(14, 13)));
+    assert_eq!(reader.read_byte_chunk(1), Err(error::BufferedBits(5)));
+    assert_eq!(reader.read_bits8(5), Ok(0b10100));
+    assert_eq!(reader.read_byte_chunk(1), Ok(&[0xff]));
+
+    let mut bit_buf = BitBuf::new();
+    for 7.times {
+      let reader = BitReader::new(bit_buf, &[1]);
+      bit_buf = reader.close_to_buf().unwrap();
+    }
+    let reader = BitReader::new(bit_buf, &[2, 3]);
+    match reader.close_to_buf() {
+      Err(err) => assert_eq!(err, error::BitBufOverflow(72)),
+      Ok(_) => fail!("close_to_buf: the buffer should overflow"),
+    }
+
+    let mut bit_buf = BitBuf::new();
+    for 7.times {
+      let reader = BitReader::new(bit_buf, &[1]);
+      bit_buf = reader.close_to_buf().unwrap();
+    }
+    let reader = BitReader::new(bit_buf, &[]);
+    assert_eq!(reader.close_to_rest(), Err(error::BufferedBits(56)));
+  }
+
   #[bench]
   fn bench_bits(b: &mut test::BenchHarness) {
     let bytes = vec::from_fn(850, |_| rand::random());
@@ -296,37 +510,37 @@ mod test {
 
       for 100.times {
         assert!(reader.has_bits(5));
-        reader.read_bits8(5);
+        reader.read_bits8(5).unwrap();
       }
 
       for 200.times {
         assert!(reader.has_bits(13));
-        reader.read_bits16(13);
+        reader.read_bits16(13).unwrap();
       }
 
       for 1000.times {
         assert!(reader.has_bits(1));
-        reader.read_bits8(1);
+        reader.read_bits8(1).unwrap();
       }
 
       for 300.times {
         assert!(reader.has_bits(5));
-        reader.read_bits8(3);
-        reader.read_bits8(2);
+        reader.read_bits8(3).unwrap();
+        reader.read_bits8(2).unwrap();
       }
 
       for 200.times {
         assert!(reader.has_bits(5));
-        let x = reader.read_bits8(5);
-        reader.unread_bits8(5, x);
+        let x = reader.read_bits8(5).unwrap();
+        reader.unread_bits8(5, x).unwrap();
         assert!(reader.has_bits(13));
-        let y = reader.read_bits16(13);
-        reader.unread_bits16(13, y);
+        let y = reader.read_bits16(13).unwrap();
+        reader.unread_bits16(13, y).unwrap();
       }
 
       for 100.times {
         assert!(reader.has_bits(9));
-        reader.read_bits16(9);
+        reader.read_bits16(9).unwrap();
       }
     };
   }
@@ -341,7 +555,7 @@ mod test {
       for 400.times {
         for (&[3u, 1, 6, 4, 1, 1]).iter().advance |&b| {
           assert!(reader.has_bits(b));
-          reader.read_rev_bits8(b);
+          reader.read_rev_bits8(b).unwrap();
         }
       }
     };
@@ -355,7 +569,7 @@ mod test {
       let mut reader = BitReader::new(BitBuf::new(), bytes);
       reader.skip_to_byte();
       while reader.has_bytes(4) {
-        reader.read_byte_chunk(32);
+        reader.read_byte_chunk(32).unwrap();
       }
     };
   }
@@ -0,0 +1,179 @@
+use recv;
+use std::uint;
+mod sanity;
+
+/// Writes bits from the least significant one (as in RFC 1951), the inverse
+/// of `BitReader`. Complete bytes are collected until they are sent to a
+/// receiver by `send`.
+pub struct BitWriter {
+  priv buf: u32,
+  priv bits: uint,
+  priv bytes: ~[u8],
+  priv position: u64,
+}
+
+impl BitWriter {
+  pub fn new() -> BitWriter {
+    BitWriter { buf: 0, bits: 0, bytes: ~[], position: 0 }
+  }
+
+  #[inline]
+  pub fn write_bits(&mut self, bits: uint, data: u32) {
+    sanity!(bits <= 24 && (bits == 0 || data >> bits == 0));
+    self.buf = self.buf | (data << self.bits);
+    self.bits = self.bits + bits;
+    self.position = self.position + bits as u64;
+
+    while self.bits >= 8 {
+      self.bytes.push(self.buf as u8);
+      self.buf = self.buf >> 8;
+      self.bits = self.bits - 8;
+    }
+  }
+
+  pub fn write_bits8(&mut self, bits: uint, data: u8) {
+    sanity!(bits <= 8);
+    self.write_bits(bits, data as u32);
+  }
+
+  pub fn write_bits16(&mut self, bits: uint, data: u16) {
+    sanity!(bits <= 16);
+    self.write_bits(bits, data as u32);
+  }
+
+  /// Writes `data` from the most significant bit, as Huffman codes are
+  /// written. Read back by `BitReader::read_rev_bits8`.
+  pub fn write_rev_bits8(&mut self, bits: uint, data: u8) {
+    sanity!(bits <= 8);
+    self.write_rev_bits16(bits, data as u16);
+  }
+
+  pub fn write_rev_bits16(&mut self, bits: uint, data: u16) {
+    sanity!(bits <= 16);
+    let mut rev = 0u32;
+    for uint::range(0, bits) |i| {
+      rev = (rev << 1) | ((data as u32 >> i) & 1);
+    }
+    self.write_bits(bits, rev);
+  }
+
+  /// Writes a Huffman code that is already reversed, so that it can be
+  /// written as a number.
+  #[inline]
+  pub fn write_code(&mut self, len: u8, code: u16) {
+    self.write_bits(len as uint, code as u32);
+  }
+
+  pub fn write_u16(&mut self, data: u16) {
+    self.write_bits16(16, data);
+  }
+
+  /// Pads the last byte with zero bits.
+  pub fn align(&mut self) {
+    if self.bits > 0 {
+      self.write_bits(8 - self.bits, 0);
+    }
+  }
+
+  pub fn write_bytes(&mut self, bytes: &[u8]) {
+    if self.bits != 0 {
+      fail!(fmt!("BitWriter::write_bytes: %u bits are not aligned", self.bits));
+    }
+    self.bytes.push_all(bytes);
+    self.position = self.position + bytes.len() as u64 * 8;
+  }
+
+  /// Bits that do not make up a whole byte yet and are not sent.
+  pub fn pending_bits(&self) -> uint {
+    self.bits
+  }
+
+  /// Number of bits written so far.
+  pub fn bit_position(&self) -> u64 {
+    self.position
+  }
+
+  /// Sends the complete bytes to `recv`.
+  pub fn send<R: recv::Recv<u8>>(&mut self, recv: R) -> R {
+    let recv = recv.receive(self.bytes);
+    self.bytes.truncate(0);
+    recv
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::{rand, vec};
+  use std::rand::{RngUtil};
+  use BitWriter;
+  use BitReader;
+  use BitBuf;
+
+  #[test]
+  fn test_write_bits() {
+    let mut out = BitWriter::new();
+    out.write_bits(3, 0b101);
+    out.write_bits(7, 0b1100110);
+    out.write_bits(10, 0b11_1111_0000);
+    assert_eq!(out.bit_position(), 20);
+    out.align();
+    let buf = out.send(~[]);
+
+    assert_eq!(buf, ~[0b00110_101, 0b110000_11, 0b0000_1111]);
+    assert_eq!(out.bit_position(), 24);
+  }
+
+  #[test]
+  fn test_write_bytes() {
+    let mut out = BitWriter::new();
+    out.write_bits(2, 0b11);
+    out.align();
+    out.write_bytes(&[10, 20, 30]);
+    let buf = out.send(~[]);
+    let buf = out.send(buf);
+
+    assert_eq!(buf, ~[0b11, 10, 20, 30]);
+    assert_eq!(out.bit_position(), 32);
+  }
+
+  #[test]
+  fn test_write_rev_bits() {
+    let mut out = BitWriter::new();
+    out.write_rev_bits8(3, 0b110);
+    out.write_rev_bits16(9, 0b1_0000_0011);
+    out.align();
+
+    assert_eq!(out.send(~[]), ~[0b0000_1_011, 0b0000_1100]);
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let mut rng = rand::IsaacRng::new_seeded(&[42]);
+    let fields = do vec::from_fn(1000) |_| {
+      let bits = rng.gen_uint_range(0, 9);
+      let data = (rng.gen::<u8>() as uint & ((1 << bits) - 1)) as u8;
+      (rng.gen_uint_range(0, 3), bits, data)
+    };
+
+    let mut out = BitWriter::new();
+    for fields.iter().advance |&(kind, bits, data)| {
+      match kind {
+        0 => out.write_bits8(bits, data),
+        1 => out.write_rev_bits8(bits, data),
+        _ => out.write_u16(data as u16 * 257),
+      }
+    }
+    out.align();
+    let bytes = out.send(~[]);
+
+    let mut reader = BitReader::new(BitBuf::new(), bytes);
+    for fields.iter().advance |&(kind, bits, data)| {
+      match kind {
+        0 => assert_eq!(reader.read_bits8(bits), Ok(data)),
+        1 => assert_eq!(reader.read_rev_bits8(bits), Ok(data)),
+        _ => assert_eq!(reader.read_u16(), Ok(data as u16 * 257)),
+      }
+    }
+    assert!(reader.available_bits() < 8);
+  }
+}
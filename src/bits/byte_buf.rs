@@ -1,43 +1,145 @@
-mod sanity;
+use std::uint;
+use error;
+mod check;
 
+/// Bytes left over from previous chunks. Shifted bytes are only skipped (the
+/// vector is compacted when more bytes are pushed), so that `shift_byte` is
+/// O(1) and the vector is not reallocated in the steady state.
 #[deriving(Clone)]
 pub struct ByteBuf {
-  pub buf: ~[u8],
+  priv buf: ~[u8],
+  priv start: uint,
 }
 
 impl ByteBuf {
   pub fn new() -> ByteBuf {
-    ByteBuf { buf: ~[] }
+    ByteBuf { buf: ~[], start: 0 }
   }
 
   pub fn is_empty(&self) -> bool {
-    self.buf.is_empty()
+    self.start == self.buf.len()
   }
 
   pub fn byte_count(&self) -> uint {
-    self.buf.len()
+    self.buf.len() - self.start
   }
 
-  pub fn shift_byte(&mut self) -> u8 {
-    sanity!(self.buf.len() > 0);
-    self.buf.shift()
+  pub fn shift_byte(&mut self) -> Option<u8> {
+    if self.is_empty() {
+      return None;
+    }
+    let byte = self.buf[self.start];
+    self.start = self.start + 1;
+    if self.is_empty() {
+      self.clear();
+    }
+    Some(byte)
   }
 
   pub fn push_bytes(&mut self, bytes: &[u8]) {
+    // moving the bytes to the front costs at most as much as the shifts that
+    // preceded it
+    if self.start > 0 && self.start >= self.byte_count() {
+      let count = self.byte_count();
+      for uint::range(0, count) |i| {
+        self.buf[i] = self.buf[self.start + i];
+      }
+      self.buf.truncate(count);
+      self.start = 0;
+    }
     self.buf.push_all(bytes);
   }
 
+  /// Passes the bytes to `body`, which returns the rest that it did not
+  /// consume (a suffix of the bytes). A rest longer than the bytes is an error
+  /// that leaves all of them in the buffer; the value from `body` is returned
+  /// in any case.
   pub fn consume_buf<'a, A, T>(
     &mut self,
     arg: A,
     body: &once fn(A, &'a [u8]) -> (T, Option<&'a [u8]>)
-  ) -> T {
-    let (x, new_buf) = 
-      match body(arg, self.buf) {
-        (x, None)       => (x, ~[]),
-        (x, Some(rest)) => (x, rest.to_owned()),
+  ) -> (T, Result<(), error::Error>) {
+    let count = self.byte_count();
+    let (x, rest_len) =
+      match body(arg, self.buf.slice(self.start, self.buf.len())) {
+        (x, None)       => (x, 0),
+        (x, Some(rest)) => (x, rest.len()),
       };
-    self.buf = new_buf;
-    x
+    (x, self.consumed(count, rest_len))
+  }
+
+  priv fn consumed(&mut self, count: uint, rest_len: uint)
+    -> Result<(), error::Error>
+  {
+    check!(rest_len <= count, error::BufferedBytes(rest_len));
+    self.start = self.buf.len() - rest_len;
+    if self.is_empty() {
+      self.clear();
+    }
+    Ok(())
+  }
+
+  priv fn clear(&mut self) {
+    self.buf.truncate(0);
+    self.start = 0;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use ByteBuf;
+  use error;
+
+  #[test]
+  fn test_shift_and_push() {
+    let mut buf = ByteBuf::new();
+    let mut pushed = 0u;
+    let mut shifted = 0u;
+    for 1000.times {
+      buf.push_bytes(&[pushed as u8, (pushed + 1) as u8, (pushed + 2) as u8]);
+      pushed = pushed + 3;
+      for 2.times {
+        assert_eq!(buf.shift_byte(), Some(shifted as u8));
+        shifted = shifted + 1;
+      }
+    }
+    assert_eq!(buf.byte_count(), 1000);
+
+    while !buf.is_empty() {
+      assert_eq!(buf.shift_byte(), Some(shifted as u8));
+      shifted = shifted + 1;
+      buf.push_bytes(&[]);
+    }
+    assert_eq!(shifted, pushed);
+    assert_eq!(buf.shift_byte(), None);
+  }
+
+  #[test]
+  fn test_consume_buf() {
+    let mut buf = ByteBuf::new();
+    buf.push_bytes(&[1, 2, 3, 4, 5]);
+    assert_eq!(buf.shift_byte(), Some(1));
+
+    let (x, res) = do buf.consume_buf(10) |arg, bytes| {
+      assert_eq!(bytes, &[2, 3, 4, 5]);
+      (arg + 1, Some(bytes.slice(3, 4)))
+    };
+    assert_eq!((x, res), (11, Ok(())));
+    assert_eq!(buf.byte_count(), 1);
+
+    buf.push_bytes(&[6, 7]);
+    do buf.consume_buf(()) |(), bytes| {
+      assert_eq!(bytes, &[5, 6, 7]);
+      ((), None)
+    };
+    assert!(buf.is_empty());
+
+    buf.push_bytes(&[8, 9]);
+    let ((), res) = do buf.consume_buf(()) |(), _bytes| {
+      ((), Some(&[1, 2, 3]))
+    };
+    assert_eq!(res, Err(error::BufferedBytes(3)));
+    assert_eq!(buf.byte_count(), 2);
+    assert_eq!(buf.shift_byte(), Some(8));
   }
 }
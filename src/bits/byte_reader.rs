@@ -1,5 +1,6 @@
 use ByteBuf;
-mod sanity;
+use error;
+mod check;
 
 pub struct ByteReader<'self> {
   priv rest_bytes: &'self [u8],
@@ -18,9 +19,10 @@ impl<'self> ByteReader<'self> {
     byte_buf
   }
 
-  pub fn close_to_rest(self) -> &'self [u8] {
-    sanity!(self.byte_buf.is_empty());
-    self.rest_bytes
+  pub fn close_to_rest(self) -> Result<&'self [u8], error::Error> {
+    check!(self.byte_buf.is_empty(),
+      error::BufferedBytes(self.byte_buf.byte_count()));
+    Ok(self.rest_bytes)
   }
 
   pub fn close(self) -> (ByteBuf, &'self [u8]) {
@@ -29,17 +31,22 @@ impl<'self> ByteReader<'self> {
   }
 
   pub fn has_bytes(&self, n: uint) -> bool {
-    n <= self.rest_bytes.len() + self.byte_buf.byte_count()
+    n <= self.available_bytes()
   }
 
-  pub fn read_byte(&mut self) -> u8 {
-    if !self.byte_buf.is_empty() {
-      self.byte_buf.shift_byte()
-    } else {
-      sanity!(self.rest_bytes.len() >= 1);
-      let res = self.rest_bytes[0];
-      self.rest_bytes = self.rest_bytes.tail();
-      res
+  pub fn available_bytes(&self) -> uint {
+    self.rest_bytes.len() + self.byte_buf.byte_count()
+  }
+
+  pub fn read_byte(&mut self) -> Result<u8, error::Error> {
+    match self.byte_buf.shift_byte() {
+      Some(byte) => Ok(byte),
+      None => {
+        check!(self.rest_bytes.len() >= 1, error::NotEnoughBytes(1, 0));
+        let res = self.rest_bytes[0];
+        self.rest_bytes = self.rest_bytes.tail();
+        Ok(res)
+      },
     }
   }
 
@@ -47,58 +54,66 @@ impl<'self> ByteReader<'self> {
     !(self.byte_buf.is_empty() && self.rest_bytes.is_empty())
   }
 
+  /// Passes the buffered bytes or the chunk to `body`, see
+  /// `ByteBuf::consume_buf`.
   pub fn consume_chunk<'a, A, T>(
     &mut self,
     arg: A,
     body: &once fn(A, &'a [u8]) -> (T, Option<&'a [u8]>)
-  ) -> T 
+  ) -> (T, Result<(), error::Error>)
   {
     if !self.byte_buf.is_empty() {
       self.byte_buf.consume_buf(arg, body)
     } else {
-      let (x, opt_rest) = body(arg, self.rest_bytes);
-      self.rest_bytes = match opt_rest {
-        Some(rest) => rest,
-        None => self.rest_bytes.slice(0, 0),
-      };
-      x
+      let rest_bytes = self.rest_bytes;
+      let (x, opt_rest) = body(arg, rest_bytes);
+      let rest_len = match opt_rest {
+          Some(rest) => rest.len(),
+          None => 0,
+        };
+      if rest_len > rest_bytes.len() {
+        return (x, Err(error::BufferedBytes(rest_len)));
+      }
+      self.rest_bytes = rest_bytes.slice(rest_bytes.len() - rest_len,
+        rest_bytes.len());
+      (x, Ok(()))
     }
   }
 
-  pub fn read_u32_be(&mut self) -> u32 {
-    sanity!(self.has_bytes(4));
-    let a = self.read_byte() as u32;
-    let b = self.read_byte() as u32;
-    let c = self.read_byte() as u32;
-    let d = self.read_byte() as u32;
+  pub fn read_u32_be(&mut self) -> Result<u32, error::Error> {
+    check!(self.has_bytes(4), error::NotEnoughBytes(4, self.available_bytes()));
+    let a = try!(self.read_byte()) as u32;
+    let b = try!(self.read_byte()) as u32;
+    let c = try!(self.read_byte()) as u32;
+    let d = try!(self.read_byte()) as u32;
 
-    (a << 24) | (b << 16) | (c << 8) | d
+    Ok((a << 24) | (b << 16) | (c << 8) | d)
   }
 
-  pub fn read_u16_be(&mut self) -> u16 {
-    sanity!(self.has_bytes(2));
-    let a = self.read_byte() as u16;
-    let b = self.read_byte() as u16;
+  pub fn read_u16_be(&mut self) -> Result<u16, error::Error> {
+    check!(self.has_bytes(2), error::NotEnoughBytes(2, self.available_bytes()));
+    let a = try!(self.read_byte()) as u16;
+    let b = try!(self.read_byte()) as u16;
 
-    (a << 8) | b
+    Ok((a << 8) | b)
   }
 
-  pub fn read_u32_le(&mut self) -> u32 {
-    sanity!(self.has_bytes(4));
-    let a = self.read_byte() as u32;
-    let b = self.read_byte() as u32;
-    let c = self.read_byte() as u32;
-    let d = self.read_byte() as u32;
+  pub fn read_u32_le(&mut self) -> Result<u32, error::Error> {
+    check!(self.has_bytes(4), error::NotEnoughBytes(4, self.available_bytes()));
+    let a = try!(self.read_byte()) as u32;
+    let b = try!(self.read_byte()) as u32;
+    let c = try!(self.read_byte()) as u32;
+    let d = try!(self.read_byte()) as u32;
 
-    (d << 24) | (c << 16) | (b << 8) | a
+    Ok((d << 24) | (c << 16) | (b << 8) | a)
   }
 
-  pub fn read_u16_le(&mut self) -> u16 {
-    sanity!(self.has_bytes(2));
-    let a = self.read_byte() as u16;
-    let b = self.read_byte() as u16;
+  pub fn read_u16_le(&mut self) -> Result<u16, error::Error> {
+    check!(self.has_bytes(2), error::NotEnoughBytes(2, self.available_bytes()));
+    let a = try!(self.read_byte()) as u16;
+    let b = try!(self.read_byte()) as u16;
 
-    (b << 8) | a
+    Ok((b << 8) | a)
   }
 }
 
@@ -106,6 +121,7 @@ impl<'self> ByteReader<'self> {
 mod test {
   use ByteReader;
   use ByteBuf;
+  use error;
 
   #[test]
   fn test_with_buf_carry() {
@@ -113,7 +129,7 @@ mod test {
 
     {
       let mut reader = ByteReader::new(byte_buf, &[10, 20, 30]);
-      reader.read_byte();
+      reader.read_byte().unwrap();
       assert!(reader.has_bytes(2) && !reader.has_bytes(3));
       byte_buf = reader.close_to_buf();
     };
@@ -129,11 +145,11 @@ mod test {
       let mut reader = ByteReader::new(byte_buf, &[60, 70, 80]);
       assert!(reader.has_some_bytes());
       assert!(reader.has_bytes(7));
-      assert_eq!(reader.read_byte(), 20);
-      assert_eq!(reader.read_byte(), 30);
-      assert_eq!(reader.read_byte(), 40);
-      assert_eq!(reader.read_byte(), 50);
-      assert_eq!(reader.read_byte(), 60);
+      assert_eq!(reader.read_byte(), Ok(20));
+      assert_eq!(reader.read_byte(), Ok(30));
+      assert_eq!(reader.read_byte(), Ok(40));
+      assert_eq!(reader.read_byte(), Ok(50));
+      assert_eq!(reader.read_byte(), Ok(60));
       let (_new_buf, rest) = reader.close();
       assert_eq!(rest, &[70, 80]);
     };
@@ -143,16 +159,17 @@ mod test {
   fn test_read_byte() {
     let mut reader = ByteReader::new(ByteBuf::new(), &[10, 20, 30, 40, 50]);
     assert!(reader.has_bytes(2));
-    assert_eq!(reader.read_byte(), 10);
-    assert_eq!(reader.read_byte(), 20);
+    assert_eq!(reader.read_byte(), Ok(10));
+    assert_eq!(reader.read_byte(), Ok(20));
     assert!(reader.has_bytes(3));
     assert!(reader.has_some_bytes());
     assert!(!reader.has_bytes(5));
-    assert_eq!(reader.read_byte(), 30);
-    assert_eq!(reader.read_byte(), 40);
-    assert_eq!(reader.read_byte(), 50);
+    assert_eq!(reader.read_byte(), Ok(30));
+    assert_eq!(reader.read_byte(), Ok(40));
+    assert_eq!(reader.read_byte(), Ok(50));
     assert!(!reader.has_bytes(1));
     assert!(reader.has_bytes(0));
+    assert_eq!(reader.read_byte(), Err(error::NotEnoughBytes(1, 0)));
   }
 
   #[test]
@@ -162,14 +179,14 @@ mod test {
 
       {
         let mut reader = ByteReader::new(buf, &[11, 22, 33, 44]);
-        assert_eq!(reader.read_byte(), 11);
+        assert_eq!(reader.read_byte(), Ok(11));
         buf = reader.close_to_buf();
       };
 
       {
         let mut reader = ByteReader::new(buf, &[55, 66]);
-        assert_eq!(reader.read_byte(), 22);
-        assert_eq!(reader.read_byte(), 33);
+        assert_eq!(reader.read_byte(), Ok(22));
+        assert_eq!(reader.read_byte(), Ok(33));
         buf = reader.close_to_buf();
       };
 
@@ -207,7 +224,7 @@ mod test {
 
         while reader.has_some_bytes() {
           // consume 4-byte cookies
-          let all_cookies = do reader.consume_chunk(42) |arg, chunk| {
+          let (all_cookies, res) = do reader.consume_chunk(42) |arg, chunk| {
             assert_eq!(arg, 42);
             let mut rest = chunk;
 
@@ -228,6 +245,7 @@ mod test {
 
             (all_cookies, opt_rest)
           };
+          assert_eq!(res, Ok(()));
 
           if all_cookies {
             break;
@@ -246,10 +264,10 @@ mod test {
         0x12, 0x34, 0x56, 0x78,
         0xd2, 0x3c,
       ]);
-    assert_eq!(reader.read_u16_be(), 0xabcd);
-    assert_eq!(reader.read_u32_be(), 0xdeadbeef);
-    assert_eq!(reader.read_u32_be(), 0x12345678);
-    assert_eq!(reader.read_u16_be(), 0xd23c);
+    assert_eq!(reader.read_u16_be(), Ok(0xabcd));
+    assert_eq!(reader.read_u32_be(), Ok(0xdeadbeef));
+    assert_eq!(reader.read_u32_be(), Ok(0x12345678));
+    assert_eq!(reader.read_u16_be(), Ok(0xd23c));
   }
 
   #[test]
@@ -261,10 +279,27 @@ mod test {
         0x3c, 0xd2,
       ]);
     
-    assert_eq!(reader.read_u16_le(), 0xabcd);
-    assert_eq!(reader.read_u32_le(), 0xdeadbeef);
-    assert_eq!(reader.read_u32_le(), 0x12345678);
-    assert_eq!(reader.read_u16_le(), 0xd23c);
+    assert_eq!(reader.read_u16_le(), Ok(0xabcd));
+    assert_eq!(reader.read_u32_le(), Ok(0xdeadbeef));
+    assert_eq!(reader.read_u32_le(), Ok(0x12345678));
+    assert_eq!(reader.read_u16_le(), Ok(0xd23c));
+  }
+
+  #[test]
+  fn test_read_errors() {
+    let mut byte_buf = ByteBuf::new();
+
+    {
+      let mut reader = ByteReader::new(byte_buf, &[10, 20, 30]);
+      assert_eq!(reader.read_u32_le(), Err(error::NotEnoughBytes(4, 3)));
+      assert_eq!(reader.read_u16_be(), Ok(0x0a14));
+      byte_buf = reader.close_to_buf();
+    };
+
+    {
+      let reader = ByteReader::new(byte_buf, &[40]);
+      assert_eq!(reader.close_to_rest(), Err(error::BufferedBytes(1)));
+    };
   }
 }
 
@@ -0,0 +1,99 @@
+use recv;
+
+/// Writes bytes and multi-byte numbers, the inverse of `ByteReader`. The bytes
+/// are collected until they are sent to a receiver by `send`.
+pub struct ByteWriter {
+  priv bytes: ~[u8],
+  priv position: u64,
+}
+
+impl ByteWriter {
+  pub fn new() -> ByteWriter {
+    ByteWriter { bytes: ~[], position: 0 }
+  }
+
+  pub fn write_byte(&mut self, byte: u8) {
+    self.bytes.push(byte);
+    self.position = self.position + 1;
+  }
+
+  pub fn write_bytes(&mut self, bytes: &[u8]) {
+    self.bytes.push_all(bytes);
+    self.position = self.position + bytes.len() as u64;
+  }
+
+  pub fn write_u32_be(&mut self, data: u32) {
+    self.write_bytes(&[
+        (data >> 24) as u8, (data >> 16) as u8,
+        (data >> 8 ) as u8, (data      ) as u8,
+      ]);
+  }
+
+  pub fn write_u16_be(&mut self, data: u16) {
+    self.write_bytes(&[(data >> 8) as u8, data as u8]);
+  }
+
+  pub fn write_u32_le(&mut self, data: u32) {
+    self.write_bytes(&[
+        (data      ) as u8, (data >> 8 ) as u8,
+        (data >> 16) as u8, (data >> 24) as u8,
+      ]);
+  }
+
+  pub fn write_u16_le(&mut self, data: u16) {
+    self.write_bytes(&[data as u8, (data >> 8) as u8]);
+  }
+
+  /// Number of bytes written so far.
+  pub fn position(&self) -> u64 {
+    self.position
+  }
+
+  /// Sends the bytes written since the last `send` to `recv`.
+  pub fn send<R: recv::Recv<u8>>(&mut self, recv: R) -> R {
+    let recv = recv.receive(self.bytes);
+    self.bytes.truncate(0);
+    recv
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use ByteWriter;
+  use ByteReader;
+  use ByteBuf;
+
+  #[test]
+  fn test_write() {
+    let mut out = ByteWriter::new();
+    out.write_byte(7);
+    out.write_u16_be(0x1234);
+    out.write_u16_le(0x1234);
+    let buf = out.send(~[]);
+    out.write_u32_be(0xdeadbeef);
+    out.write_u32_le(0xdeadbeef);
+    let buf = out.send(buf);
+
+    assert_eq!(buf, ~[7, 0x12, 0x34, 0x34, 0x12,
+      0xde, 0xad, 0xbe, 0xef, 0xef, 0xbe, 0xad, 0xde]);
+    assert_eq!(out.position(), 13);
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let mut out = ByteWriter::new();
+    out.write_u32_be(0x01020304);
+    out.write_u16_be(0xabcd);
+    out.write_u32_le(0x05060708);
+    out.write_u16_le(0xef01);
+    out.write_bytes(&[1, 2]);
+    let bytes = out.send(~[]);
+
+    let mut reader = ByteReader::new(ByteBuf::new(), bytes);
+    assert_eq!(reader.read_u32_be(), Ok(0x01020304));
+    assert_eq!(reader.read_u16_be(), Ok(0xabcd));
+    assert_eq!(reader.read_u32_le(), Ok(0x05060708));
+    assert_eq!(reader.read_u16_le(), Ok(0xef01));
+    assert_eq!(reader.close_to_rest(), Ok(&[1, 2]));
+  }
+}
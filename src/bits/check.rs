@@ -0,0 +1,22 @@
+#[macro_escape];
+
+// Unlike `sanity!`, these checks are never compiled out: they guard the
+// readers and buffers against misuse, which a decoder may be tricked into by
+// its input.
+
+macro_rules! check(
+  ($cond:expr, $err:expr) => (
+    if !$cond {
+      return Err($err);
+    }
+  )
+)
+
+macro_rules! try(
+  ($res:expr) => (
+    match $res {
+      Ok(x) => x,
+      Err(err) => return Err(err),
+    }
+  )
+)
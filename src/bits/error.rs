@@ -0,0 +1,29 @@
+#[deriving(Clone,Eq)]
+pub enum Error {
+  TooManyBits(uint, uint),
+  NotEnoughBits(uint, uint),
+  NotEnoughBytes(uint, uint),
+  BitBufOverflow(uint),
+  BufferedBits(uint),
+  BufferedBytes(uint),
+}
+
+impl ToStr for Error {
+  fn to_str(&self) -> ~str {
+    match *self {
+      TooManyBits(bits, max_bits) =>
+        fmt!("Requested %u bits at once, at most %u are supported",
+          bits, max_bits),
+      NotEnoughBits(bits, available) =>
+        fmt!("Requested %u bits, only %u are available", bits, available),
+      NotEnoughBytes(bytes, available) =>
+        fmt!("Requested %u bytes, only %u are available", bytes, available),
+      BitBufOverflow(bits) =>
+        fmt!("Bit buffer would hold %u bits, at most 64 fit", bits),
+      BufferedBits(bits) =>
+        fmt!("%u bits are still in the bit buffer", bits),
+      BufferedBytes(bytes) =>
+        fmt!("%u bytes are still in the byte buffer", bytes),
+    }
+  }
+}
@@ -0,0 +1,567 @@
+use bits::recv;
+use std::rt::io::{Reader, Writer, io_error, IoError};
+use std::rt::io::{OtherIoError, PreviousIoError};
+use std::{util, vec};
+
+/// A decoder of compressed data that may end before the input does.
+pub trait StreamDecoder<E> {
+  /// Called when there is no more input. Checks that the input did not end
+  /// in the middle of the data.
+  pub fn end_input(self) -> Result<(), E>;
+}
+
+/// A decoder that decodes into a caller-provided buffer, so that its output
+/// can be pulled by `DecompressReader`.
+pub trait BufDecoder<E>: StreamDecoder<E> {
+  /// Decodes `chunk` into `buf` and stops when it is full. Returns the number
+  /// of input bytes consumed and output bytes written, the unconsumed input
+  /// must be passed to the next call (see `inflater::Inflater::input_into`).
+  pub fn decode_into(self, chunk: &[u8], buf: &mut [u8])
+    -> (Either<Self, Result<(), E>>, uint, uint);
+}
+
+/// A decoder that sends its output to a `recv::TryRecv`, so that it can be
+/// driven by `DecompressWriter`.
+pub trait RecvDecoder<E>: StreamDecoder<E> {
+  /// Decodes the whole `chunk`, or the part before the end of the data,
+  /// which is returned with the result. Stops with the unconsumed input when
+  /// the receiver fails (see `inflater::Inflater::try_input`).
+  pub fn decode<'a, R: recv::TryRecv<u8>>(self, chunk: &'a [u8], recv: R)
+    -> (Either<(Self, &'a [u8]), (Result<(), E>, &'a [u8])>, Result<R, R>);
+}
+
+/// Reads the decoded data of the compressed data read from `source`.
+///
+/// A decoding error is raised on `io_error` (see `raise_decode_error`) and
+/// kept, see `error`. Every read after an error or the end of the data
+/// returns `None`.
+pub struct DecompressReader<S, D, E> {
+  priv source: S,
+  priv state: State<D, E>,
+  priv input: ~[u8],
+  priv input_start: uint,
+  priv input_end: uint,
+  priv source_eof: bool,
+}
+
+enum State<D, E> {
+  DecodeState(D),
+  EndState(),
+  ErrorState(E),
+}
+
+static input_size: uint = 16_384;
+
+impl<S: Reader, E: Clone + ToStr, D: BufDecoder<E>> DecompressReader<S, D, E> {
+  pub fn new(source: S, decoder: D) -> DecompressReader<S, D, E> {
+    DecompressReader {
+      source: source,
+      state: DecodeState(decoder),
+      input: vec::from_elem(input_size, 0u8),
+      input_start: 0,
+      input_end: 0,
+      source_eof: false,
+    }
+  }
+
+  /// The error that stopped the decoding, if any.
+  pub fn error(&self) -> Option<E> {
+    match self.state {
+      ErrorState(ref err) => Some(err.clone()),
+      _ => None,
+    }
+  }
+
+  /// Returns the source and the input that was read from it but not used by
+  /// the decoder (after the end of the data, this is the trailing input).
+  pub fn close(self) -> (S, ~[u8]) {
+    let DecompressReader { source, input, input_start, input_end, _ } = self;
+    (source, input.slice(input_start, input_end).to_owned())
+  }
+
+  fn stop_with_error(&mut self, err: E) {
+    raise_decode_error(&err);
+    self.state = ErrorState(err);
+  }
+}
+
+impl<S: Reader, E: Clone + ToStr, D: BufDecoder<E>> Reader
+  for DecompressReader<S, D, E>
+{
+  fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+    loop {
+      let decoder = match util::replace(&mut self.state, EndState) {
+          DecodeState(decoder) => decoder,
+          EndState => return None,
+          ErrorState(err) => { self.stop_with_error(err); return None },
+        };
+
+      if self.input_start == self.input_end && !self.source_eof {
+        match self.source.read(self.input.mut_slice(0, input_size)) {
+          Some(len) => { self.input_start = 0; self.input_end = len; },
+          None => { self.source_eof = true; },
+        }
+      }
+
+      let (res, consumed, written) = decoder.decode_into(
+        self.input.slice(self.input_start, self.input_end), buf);
+      self.input_start = self.input_start + consumed;
+
+      match res {
+        Left(decoder) =>
+          if written > 0 || buf.is_empty() {
+            self.state = DecodeState(decoder);
+            return Some(written);
+          } else if self.source_eof && self.input_start == self.input_end {
+            match decoder.end_input() {
+              Ok(()) => return None,
+              Err(err) => { self.stop_with_error(err); return None },
+            }
+          } else {
+            self.state = DecodeState(decoder);
+          },
+        Right(Ok(())) =>
+          return if written > 0 { Some(written) } else { None },
+        // the data before the error is returned first
+        Right(Err(err)) =>
+          if written > 0 {
+            self.state = ErrorState(err);
+            return Some(written);
+          } else {
+            self.stop_with_error(err);
+            return None;
+          },
+      }
+    }
+  }
+
+  fn eof(&mut self) -> bool {
+    match self.state {
+      DecodeState(_) => false,
+      _ => true,
+    }
+  }
+}
+
+/// Receiver that writes the data to a `Writer`. The first error raised by the
+/// writer is kept (see `take_error`) and no data are written after it.
+pub struct WriterRecv<W> {
+  priv writer: W,
+  priv error: Option<IoError>,
+  priv failed: bool,
+}
+
+impl<W: Writer> WriterRecv<W> {
+  pub fn new(writer: W) -> WriterRecv<W> {
+    WriterRecv { writer: writer, error: None, failed: false }
+  }
+
+  pub fn has_failed(&self) -> bool {
+    self.failed
+  }
+
+  /// Returns the error raised by the writer, unless it was already taken.
+  pub fn take_error(&mut self) -> Option<IoError> {
+    util::replace(&mut self.error, None)
+  }
+
+  pub fn unwrap(self) -> W {
+    self.writer
+  }
+
+  priv fn write_data(&mut self, xs: &[u8]) {
+    if !self.failed {
+      let mut error = None;
+      do io_error::cond.trap(|err| {
+        if error.is_none() { error = Some(err); }
+      }).in {
+        self.writer.write(xs);
+      }
+      self.failed = error.is_some();
+      self.error = error;
+    }
+  }
+}
+
+impl<W: Writer> recv::Recv<u8> for WriterRecv<W> {
+  pub fn receive(self, xs: &[u8]) -> WriterRecv<W> {
+    let mut recv = self;
+    recv.write_data(xs);
+    recv
+  }
+}
+
+// the decoders stop when the writer fails (a failed writer cannot resume)
+impl<W: Writer> recv::TryRecv<u8> for WriterRecv<W> {
+  pub fn try_receive(self, xs: &[u8]) -> Result<WriterRecv<W>, WriterRecv<W>> {
+    let mut recv = self;
+    recv.write_data(xs);
+    if recv.failed { Err(recv) } else { Ok(recv) }
+  }
+}
+
+/// Why `DecompressWriter::finish` failed.
+#[deriving(Clone, Eq)]
+pub enum FinishError<E> {
+  /// The data were corrupted or ended early.
+  DecodeError(E),
+  /// The inner writer failed, its error was raised by `write`.
+  WriterFailed,
+}
+
+/// Decodes the compressed data written to it and writes the decoded data to
+/// the inner writer.
+///
+/// A decoding error is raised on `io_error` (see `raise_decode_error`) and
+/// kept, see `error`. An error of the inner writer is raised as is and stops
+/// the decoding, later writes then raise `PreviousIoError`. The input after
+/// the end of the data is kept, see `finish`.
+pub struct DecompressWriter<W, D, E> {
+  priv state: State<D, E>,
+  priv recv: Option<WriterRecv<W>>,
+  priv rest: ~[u8],
+}
+
+impl<W: Writer, E: Clone + ToStr, D: RecvDecoder<E>> DecompressWriter<W, D, E> {
+  pub fn new(writer: W, decoder: D) -> DecompressWriter<W, D, E> {
+    DecompressWriter {
+      state: DecodeState(decoder),
+      recv: Some(WriterRecv::new(writer)),
+      rest: ~[],
+    }
+  }
+
+  /// The error that stopped the decoding, if any.
+  pub fn error(&self) -> Option<E> {
+    match self.state {
+      ErrorState(ref err) => Some(err.clone()),
+      _ => None,
+    }
+  }
+
+  /// Checks that the inner writer did not fail and that the written data did
+  /// not end early. Returns the result with the inner writer and the input
+  /// written after the end of the data.
+  pub fn finish(self) -> (Result<(), FinishError<E>>, W, ~[u8]) {
+    let DecompressWriter { state, recv, rest } = self;
+    let recv = recv.unwrap();
+    let res = if recv.has_failed() {
+        Err(WriterFailed)
+      } else {
+        match state {
+          DecodeState(decoder) => match decoder.end_input() {
+            Ok(()) => Ok(()),
+            Err(err) => Err(DecodeError(err)),
+          },
+          EndState => Ok(()),
+          ErrorState(err) => Err(DecodeError(err)),
+        }
+      };
+    (res, recv.unwrap(), rest)
+  }
+}
+
+impl<W: Writer, E: Clone + ToStr, D: RecvDecoder<E>> Writer
+  for DecompressWriter<W, D, E>
+{
+  fn write(&mut self, buf: &[u8]) {
+    if self.recv.get_ref().has_failed() {
+      io_error::cond.raise(IoError {
+        kind: PreviousIoError,
+        desc: "Inner writer failed",
+        detail: None,
+      });
+      return;
+    }
+
+    let decoder = match util::replace(&mut self.state, EndState) {
+        DecodeState(decoder) => decoder,
+        EndState => { self.rest.push_all(buf); return },
+        ErrorState(err) => {
+          raise_decode_error(&err);
+          self.state = ErrorState(err);
+          return
+        },
+      };
+
+    let recv = util::replace(&mut self.recv, None).unwrap();
+    let (res, recv) = decoder.decode(buf, recv);
+    let mut recv = match recv { Ok(recv) | Err(recv) => recv };
+    let write_error = recv.take_error();
+    self.recv = Some(recv);
+
+    match res {
+      // the input is left over only when the writer failed, which is final
+      Left((decoder, _)) =>
+        self.state = DecodeState(decoder),
+      Right((Ok(()), rest)) =>
+        self.rest.push_all(rest),
+      Right((Err(err), _)) =>
+        self.state = ErrorState(err),
+    }
+
+    match write_error {
+      Some(err) => io_error::cond.raise(err),
+      None => match self.state {
+        ErrorState(ref err) => raise_decode_error(err),
+        _ => { },
+      },
+    }
+  }
+
+  fn flush(&mut self) {
+    match self.recv {
+      Some(ref mut recv) if !recv.failed => recv.writer.flush(),
+      _ => { },
+    }
+  }
+}
+
+/// Raises a decoding error as an `OtherIoError`, with the message of the
+/// error as the detail.
+fn raise_decode_error<E: ToStr>(err: &E) {
+  io_error::cond.raise(IoError {
+    kind: OtherIoError,
+    desc: "Decompression error",
+    detail: Some(err.to_str()),
+  });
+}
+
+#[cfg(test)]
+mod test {
+  use bits::io::*;
+  use bits::recv;
+  use std::rt::io::{Reader, Writer, io_error, IoError, OtherIoError};
+  use std::rt::io::mem::{MemReader};
+  use std::vec;
+
+  // reads until `reader` returns `None`, `buf_size` bytes at a time
+  fn read_to_end<R: Reader>(reader: &mut R, buf_size: uint) -> ~[u8] {
+    let mut out = ~[];
+    let mut buf = vec::from_elem(buf_size, 0u8);
+    loop {
+      match reader.read(buf.mut_slice(0, buf_size)) {
+        Some(len) => out.push_all(buf.slice(0, len)),
+        None => return out,
+      }
+    }
+  }
+
+  // decodes `bytes` with a `DecompressReader`, the raised errors are ignored
+  fn decompress_bytes<E: Clone + ToStr, D: BufDecoder<E>>
+    (decoder: D, bytes: ~[u8], buf_size: uint) -> (~[u8], Option<E>, ~[u8])
+  {
+    let mut reader = DecompressReader::new(MemReader::new(bytes), decoder);
+    let out = do io_error::cond.trap(|_| { }).in {
+        read_to_end(&mut reader, buf_size)
+      };
+    let err = reader.error();
+    let (_, rest) = reader.close();
+    (out, err, rest)
+  }
+
+  // decodes pairs of a count and a byte into repeated bytes, the data end
+  // with a zero count
+  struct RunDecoder {
+    count: Option<u8>,
+  }
+
+  impl StreamDecoder<~str> for RunDecoder {
+    pub fn end_input(self) -> Result<(), ~str> {
+      Err(~"unexpected end")
+    }
+  }
+
+  impl BufDecoder<~str> for RunDecoder {
+    pub fn decode_into(self, chunk: &[u8], buf: &mut [u8])
+      -> (Either<RunDecoder, Result<(), ~str>>, uint, uint)
+    {
+      let mut count = self.count;
+      let mut consumed = 0;
+      let mut written = 0;
+      loop {
+        match count {
+          None if consumed < chunk.len() => {
+            if chunk[consumed] == 0 {
+              return (Right(Ok(())), consumed + 1, written);
+            }
+            count = Some(chunk[consumed]);
+            consumed = consumed + 1;
+          },
+          Some(n) if consumed < chunk.len() => {
+            if n as uint > buf.len() - written {
+              break;
+            }
+            if chunk[consumed] == 0xff {
+              return (Right(Err(~"bad byte")), consumed, written);
+            }
+            for (n as uint).times {
+              buf[written] = chunk[consumed];
+              written = written + 1;
+            }
+            count = None;
+            consumed = consumed + 1;
+          },
+          _ => break,
+        }
+      }
+      (Left(RunDecoder { count: count }), consumed, written)
+    }
+  }
+
+  // unlike the real decoders, drops the data that a failed receiver did not
+  // get, which is enough for a writer that cannot resume
+  impl RecvDecoder<~str> for RunDecoder {
+    pub fn decode<'a, R: recv::TryRecv<u8>>(self, chunk: &'a [u8], recv: R)
+      -> (Either<(RunDecoder, &'a [u8]), (Result<(), ~str>, &'a [u8])>,
+        Result<R, R>)
+    {
+      let mut decoder = self;
+      let mut recv = recv;
+      let mut pos = 0;
+      let mut buf = [0u8, ..255];
+      loop {
+        let (res, consumed, written) = decoder.decode_into(
+          chunk.slice(pos, chunk.len()), buf.mut_slice(0, 255));
+        pos = pos + consumed;
+        let rest = chunk.slice(pos, chunk.len());
+        let (new_recv, failed) = match recv.try_receive(buf.slice(0, written)) {
+            Ok(recv) => (recv, false),
+            Err(recv) => (recv, true),
+          };
+        let new_recv = if failed { Err(new_recv) } else { Ok(new_recv) };
+        match res {
+          Left(new_decoder) => {
+            if failed || rest.is_empty() {
+              return (Left((new_decoder, rest)), new_recv);
+            }
+            decoder = new_decoder;
+            recv = new_recv.unwrap();
+          },
+          Right(res) =>
+            return (Right((res, rest)), new_recv),
+        }
+      }
+    }
+  }
+
+  // collects the data and fails when there would be more than `limit` bytes
+  struct LimitWriter {
+    data: ~[u8],
+    limit: uint,
+  }
+
+  impl Writer for LimitWriter {
+    fn write(&mut self, buf: &[u8]) {
+      if self.data.len() + buf.len() > self.limit {
+        io_error::cond.raise(IoError {
+          kind: OtherIoError,
+          desc: "Limit exceeded",
+          detail: None,
+        });
+      } else {
+        self.data.push_all(buf);
+      }
+    }
+
+    fn flush(&mut self) { }
+  }
+
+  fn run_reader(bytes: ~[u8])
+    -> DecompressReader<MemReader, RunDecoder, ~str>
+  {
+    DecompressReader::new(MemReader::new(bytes), RunDecoder { count: None })
+  }
+
+  #[test]
+  fn test_read() {
+    for [3u, 4, 1000].iter().advance |&step| {
+      let mut reader = run_reader(~[2, 10, 3, 20, 1, 30, 0, 40, 50]);
+      assert_eq!(read_to_end(&mut reader, step), ~[10, 10, 20, 20, 20, 30]);
+      assert!(reader.eof());
+      assert_eq!(reader.error(), None);
+      let (_, rest) = reader.close();
+      assert_eq!(rest, ~[40, 50]);
+    }
+  }
+
+  #[test]
+  fn test_read_error() {
+    let mut reader = run_reader(~[2, 10, 3, 0xff, 0]);
+    let mut details = ~[];
+    let out = do io_error::cond.trap(|err| { details.push(err.detail); }).in {
+        read_to_end(&mut reader, 100)
+      };
+    assert_eq!(out, ~[10, 10]);
+    assert_eq!(details.clone(), ~[Some(~"bad byte")]);
+    assert_eq!(reader.error(), Some(~"bad byte"));
+    let (_, rest) = reader.close();
+    assert_eq!(rest, ~[0xff, 0]);
+  }
+
+  #[test]
+  fn test_read_unexpected_end() {
+    assert_eq!(decompress_bytes(RunDecoder { count: None }, ~[2, 10, 3], 100),
+      (~[10, 10], Some(~"unexpected end"), ~[]));
+  }
+
+  fn run_writer(limit: uint)
+    -> DecompressWriter<LimitWriter, RunDecoder, ~str>
+  {
+    let writer = LimitWriter { data: ~[], limit: limit };
+    DecompressWriter::new(writer, RunDecoder { count: None })
+  }
+
+  #[test]
+  fn test_write() {
+    let bytes = [2u8, 10, 3, 20, 1, 30, 0, 40, 50];
+    for [1u, 2, 4, 100].iter().advance |&step| {
+      let mut writer = run_writer(100);
+      for bytes.chunk_iter(step).advance |chunk| {
+        writer.write(chunk);
+      }
+      assert_eq!(writer.error(), None);
+      let (res, inner, rest) = writer.finish();
+      assert_eq!(res, Ok(()));
+      assert_eq!(inner.data, ~[10, 10, 20, 20, 20, 30]);
+      assert_eq!(rest, ~[40, 50]);
+    }
+  }
+
+  #[test]
+  fn test_write_errors() {
+    let mut descs = ~[];
+    do io_error::cond.trap(|err| { descs.push(err.desc); }).in {
+      let mut writer = run_writer(100);
+      writer.write([2, 10, 3, 0xff]);
+      writer.write([0]);
+      assert_eq!(writer.error(), Some(~"bad byte"));
+      let (res, inner, _) = writer.finish();
+      assert_eq!(res, Err(DecodeError(~"bad byte")));
+      assert_eq!(inner.data, ~[10, 10]);
+    }
+    assert_eq!(descs.clone(), ~["Decompression error", "Decompression error"]);
+
+    let mut descs = ~[];
+    do io_error::cond.trap(|err| { descs.push(err.desc); }).in {
+      let mut writer = run_writer(3);
+      writer.write([2, 10, 2, 20]);
+      writer.write([0]);
+      assert_eq!(writer.error(), None);
+      let (res, inner, _) = writer.finish();
+      assert_eq!(res, Err(WriterFailed));
+      assert_eq!(inner.data, ~[]);
+    }
+    assert_eq!(descs.clone(), ~["Limit exceeded", "Inner writer failed"]);
+
+    let mut writer = run_writer(100);
+    writer.write([2, 10, 3]);
+    match writer.finish() {
+      (Err(err), inner, _) => {
+        assert_eq!(err, DecodeError(~"unexpected end"));
+        assert_eq!(inner.data, ~[10, 10]);
+      },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+}
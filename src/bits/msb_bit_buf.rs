@@ -0,0 +1,80 @@
+use error;
+mod check;
+
+/// Bits that were read from the input but not consumed yet, for
+/// `MsbBitReader`. The bits are in the low `bits` bits of `buf`, the first
+/// bit in the most significant of them.
+pub struct MsbBitBuf {
+  buf: u64,
+  bits: uint,
+}
+
+impl MsbBitBuf {
+  pub fn new() -> MsbBitBuf {
+    MsbBitBuf { buf: 0, bits: 0 }
+  }
+
+  #[inline]
+  pub fn shift_bits(&mut self, bits: uint) -> Result<u64, error::Error> {
+    check!(bits <= self.bits, error::NotEnoughBits(bits, self.bits));
+    self.bits = self.bits - bits;
+    let ret = if bits > 0 { (self.buf >> self.bits) & low_mask(bits) } else { 0 };
+    self.buf = self.buf & low_mask(self.bits);
+    Ok(ret)
+  }
+
+  #[inline]
+  pub fn unshift_bits(&mut self, bits: uint, data: u64)
+    -> Result<(), error::Error>
+  {
+    check!(bits + self.bits <= 64, error::BitBufOverflow(bits + self.bits));
+    if bits > 0 {
+      self.buf = self.buf | (data << self.bits);
+      self.bits = self.bits + bits;
+    }
+    Ok(())
+  }
+
+  #[inline]
+  pub fn push_byte(&mut self, byte: u8) -> Result<(), error::Error> {
+    check!(self.bits + 8 <= 64, error::BitBufOverflow(self.bits + 8));
+    self.buf = (self.buf << 8) | byte as u64;
+    self.bits = self.bits + 8;
+    Ok(())
+  }
+
+  /// Pushes the bytes of the big-endian `word` until the buffer has more
+  /// than 56 bits and returns the number of bytes pushed.
+  #[inline]
+  pub fn push_word(&mut self, word: u64) -> Result<uint, error::Error> {
+    check!(self.bits <= 56, error::BitBufOverflow(self.bits + 8));
+    let bytes = (64 - self.bits) / 8;
+    self.buf = if bytes < 8 {
+        (self.buf << (8 * bytes)) | (word >> (64 - 8 * bytes))
+      } else {
+        word
+      };
+    self.bits = self.bits + 8 * bytes;
+    Ok(bytes)
+  }
+
+  /// Drops the last `bits` bits.
+  #[inline]
+  pub fn pop_bits(&mut self, bits: uint) -> Result<(), error::Error> {
+    check!(bits <= self.bits, error::NotEnoughBits(bits, self.bits));
+    self.buf = if bits < 64 { self.buf >> bits } else { 0 };
+    self.bits = self.bits - bits;
+    Ok(())
+  }
+
+  #[inline]
+  pub fn clear(&mut self) {
+    self.buf = 0;
+    self.bits = 0;
+  }
+}
+
+#[inline]
+fn low_mask(bits: uint) -> u64 {
+  if bits < 64 { !(!0u64 << bits) } else { !0u64 }
+}
@@ -0,0 +1,313 @@
+use std::cmp;
+use MsbBitBuf;
+use error;
+mod check;
+
+/// Reads bits from the most significant one, as in bzip2 or LZW. Apart from
+/// the bit order it works as `BitReader`: the bits are loaded from the chunk
+/// into a 64-bit `MsbBitBuf` several bytes at once and the bytes that were
+/// loaded but not consumed are given back to the chunk when the reader is
+/// closed.
+pub struct MsbBitReader<'self> {
+  priv chunk: &'self [u8],
+  priv pos: uint,
+  priv bit_buf: MsbBitBuf,
+}
+
+impl<'self> MsbBitReader<'self> {
+  pub fn new<'a>(bit_buf: MsbBitBuf, chunk: &'a [u8]) -> MsbBitReader<'a> {
+    MsbBitReader { chunk: chunk, pos: 0, bit_buf: bit_buf }
+  }
+
+  pub fn close_to_buf(self) -> Result<MsbBitBuf, error::Error> {
+    let MsbBitReader { chunk, pos, bit_buf } = self;
+    let mut bit_buf = bit_buf;
+
+    let total = (chunk.len() - pos) * 8 + bit_buf.bits;
+    check!(total <= 64, error::BitBufOverflow(total));
+    for chunk.slice(pos, chunk.len()).iter().advance |&byte| {
+      try!(bit_buf.push_byte(byte));
+    }
+    Ok(bit_buf)
+  }
+
+  pub fn close_to_rest(self) -> Result<&'self [u8], error::Error> {
+    let mut reader = self;
+    reader.give_back();
+    check!(reader.bit_buf.bits < 8, error::BufferedBits(reader.bit_buf.bits));
+    Ok(reader.chunk.slice(reader.pos, reader.chunk.len()))
+  }
+
+  pub fn close(self) -> (MsbBitBuf, &'self [u8]) {
+    let mut reader = self;
+    reader.give_back();
+    let MsbBitReader { chunk, pos, bit_buf } = reader;
+    (bit_buf, chunk.slice(pos, chunk.len()))
+  }
+
+  pub fn has_bits(&self, bits: uint) -> bool {
+    bits <= self.bit_buf.bits || bits <= self.available_bits()
+  }
+
+  pub fn available_bits(&self) -> uint {
+    (self.chunk.len() - self.pos) * 8 + self.bit_buf.bits
+  }
+
+  pub fn has_bytes(&self, bytes: uint) -> bool {
+    bytes <= self.bit_buf.bits / 8 + (self.chunk.len() - self.pos)
+  }
+
+  pub fn skip_to_byte(&mut self) {
+    let partial = self.bit_buf.bits % 8;
+    self.bit_buf.shift_bits(partial);
+  }
+
+  // Same as `BitReader::refill`, but the word is big-endian.
+  #[inline]
+  priv fn refill(&mut self) -> Result<(), error::Error> {
+    let chunk = self.chunk;
+    if self.bit_buf.bits <= 56 && self.pos + 8 <= chunk.len() {
+      let p = self.pos;
+      let word = (chunk[p] as u64 << 56) | (chunk[p + 1] as u64 << 48)
+        | (chunk[p + 2] as u64 << 40) | (chunk[p + 3] as u64 << 32)
+        | (chunk[p + 4] as u64 << 24) | (chunk[p + 5] as u64 << 16)
+        | (chunk[p + 6] as u64 << 8) | (chunk[p + 7] as u64);
+      let bytes = try!(self.bit_buf.push_word(word));
+      self.pos = p + bytes;
+    } else {
+      while self.bit_buf.bits <= 56 && self.pos < chunk.len() {
+        try!(self.bit_buf.push_byte(chunk[self.pos]));
+        self.pos = self.pos + 1;
+      }
+    }
+    Ok(())
+  }
+
+  // Returns the whole bytes that were loaded from the chunk but not consumed.
+  priv fn give_back(&mut self) {
+    let bytes = cmp::min(self.bit_buf.bits / 8, self.pos);
+    self.bit_buf.pop_bits(8 * bytes);
+    self.pos = self.pos - bytes;
+  }
+
+  #[inline]
+  priv fn read_bits(&mut self, bits: uint) -> Result<u64, error::Error> {
+    check!(bits <= 57, error::TooManyBits(bits, 57));
+    if self.bit_buf.bits < bits {
+      try!(self.refill());
+    }
+    self.bit_buf.shift_bits(bits)
+  }
+
+  /// Returns the next `bits` bits without consuming them.
+  pub fn peek_bits16(&self, bits: uint) -> Result<u16, error::Error> {
+    check!(bits <= 16, error::TooManyBits(bits, 16));
+    Ok(try!(self.peek_bits32(bits)) as u16)
+  }
+
+  pub fn peek_bits32(&self, bits: uint) -> Result<u32, error::Error> {
+    check!(bits <= 32, error::TooManyBits(bits, 32));
+    check!(bits <= self.available_bits(),
+      error::NotEnoughBits(bits, self.available_bits()));
+    let mut data = self.bit_buf.buf;
+    let mut have = self.bit_buf.bits;
+    let mut i = self.pos;
+    while have < bits {
+      data = (data << 8) | self.chunk[i] as u64;
+      have = have + 8;
+      i = i + 1;
+    }
+
+    Ok(((data >> (have - bits)) & !(!0u64 << bits)) as u32)
+  }
+
+  pub fn read_bits8(&mut self, bits: uint) -> Result<u8, error::Error> {
+    check!(bits <= 8, error::TooManyBits(bits, 8));
+    Ok(try!(self.read_bits(bits)) as u8)
+  }
+
+  pub fn read_bits16(&mut self, bits: uint) -> Result<u16, error::Error> {
+    check!(bits <= 16, error::TooManyBits(bits, 16));
+    Ok(try!(self.read_bits(bits)) as u16)
+  }
+
+  pub fn read_bits32(&mut self, bits: uint) -> Result<u32, error::Error> {
+    check!(bits <= 32, error::TooManyBits(bits, 32));
+    Ok(try!(self.read_bits(bits)) as u32)
+  }
+
+  /// Reads up to 57 bits at once.
+  pub fn read_bits64(&mut self, bits: uint) -> Result<u64, error::Error> {
+    self.read_bits(bits)
+  }
+
+  pub fn unread_bits8(&mut self, bits: uint, data: u8)
+    -> Result<(), error::Error>
+  {
+    check!(bits <= 8, error::TooManyBits(bits, 8));
+    self.unread_bits(bits, data as u64)
+  }
+
+  pub fn unread_bits16(&mut self, bits: uint, data: u16)
+    -> Result<(), error::Error>
+  {
+    check!(bits <= 16, error::TooManyBits(bits, 16));
+    self.unread_bits(bits, data as u64)
+  }
+
+  pub fn unread_bits32(&mut self, bits: uint, data: u32)
+    -> Result<(), error::Error>
+  {
+    check!(bits <= 32, error::TooManyBits(bits, 32));
+    self.unread_bits(bits, data as u64)
+  }
+
+  priv fn unread_bits(&mut self, bits: uint, data: u64)
+    -> Result<(), error::Error>
+  {
+    if self.bit_buf.bits + bits > 64 {
+      self.give_back();
+    }
+    self.bit_buf.unshift_bits(bits, data)
+  }
+
+  /// Reads a big-endian 16-bit number.
+  pub fn read_u16(&mut self) -> Result<u16, error::Error> {
+    self.read_bits16(16)
+  }
+
+  /// Reads a big-endian 32-bit number.
+  pub fn read_u32(&mut self) -> Result<u32, error::Error> {
+    self.read_bits32(32)
+  }
+
+  pub fn read_byte_chunk(&mut self, limit: uint)
+    -> Result<&'self [u8], error::Error>
+  {
+    self.give_back();
+    check!(self.bit_buf.bits == 0, error::BufferedBits(self.bit_buf.bits));
+    let len = cmp::min(limit, self.chunk.len() - self.pos);
+    let chunk = self.chunk.slice(self.pos, self.pos + len);
+
+    self.pos = self.pos + len;
+    Ok(chunk)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use MsbBitReader;
+  use MsbBitBuf;
+
+  #[test]
+  fn test_with_buf_carry() {
+    let mut bit_buf = MsbBitBuf::new();
+
+    {
+      let mut reader = MsbBitReader::new(bit_buf, &[0b11110010, 0b10_100110]);
+      assert_eq!(reader.read_bits16(10), Ok(0b11110010_10));
+      assert!(reader.has_bits(6) && !reader.has_bits(7));
+      bit_buf = reader.close_to_buf().unwrap();
+    }
+
+    {
+      let mut reader = MsbBitReader::new(bit_buf, &[0b010010_10]);
+      assert_eq!(reader.read_bits16(12), Ok(0b100110_010010));
+      assert!(reader.has_bits(2) && !reader.has_bits(3));
+    }
+  }
+
+  #[test]
+  fn test_read_and_has_bits() {
+    let mut reader = MsbBitReader::new(MsbBitBuf::new(),
+      &[0b100_10001, 0b101_01011, 0b11001100]);
+    assert_eq!(reader.read_bits8(3), Ok(0b100));
+    assert!(reader.has_bits(21) && !reader.has_bits(22));
+    assert_eq!(reader.read_bits8(8), Ok(0b10001_101));
+    assert_eq!(reader.read_bits16(13), Ok(0b01011_11001100));
+    assert!(!reader.has_bits(1));
+  }
+
+  #[test]
+  fn test_skip_to_byte() {
+    let mut reader = MsbBitReader::new(MsbBitBuf::new(),
+      &[0b01101_111, 0b011100_01]);
+    assert_eq!(reader.read_bits8(5), Ok(0b01101));
+    reader.skip_to_byte();
+    assert_eq!(reader.read_bits8(6), Ok(0b011100));
+    assert!(reader.has_bits(2) && !reader.has_bits(3));
+  }
+
+  #[test]
+  fn test_peek_bits() {
+    let mut reader = MsbBitReader::new(MsbBitBuf::new(),
+      &[0b110_01010, 0b011001_01, 0b11110000]);
+    reader.read_bits8(3).unwrap();
+    assert_eq!(reader.peek_bits16(11), Ok(0b01010_011001));
+    assert_eq!(reader.peek_bits16(0), Ok(0));
+    assert_eq!(reader.read_bits16(11), Ok(0b01010_011001));
+    assert_eq!(reader.peek_bits16(10), Ok(0b01_11110000));
+    assert_eq!(reader.available_bits(), 10);
+  }
+
+  #[test]
+  fn test_unread_bits() {
+    let mut reader = MsbBitReader::new(MsbBitBuf::new(),
+      &[0b0001_0111, 0b01101_110]);
+    assert_eq!(reader.read_bits8(4), Ok(0b0001));
+    reader.unread_bits8(4, 0b0001).unwrap();
+    assert_eq!(reader.read_bits8(6), Ok(0b0001_01));
+    reader.unread_bits8(2, 0b01).unwrap();
+    assert_eq!(reader.read_bits16(12), Ok(0b01_11_01101_110));
+    assert!(!reader.has_bits(1));
+  }
+
+  #[test]
+  fn test_read_wide_bits() {
+    let bytes = [0x01u8, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef,
+      0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32, 0x10];
+    let mut reader = MsbBitReader::new(MsbBitBuf::new(), bytes);
+    assert_eq!(reader.read_bits8(4), Ok(0x0));
+    assert!(reader.has_bits(57));
+    assert_eq!(reader.read_bits64(56), Ok(0x12_3456_789a_bcde));
+    assert_eq!(reader.read_bits8(4), Ok(0xf));
+    assert_eq!(reader.read_u32(), Ok(0xfedc_ba98));
+    assert_eq!(reader.peek_bits32(28), Ok(0x765_4321));
+    assert_eq!(reader.available_bits(), 32);
+  }
+
+  #[test]
+  fn test_read_u32_across_chunks() {
+    let mut bit_buf = MsbBitBuf::new();
+
+    {
+      let mut reader = MsbBitReader::new(bit_buf, &[0xaa, 0x12, 0x34]);
+      assert_eq!(reader.read_bits8(8), Ok(0xaa));
+      assert!(reader.has_bits(16) && !reader.has_bits(17));
+      bit_buf = reader.close_to_buf().unwrap();
+    };
+
+    {
+      let mut reader = MsbBitReader::new(bit_buf, &[0x56, 0x78, 0xbb]);
+      assert_eq!(reader.read_u32(), Ok(0x1234_5678));
+      assert_eq!(reader.close_to_rest(), Ok(&[0xbb]));
+    };
+  }
+
+  #[test]
+  fn test_close_gives_back_bytes() {
+    let bytes = [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+    let mut reader = MsbBitReader::new(MsbBitBuf::new(), bytes);
+    assert_eq!(reader.read_bits16(12), Ok(0x010));
+    reader.skip_to_byte();
+    let (bit_buf, rest) = reader.close();
+    assert_eq!(bit_buf.bits, 0);
+    assert_eq!(rest, &[3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+
+    let mut reader = MsbBitReader::new(MsbBitBuf::new(), bytes);
+    assert_eq!(reader.read_bits16(16), Ok(0x0102));
+    assert_eq!(reader.read_byte_chunk(3), Ok(&[3, 4, 5]));
+    assert_eq!(reader.read_bits8(8), Ok(6));
+    assert_eq!(reader.close_to_rest(), Ok(&[7, 8, 9, 10, 11, 12]));
+  }
+}
@@ -1,4 +1,5 @@
 use std::vec;
+use std::vec::bytes;
 
 pub trait Recv<X> {
   pub fn receive(self, xs: &[X]) -> Self;
@@ -36,23 +37,84 @@ impl<X> Recv<X> for () {
   }
 }
 
-/*
-impl<X, I: num::NumCast + ops::Add<I, I>> Recv<X> for I {
-  pub fn receive(self, xs: &[X]) -> I {
-    self + num::NumCast::from(xs.len())
-  }
-}
-*/
-
-// TODO: generalize at least to integers
+// a generic impl for all integers would overlap with the impls above, other
+// integers count through `recv_comb::Count`
 impl<X> Recv<X> for u32 {
   pub fn receive(self, xs: &[X]) -> u32 {
     self + xs.len() as u32
   }
 }
 
+/// Receiver that copies the data into a borrowed slice, for decoding into a
+/// caller-provided buffer. Fails if the data do not fit.
+pub struct SliceRecv<'self> {
+  priv buf: &'self mut [u8],
+  priv len: uint,
+}
+
+impl<'self> SliceRecv<'self> {
+  pub fn new<'a>(buf: &'a mut [u8]) -> SliceRecv<'a> {
+    SliceRecv { buf: buf, len: 0 }
+  }
+
+  /// Number of bytes written so far.
+  pub fn len(&self) -> uint {
+    self.len
+  }
+}
+
+impl<'self> Recv<u8> for SliceRecv<'self> {
+  pub fn receive(self, xs: &[u8]) -> SliceRecv<'self> {
+    let SliceRecv { buf, len } = self;
+    if len + xs.len() > buf.len() {
+      fail!(fmt!("SliceRecv::receive: %u bytes do not fit into %u free bytes",
+        xs.len(), buf.len() - len));
+    }
+    bytes::copy_memory(buf.mut_slice(len, len + xs.len()), xs, xs.len());
+    SliceRecv { buf: buf, len: len + xs.len() }
+  }
+}
+
+/// Receiver that may fail, for example when the disk is full. A receiver that
+/// failed is returned in `Err` and keeps its error, the decoder then stops.
+/// The data that were not received are sent again when the decoding resumes.
+pub trait TryRecv<X> {
+  pub fn try_receive(self, xs: &[X]) -> Result<Self, Self>;
+}
+
+/// Adapts a `Recv`, which never fails, to `TryRecv`.
+pub struct Infallible<R>(R);
+
+impl<R> Infallible<R> {
+  pub fn unwrap(self) -> R {
+    let Infallible(recv) = self;
+    recv
+  }
+}
+
+impl<X, R: Recv<X>> TryRecv<X> for Infallible<R> {
+  pub fn try_receive(self, xs: &[X]) -> Result<Infallible<R>, Infallible<R>> {
+    Ok(Infallible(self.unwrap().receive(xs)))
+  }
+}
+
+// the data reach the right receiver (usually a checksum) only if the left one
+// did not fail, so that it sees exactly the accepted data
+impl<X, L: TryRecv<X>, R: Recv<X>> TryRecv<X> for (L, R) {
+  pub fn try_receive(self, xs: &[X]) -> Result<(L, R), (L, R)> {
+    let (left, right) = self;
+    match left.try_receive(xs) {
+      Ok(left) => Ok((left, right.receive(xs))),
+      Err(left) => Err((left, right)),
+    }
+  }
+}
+
 #[cfg(test)]
 mod test {
+  use recv::{SliceRecv, TryRecv, Infallible};
+  use recv_comb::Limit;
+  use std::vec;
 
   #[test]
   fn test_pair_recv() {
@@ -105,6 +167,39 @@ mod test {
     assert_eq!(i, 29);
   }
 
+  #[test]
+  fn test_slice_recv() {
+    let mut buf = vec::from_elem(6, 0u8);
+    {
+      let recv = SliceRecv::new(buf);
+      let recv = recv.receive(&[1, 2, 3]);
+      let recv = recv.receive(&[]);
+      let recv = recv.receive(&[4, 5]);
+      assert_eq!(recv.len(), 5);
+    }
+    assert_eq!(buf, ~[1, 2, 3, 4, 5, 0]);
+  }
+
+  #[test]
+  #[should_fail]
+  fn test_slice_recv_overflow() {
+    let mut buf = vec::from_elem(2, 0u8);
+    let recv = SliceRecv::new(buf);
+    let _ = recv.receive(&[1, 2, 3]);
+  }
+
+  #[test]
+  fn test_try_recv() {
+    let recv = (Limit::new(Infallible(~[]), 4), 0u32);
+    let recv = recv.try_receive(&[1, 2, 3]).unwrap();
+    let (limited, count) = match recv.try_receive(&[4, 5]) {
+        Ok(_) => fail!(~"the receiver did not fail"),
+        Err(recv) => recv,
+      };
+    assert_eq!(limited.unwrap().unwrap(), ~[1, 2, 3]);
+    assert_eq!(count, 3);
+  }
+
   #[test]
   fn test_unit_recv() {
     let unit_recv = ();
@@ -0,0 +1,332 @@
+use bits::recv::{Recv, TryRecv};
+use std::{cmp, num, util, vec};
+
+/// Counts the received items in an integer of any type.
+pub struct Count<I>(I);
+
+impl<I> Count<I> {
+  pub fn unwrap(self) -> I {
+    let Count(count) = self;
+    count
+  }
+}
+
+impl<X, I: num::NumCast + Add<I, I>> Recv<X> for Count<I> {
+  pub fn receive(self, xs: &[X]) -> Count<I> {
+    let Count(count) = self;
+    Count(count + num::cast(xs.len()))
+  }
+}
+
+/// Receiver that maps the items with `f` and sends them to the inner
+/// receiver.
+pub struct Map<'self, X, Y, R> {
+  priv f: &'self fn(&X) -> Y,
+  priv recv: R,
+}
+
+impl<'self, X, Y, R: Recv<Y>> Map<'self, X, Y, R> {
+  pub fn new<'a>(f: &'a fn(&X) -> Y, recv: R) -> Map<'a, X, Y, R> {
+    Map { f: f, recv: recv }
+  }
+
+  pub fn unwrap(self) -> R {
+    self.recv
+  }
+}
+
+impl<'self, X, Y, R: Recv<Y>> Recv<X> for Map<'self, X, Y, R> {
+  pub fn receive(self, xs: &[X]) -> Map<'self, X, Y, R> {
+    let Map { f, recv } = self;
+    let ys = xs.map(|x| f(x));
+    Map { f: f, recv: recv.receive(ys) }
+  }
+}
+
+/// Receiver that passes at most `limit` items to the inner receiver. As a
+/// `Recv` it drops the items over the limit, as a `TryRecv` it fails instead
+/// and the items of the failed call are not passed at all, as `TryRecv`
+/// requires.
+pub struct Limit<R> {
+  priv recv: R,
+  priv left: uint,
+  priv exceeded: bool,
+}
+
+impl<R> Limit<R> {
+  pub fn new(recv: R, limit: uint) -> Limit<R> {
+    Limit { recv: recv, left: limit, exceeded: false }
+  }
+
+  /// Whether some items did not fit into the limit.
+  pub fn exceeded(&self) -> bool {
+    self.exceeded
+  }
+
+  pub fn unwrap(self) -> R {
+    self.recv
+  }
+}
+
+impl<X, R: Recv<X>> Recv<X> for Limit<R> {
+  pub fn receive(self, xs: &[X]) -> Limit<R> {
+    let Limit { recv, left, exceeded } = self;
+    let len = cmp::min(left, xs.len());
+    Limit {
+      recv: if len > 0 { recv.receive(xs.slice(0, len)) } else { recv },
+      left: left - len,
+      exceeded: exceeded || len < xs.len(),
+    }
+  }
+}
+
+impl<X, R: TryRecv<X>> TryRecv<X> for Limit<R> {
+  pub fn try_receive(self, xs: &[X]) -> Result<Limit<R>, Limit<R>> {
+    let Limit { recv, left, exceeded } = self;
+    if xs.len() > left {
+      return Err(Limit { recv: recv, left: left, exceeded: true });
+    }
+    match recv.try_receive(xs) {
+      Ok(recv) =>
+        Ok(Limit { recv: recv, left: left - xs.len(), exceeded: exceeded }),
+      Err(recv) =>
+        Err(Limit { recv: recv, left: left, exceeded: exceeded }),
+    }
+  }
+}
+
+/// Receiver that skips the first `start` items and passes the next `len`
+/// items to the inner receiver, to extract a range of the data.
+pub struct Range<R> {
+  priv recv: R,
+  priv skip: uint,
+  priv keep: uint,
+}
+
+impl<R> Range<R> {
+  pub fn new(recv: R, start: uint, len: uint) -> Range<R> {
+    Range { recv: recv, skip: start, keep: len }
+  }
+
+  /// Whether the whole range was received, so that the decoding can stop.
+  pub fn is_complete(&self) -> bool {
+    self.skip == 0 && self.keep == 0
+  }
+
+  pub fn unwrap(self) -> R {
+    self.recv
+  }
+}
+
+impl<X, R: Recv<X>> Recv<X> for Range<R> {
+  pub fn receive(self, xs: &[X]) -> Range<R> {
+    let Range { recv, skip, keep } = self;
+    let skipped = cmp::min(skip, xs.len());
+    let kept = cmp::min(keep, xs.len() - skipped);
+    Range {
+      recv: if kept > 0 {
+          recv.receive(xs.slice(skipped, skipped + kept))
+        } else {
+          recv
+        },
+      skip: skip - skipped,
+      keep: keep - kept,
+    }
+  }
+}
+
+/// Receiver that sends the items to the inner receiver in blocks of exactly
+/// `size` items. The last, shorter block is sent by `finish`.
+pub struct Blocks<X, R> {
+  priv recv: R,
+  priv buf: ~[X],
+  priv size: uint,
+}
+
+impl<X: Clone, R: Recv<X>> Blocks<X, R> {
+  /// Fails if `size` is zero.
+  pub fn new(recv: R, size: uint) -> Blocks<X, R> {
+    if size == 0 {
+      fail!("Blocks::new: the block size must not be zero");
+    }
+    Blocks { recv: recv, buf: vec::with_capacity(size), size: size }
+  }
+
+  /// Sends the items of the unfinished block and returns the inner receiver.
+  pub fn finish(self) -> R {
+    let Blocks { recv, buf, _ } = self;
+    if buf.is_empty() { recv } else { recv.receive(buf) }
+  }
+}
+
+impl<X: Clone, R: Recv<X>> Recv<X> for Blocks<X, R> {
+  pub fn receive(self, xs: &[X]) -> Blocks<X, R> {
+    let Blocks { recv, buf, size } = self;
+    let mut recv = recv;
+    let mut buf = buf;
+    let mut rest = xs;
+
+    if !buf.is_empty() {
+      let fill = cmp::min(size - buf.len(), rest.len());
+      buf.push_all(rest.slice(0, fill));
+      rest = rest.slice(fill, rest.len());
+      if buf.len() < size {
+        return Blocks { recv: recv, buf: buf, size: size };
+      }
+      recv = recv.receive(buf);
+      buf.truncate(0);
+    }
+
+    // whole blocks are sent without copying
+    while rest.len() >= size {
+      recv = recv.receive(rest.slice(0, size));
+      rest = rest.slice(size, rest.len());
+    }
+    buf.push_all(rest);
+    Blocks { recv: recv, buf: buf, size: size }
+  }
+}
+
+/// Receiver that splits the bytes into lines and sends them (without the
+/// `\n`) to the inner receiver. The last line is sent by `finish`, if it is
+/// not empty.
+pub struct Lines<R> {
+  priv recv: R,
+  priv line: ~[u8],
+}
+
+impl<R: Recv<~[u8]>> Lines<R> {
+  pub fn new(recv: R) -> Lines<R> {
+    Lines { recv: recv, line: ~[] }
+  }
+
+  /// Sends the unterminated last line and returns the inner receiver.
+  pub fn finish(self) -> R {
+    let Lines { recv, line } = self;
+    if line.is_empty() { recv } else { recv.receive(&[line]) }
+  }
+}
+
+impl<R: Recv<~[u8]>> Recv<u8> for Lines<R> {
+  pub fn receive(self, xs: &[u8]) -> Lines<R> {
+    let Lines { recv, line } = self;
+    let mut line = line;
+    let mut lines = ~[];
+    for xs.iter().advance |&byte| {
+      if byte == '\n' as u8 {
+        lines.push(util::replace(&mut line, ~[]));
+      } else {
+        line.push(byte);
+      }
+    }
+    let recv = if lines.is_empty() { recv } else { recv.receive(lines) };
+    Lines { recv: recv, line: line }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use recv_comb::*;
+  use recv::{Recv, TryRecv, Infallible};
+
+  #[test]
+  fn test_count() {
+    let count = Count(0u64).receive(&[1, 2, 3]).receive(&[4, 5]);
+    assert_eq!(count.unwrap(), 5u64);
+
+    let (data, count) = (~[], Count(10u8)).receive(&[1, 2]);
+    assert_eq!(data, ~[1, 2]);
+    assert_eq!(count.unwrap(), 12u8);
+  }
+
+  #[test]
+  fn test_map() {
+    let double: &fn(&u8) -> uint = |&x| x as uint * 2;
+    let map = Map::new(double, ~[]).receive(&[1, 2]).receive(&[3]);
+    assert_eq!(map.unwrap(), ~[2u, 4, 6]);
+  }
+
+  #[test]
+  fn test_limit_truncates() {
+    let (limit, count) = (Limit::new(~[], 4), 0u32)
+      .receive(&[1, 2, 3]).receive(&[4, 5, 6]);
+    assert!(limit.exceeded());
+    assert_eq!(limit.unwrap(), ~[1, 2, 3, 4]);
+    assert_eq!(count, 6);
+  }
+
+  #[test]
+  fn test_limit_fails() {
+    let limit = Limit::new(Infallible(~[]), 4);
+    let limit = match limit.try_receive(&[1, 2, 3]) {
+        Ok(limit) => limit,
+        Err(_) => fail!(~"the limit failed too early"),
+      };
+    assert!(!limit.exceeded());
+
+    let limit = match (limit, 0u32).try_receive(&[4, 5]) {
+        Ok(_) => fail!(~"the limit did not fail"),
+        Err((limit, count)) => {
+          assert_eq!(count, 0);
+          limit
+        },
+      };
+    assert!(limit.exceeded());
+    assert_eq!(limit.unwrap().unwrap(), ~[1, 2, 3]);
+  }
+
+  #[test]
+  fn test_range() {
+    let range = Range::new(~[], 3, 4);
+    let range = range.receive(&[0, 1]).receive(&[2, 3, 4]);
+    assert!(!range.is_complete());
+    let range = range.receive(&[5, 6, 7, 8]).receive(&[9]);
+    assert!(range.is_complete());
+    assert_eq!(range.unwrap(), ~[3, 4, 5, 6]);
+
+    let (head, tail) = (Range::new(~[], 0, 2), Range::new(~[], 2, 100))
+      .receive(&[1, 2, 3, 4, 5]);
+    assert_eq!(head.unwrap(), ~[1, 2]);
+    assert_eq!(tail.unwrap(), ~[3, 4, 5]);
+  }
+
+  // keeps every received slice as a separate vector
+  struct SliceList {
+    slices: ~[~[uint]],
+  }
+
+  impl Recv<uint> for SliceList {
+    pub fn receive(self, xs: &[uint]) -> SliceList {
+      let mut list = self;
+      list.slices.push(xs.to_owned());
+      list
+    }
+  }
+
+  #[test]
+  fn test_blocks() {
+    let mut blocks = Blocks::new(SliceList { slices: ~[] }, 3);
+    for [&[1u, 2], &[3, 4, 5, 6, 7, 8, 9], &[], &[10]].iter().advance |xs| {
+      blocks = blocks.receive(*xs);
+    }
+    assert_eq!(blocks.finish().slices,
+      ~[~[1, 2, 3], ~[4, 5, 6], ~[7, 8, 9], ~[10]]);
+  }
+
+  #[test]
+  #[should_fail]
+  fn test_blocks_zero_size() {
+    let _ = Blocks::new(SliceList { slices: ~[] }, 0);
+  }
+
+  #[test]
+  fn test_lines() {
+    let (lines, count) = (Lines::new(~[]), 0u32)
+      .receive("first\nsec".as_bytes())
+      .receive("ond\n\nlast".as_bytes());
+    let lines = lines.finish();
+    assert_eq!(lines, ~["first".as_bytes().to_owned(),
+      "second".as_bytes().to_owned(), ~[], "last".as_bytes().to_owned()]);
+    assert_eq!(count, 18);
+  }
+}
@@ -0,0 +1,14 @@
+/// What a decoder did with a chunk of input passed by `&mut` reference (for
+/// example `Inflater::feed`). `T` is the decoded value (`()` for the decoders
+/// that send the data to a receiver) and `E` is the error type.
+#[deriving(Clone, Eq)]
+pub enum Status<'self, T, E> {
+  /// All of the input was consumed and more is needed.
+  NeedInput,
+  /// The data ended, with the input after the end. Later calls return the
+  /// whole input as unused.
+  Finished(T, &'self [u8]),
+  /// The data are corrupted, with the input after the place of the error.
+  /// Later calls return the same error.
+  Error(E, &'self [u8]),
+}
@@ -1,5 +1,7 @@
 use bits::recv;
+use checksums::checksum;
 
+#[deriving(Clone)]
 pub struct Adler32 {
   priv s1: u32,
   priv s2: u32,
@@ -34,6 +36,89 @@ impl Adler32 {
   pub fn adler32(&self) -> u32 {
     ((self.s2 % 65521) << 16) | (self.s1 % 65521)
   }
+
+  /// Returns the Adler32 of the data of `self` followed by the `other_len`
+  /// bytes of `other`.
+  pub fn combine(self, other: &Adler32, other_len: u64) -> Adler32 {
+    let a32 = combine(self.adler32(), other.adler32(), other_len);
+    Adler32 { s1: a32 & 0xffff, s2: a32 >> 16, i: 0 }
+  }
+}
+
+/// Computes the Adler32 of joined data from the Adler32 `adler1` of the first
+/// part, `adler2` of the second part and the length of the second part, like
+/// `adler32_combine` from zlib.
+pub fn combine(adler1: u32, adler2: u32, len2: u64) -> u32 {
+  let base = 65521;
+  let rem = (len2 % base as u64) as u32;
+
+  // every byte of the second part adds the first s1 to s2 once more
+  let mut s1 = adler1 & 0xffff;
+  let mut s2 = (rem * s1) % base;
+  s1 = s1 + (adler2 & 0xffff) + base - 1;
+  s2 = s2 + (adler1 >> 16) + (adler2 >> 16) + base - rem;
+
+  if s1 >= base { s1 = s1 - base; }
+  if s1 >= base { s1 = s1 - base; }
+  if s2 >= 2 * base { s2 = s2 - 2 * base; }
+  if s2 >= base { s2 = s2 - base; }
+  (s2 << 16) | s1
+}
+
+/// Adler32 of a window of fixed length that slides over the data, as the weak
+/// hash of rsync. The checksum is the same as the Adler32 of the window
+/// computed from scratch.
+#[deriving(Clone)]
+pub struct RollingAdler32 {
+  priv s1: u32,
+  priv s2: u32,
+  priv window_len: uint,
+}
+
+impl RollingAdler32 {
+  /// Starts with the first window of the data, which sets the window length.
+  pub fn new(window: &[u8]) -> RollingAdler32 {
+    let a32 = Adler32::new().update(window);
+    RollingAdler32 {
+      s1: a32.s1 % 65521,
+      s2: a32.s2 % 65521,
+      window_len: window.len(),
+    }
+  }
+
+  /// Slides the window by one byte: `old` is the first byte of the window
+  /// and `new` the byte after its end.
+  #[inline]
+  pub fn roll(self, old: u8, new: u8) -> RollingAdler32 {
+    let base = 65521;
+    // `old` was counted in s2 once for every byte of the window and the
+    // initial 1 of s1 is counted again after the new byte
+    let old_s2 = ((self.window_len % base as uint) as u32 * old as u32) % base;
+    let s1 = (self.s1 + base - old as u32 + new as u32) % base;
+    let s2 = (self.s2 + base - old_s2 + s1 + base - 1) % base;
+    RollingAdler32 { s1: s1, s2: s2, window_len: self.window_len }
+  }
+
+  pub fn window_len(&self) -> uint {
+    self.window_len
+  }
+
+  #[inline]
+  pub fn adler32(&self) -> u32 {
+    (self.s2 << 16) | self.s1
+  }
+}
+
+impl checksum::Checksum for Adler32 {
+  fn update(self, chunk: &[u8]) -> Adler32 { self.update(chunk) }
+  fn reset(self) -> Adler32 { Adler32::new() }
+  fn value(&self) -> u64 { self.adler32() as u64 }
+  fn to_bytes(&self) -> ~[u8] { checksum::be_bytes(self.value(), 32) }
+  fn width(&self) -> uint { 32 }
+  fn verify(&self, stored: u64) -> bool { self.value() == stored }
+  fn combine(self, other: &Adler32, other_len: u64) -> Adler32 {
+    self.combine(other, other_len)
+  }
 }
 
 impl recv::Recv<u8> for Adler32 {
@@ -47,6 +132,8 @@ impl recv::Recv<u8> for Adler32 {
 mod test {
   use checksums::adler32;
   use bits::recv::{Recv};
+  use std::{cmp, rand, uint, vec};
+  use std::rand::{RngUtil};
 
   fn adler32(bytes: &[u8]) -> u32 {
     adler32::Adler32::new().update(bytes).adler32()
@@ -142,4 +229,58 @@ mod test {
     a32 = a32.receive(&[115, 175, 223, 37, 68, 61, 23, 184, 210, 172]);
     assert_eq!(a32.adler32(), 0x648709e7);
   }
+
+  #[test]
+  fn test_rolling_adler32() {
+    let mut rng = rand::IsaacRng::new_seeded(&[42]);
+    let random = rng.gen_bytes(75_000);
+    let ones = vec::from_elem(75_000, 0xffu8);
+
+    for [random, ones].iter().advance |bytes| {
+      // the window lengths around the modulus test the edge cases of the
+      // removed byte
+      for (&[1u, 2, 16, 5_552, 65_520, 65_521, 65_522, 70_000]).iter().advance
+        |&len| {
+        let mut rolling = adler32::RollingAdler32::new(bytes.slice(0, len));
+        assert_eq!(rolling.adler32(), adler32(bytes.slice(0, len)));
+
+        for uint::range(len, cmp::min(len + 200, bytes.len())) |end| {
+          rolling = rolling.roll(bytes[end - len], bytes[end]);
+          let start = end + 1 - len;
+          if end % 50 == 0 || end + 1 == len + 200 {
+            assert_eq!(rolling.adler32(), adler32(bytes.slice(start, end + 1)));
+          }
+        }
+        assert_eq!(rolling.window_len(), len);
+      }
+    }
+  }
+
+  #[test]
+  fn test_adler32_combine() {
+    let mut rng = rand::IsaacRng::new_seeded(&[42]);
+    let bytes = rng.gen_bytes(10_000);
+
+    for (&[0u, 1, 7, 5_552, 9_999, 10_000]).iter().advance |&split| {
+      let (a, b) = (bytes.slice(0, split), bytes.slice(split, bytes.len()));
+      assert_eq!(adler32::combine(adler32(a), adler32(b), b.len() as u64),
+        adler32(bytes));
+
+      let a32_a = adler32::Adler32::new().update(a);
+      let a32_b = adler32::Adler32::new().update(b);
+      let a32 = a32_a.combine(&a32_b, b.len() as u64).update(&[1, 2, 3]);
+      assert_eq!(a32.adler32(), adler32(vec::append(bytes.clone(), &[1, 2, 3])));
+    }
+  }
+
+  #[test]
+  fn test_adler32_combine_long() {
+    // n zero bytes have s1 = 1 and s2 = n
+    let zeros = |n: u64| { ((n % 65521) << 16) as u32 | 1 };
+    let len1 = 5_000_000_000u64;
+    let len2 = 7_000_000_123u64;
+    assert_eq!(adler32::combine(zeros(len1), zeros(len2), len2),
+      zeros(len1 + len2));
+    assert_eq!(zeros(1000), adler32(vec::from_elem(1000, 0u8)));
+  }
 }
@@ -0,0 +1,109 @@
+use bits::recv;
+use std::vec;
+
+/// A checksum of a stream of bytes. Decoders that verify checksums are
+/// generic over this trait, so that other checksums (or `NoCheck`) can be
+/// plugged in.
+pub trait Checksum: recv::Recv<u8> + Clone {
+  /// Adds the bytes of `chunk` to the data.
+  fn update(self, chunk: &[u8]) -> Self;
+
+  /// Returns the checksum of empty data, with the same parameters.
+  fn reset(self) -> Self;
+
+  /// The checksum of the data so far.
+  fn value(&self) -> u64;
+
+  /// The checksum of the data so far as `(width() + 7) / 8` bytes, the most
+  /// significant first.
+  fn to_bytes(&self) -> ~[u8];
+
+  /// Width of the checksum in bits.
+  fn width(&self) -> uint;
+
+  /// Checks a checksum stored in the compressed data.
+  fn verify(&self, stored: u64) -> bool;
+
+  /// Returns the checksum of the data of `self` followed by the `other_len`
+  /// bytes of `other`.
+  fn combine(self, other: &Self, other_len: u64) -> Self;
+}
+
+/// Splits a checksum of `width` bits into bytes, the most significant first.
+pub fn be_bytes(value: u64, width: uint) -> ~[u8] {
+  let len = (width + 7) / 8;
+  do vec::from_fn(len) |i| {
+    (value >> (8 * (len - 1 - i))) as u8
+  }
+}
+
+/// A checksum that computes nothing and accepts any stored value, to skip the
+/// verification of trusted data.
+#[deriving(Clone, Eq)]
+pub struct NoCheck;
+
+impl Checksum for NoCheck {
+  fn update(self, _chunk: &[u8]) -> NoCheck { self }
+  fn reset(self) -> NoCheck { self }
+  fn value(&self) -> u64 { 0 }
+  fn to_bytes(&self) -> ~[u8] { ~[] }
+  fn width(&self) -> uint { 0 }
+  fn verify(&self, _stored: u64) -> bool { true }
+  fn combine(self, _other: &NoCheck, _other_len: u64) -> NoCheck { self }
+}
+
+impl recv::Recv<u8> for NoCheck {
+  #[inline]
+  pub fn receive(self, _xs: &[u8]) -> NoCheck {
+    self
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use checksums::checksum::*;
+  use checksums::{adler32, crc, crc32};
+
+  fn check<C: Checksum>(empty: C, data: &[u8], width: uint) -> C {
+    let split = data.len() / 3;
+    let (a, b) = (data.slice(0, split), data.slice(split, data.len()));
+
+    let whole = empty.clone().update(data);
+    let part_a = empty.clone().update(a);
+    let part_b = empty.clone().update(b);
+    let joined = part_a.combine(&part_b, b.len() as u64);
+
+    assert_eq!(joined.value(), whole.value());
+    assert!(whole.verify(whole.value()));
+    assert!(!whole.verify(whole.value() ^ 1));
+    assert_eq!(whole.width(), width);
+    assert_eq!(whole.to_bytes(), be_bytes(whole.value(), width));
+    assert_eq!(whole.clone().reset().value(), empty.value());
+    whole
+  }
+
+  #[test]
+  fn test_checksums() {
+    let data = "123456789".as_bytes();
+    assert_eq!(check(adler32::Adler32::new(), data, 32).value(), 0x091e01de);
+    assert_eq!(check(crc32::Crc32::new(), data, 32).value(), 0xcbf43926);
+    assert_eq!(check(crc::Crc::new(&crc::crc_16_x25), data, 16).value(), 0x906e);
+    assert_eq!(check(crc::Crc::new(&crc::crc_64_xz), data, 64).value(),
+      0x995dc9bbdf1939fa);
+    assert_eq!(check(crc::Crc::new(&crc::crc_32_bzip2), data, 32).value(),
+      0xfc891918);
+  }
+
+  #[test]
+  fn test_be_bytes() {
+    assert_eq!(be_bytes(0xcbf43926, 32), ~[0xcb, 0xf4, 0x39, 0x26]);
+    assert_eq!(be_bytes(0xdaf, 12), ~[0x0d, 0xaf]);
+  }
+
+  #[test]
+  fn test_no_check() {
+    let c = NoCheck.update(&[1, 2, 3]);
+    assert!(c.verify(0xdeadbeef));
+    assert_eq!(c.to_bytes(), ~[]);
+  }
+}
@@ -0,0 +1,343 @@
+use bits::recv;
+use checksums::checksum;
+use std::uint;
+mod tables;
+
+/// Parameters of a CRC in the Rocksoft model (from "A Painless Guide to CRC
+/// Error Detection Algorithms" by Ross Williams). `poly`, `init` and `xorout`
+/// are written unreflected, with the most significant bit first.
+#[deriving(Eq, Clone)]
+pub struct Params {
+  width: uint, // from 8 to 64 bits
+  poly: u64,
+  init: u64,
+  refin: bool,
+  refout: bool,
+  xorout: u64,
+  check: u64, // CRC of the ASCII "123456789"
+}
+
+/// A CRC algorithm with the table used to divide a byte at once. Tables of the
+/// predefined algorithms are computed by the compiler; for other parameters
+/// use `make_table`.
+pub struct Algorithm<'self> {
+  params: Params,
+  table: &'self [u64, ..256],
+}
+
+#[deriving(Clone)]
+pub struct Crc<'self> {
+  priv algo: &'self Algorithm<'self>,
+  priv reg: u64,
+}
+
+impl<'self> Crc<'self> {
+  pub fn new(algo: &'self Algorithm<'self>) -> Crc<'self> {
+    let params = &algo.params;
+    let reg = if params.refin {
+        reflect(params.init, params.width)
+      } else {
+        params.init
+      };
+    Crc { algo: algo, reg: reg }
+  }
+
+  pub fn update(self, chunk: &[u8]) -> Crc<'self> {
+    let params = &self.algo.params;
+    let table = self.algo.table;
+    let mut reg = self.reg;
+
+    // reflected CRCs keep the register reflected, so the bytes can be
+    // processed from the low end
+    if params.refin {
+      for chunk.iter().advance |&b| {
+        reg = (reg >> 8) ^ table[((reg ^ b as u64) & 0xff) as uint];
+      }
+    } else {
+      let shift = params.width - 8;
+      let mask = width_mask(params.width);
+      for chunk.iter().advance |&b| {
+        reg = ((reg << 8) ^ table[(((reg >> shift) ^ b as u64) & 0xff) as uint])
+          & mask;
+      }
+    }
+
+    Crc { algo: self.algo, reg: reg }
+  }
+
+  pub fn crc(&self) -> u64 {
+    let params = &self.algo.params;
+    let reg = if params.refin != params.refout {
+        reflect(self.reg, params.width)
+      } else {
+        self.reg
+      };
+    reg ^ params.xorout
+  }
+
+  pub fn params(&self) -> &'self Params {
+    &self.algo.params
+  }
+
+  /// Returns the CRC of the data of `self` followed by the `other_len` bytes
+  /// of `other`. Both must use the same algorithm.
+  pub fn combine(self, other: &Crc<'self>, other_len: u64) -> Crc<'self> {
+    let params = &self.algo.params;
+    assert!(*params == other.algo.params);
+
+    // in the unreflected register, the CRC is an affine function of the
+    // initial value, so the first register only has to be advanced over
+    // `other_len` zero bytes with the init removed
+    let unreflect = |reg: u64| {
+        if params.refin { reflect(reg, params.width) } else { reg }
+      };
+    let reg1 = unreflect(self.reg) ^ params.init;
+    let power = x_pow_8n(other_len, params);
+    let reg = mult_mod(reg1, power, params) ^ unreflect(other.reg);
+    Crc { algo: self.algo, reg: unreflect(reg) }
+  }
+}
+
+impl<'self> checksum::Checksum for Crc<'self> {
+  fn update(self, chunk: &[u8]) -> Crc<'self> { self.update(chunk) }
+  fn reset(self) -> Crc<'self> { Crc::new(self.algo) }
+  fn value(&self) -> u64 { self.crc() }
+  fn to_bytes(&self) -> ~[u8] {
+    checksum::be_bytes(self.crc(), self.algo.params.width)
+  }
+  fn width(&self) -> uint { self.algo.params.width }
+  fn verify(&self, stored: u64) -> bool { self.crc() == stored }
+  fn combine(self, other: &Crc<'self>, other_len: u64) -> Crc<'self> {
+    self.combine(other, other_len)
+  }
+}
+
+impl<'self> recv::Recv<u8> for Crc<'self> {
+  pub fn receive(self, xs: &[u8]) -> Crc<'self> {
+    self.update(xs)
+  }
+}
+
+/// Computes the table of a CRC with the given parameters.
+pub fn make_table(params: &Params) -> [u64, ..256] {
+  assert!(params.width >= 8 && params.width <= 64);
+  let mut table = [0u64, ..256];
+
+  for uint::range(0, 256) |n| {
+    table[n] = if params.refin {
+        let poly = reflect(params.poly, params.width);
+        let mut c = n as u64;
+        for 8.times {
+          c = if c & 1 != 0 { (c >> 1) ^ poly } else { c >> 1 };
+        }
+        c
+      } else {
+        let top = 1u64 << (params.width - 1);
+        let mut c = (n as u64) << (params.width - 8);
+        for 8.times {
+          c = if c & top != 0 { (c << 1) ^ params.poly } else { c << 1 };
+        }
+        c & width_mask(params.width)
+      };
+  }
+
+  table
+}
+
+fn reflect(x: u64, width: uint) -> u64 {
+  let mut x = x;
+  let mut r = 0u64;
+  for width.times {
+    r = (r << 1) | (x & 1);
+    x = x >> 1;
+  }
+  r
+}
+
+// multiplies two unreflected polynomials modulo the CRC polynomial
+fn mult_mod(a: u64, b: u64, params: &Params) -> u64 {
+  let top = 1u64 << (params.width - 1);
+  let mask = width_mask(params.width);
+  let mut product = 0u64;
+  let mut m = top;
+  while m != 0 {
+    product = if product & top != 0 {
+        ((product << 1) ^ params.poly) & mask
+      } else {
+        (product << 1) & mask
+      };
+    if b & m != 0 {
+      product = product ^ a;
+    }
+    m = m >> 1;
+  }
+  product
+}
+
+// x^(8 * n) modulo the CRC polynomial, by repeated squaring
+fn x_pow_8n(n: u64, params: &Params) -> u64 {
+  let mut square = 1u64; // x^0
+  for 8.times {
+    square = mult_mod(square, 2, params);
+  }
+  let mut power = 1u64;
+  let mut n = n;
+  while n != 0 {
+    if n & 1 != 0 {
+      power = mult_mod(power, square, params);
+    }
+    square = mult_mod(square, square, params);
+    n = n >> 1;
+  }
+  power
+}
+
+fn width_mask(width: uint) -> u64 {
+  !0u64 >> (64 - width)
+}
+
+// The reflected tables take the reflected polynomial.
+
+pub static crc_8: Algorithm<'static> = Algorithm {
+  params: Params { width: 8, poly: 0x07, init: 0,
+    refin: false, refout: false, xorout: 0, check: 0xf4 },
+  table: &crc_8_table,
+};
+static crc_8_table: [u64, ..256] = table_256!(normal_entry, 0x07, 8);
+
+pub static crc_16_arc: Algorithm<'static> = Algorithm {
+  params: Params { width: 16, poly: 0x8005, init: 0,
+    refin: true, refout: true, xorout: 0, check: 0xbb3d },
+  table: &crc_16_arc_table,
+};
+static crc_16_arc_table: [u64, ..256] = table_256!(reflected_entry, 0xa001);
+
+pub static crc_16_ccitt_false: Algorithm<'static> = Algorithm {
+  params: Params { width: 16, poly: 0x1021, init: 0xffff,
+    refin: false, refout: false, xorout: 0, check: 0x29b1 },
+  table: &crc_16_ccitt_table,
+};
+
+pub static crc_16_xmodem: Algorithm<'static> = Algorithm {
+  params: Params { width: 16, poly: 0x1021, init: 0,
+    refin: false, refout: false, xorout: 0, check: 0x31c3 },
+  table: &crc_16_ccitt_table,
+};
+static crc_16_ccitt_table: [u64, ..256] = table_256!(normal_entry, 0x1021, 16);
+
+pub static crc_16_kermit: Algorithm<'static> = Algorithm {
+  params: Params { width: 16, poly: 0x1021, init: 0,
+    refin: true, refout: true, xorout: 0, check: 0x2189 },
+  table: &crc_16_ccitt_refl_table,
+};
+
+pub static crc_16_x25: Algorithm<'static> = Algorithm {
+  params: Params { width: 16, poly: 0x1021, init: 0xffff,
+    refin: true, refout: true, xorout: 0xffff, check: 0x906e },
+  table: &crc_16_ccitt_refl_table,
+};
+static crc_16_ccitt_refl_table: [u64, ..256] = table_256!(reflected_entry, 0x8408);
+
+/// The CRC-32 of zlib and gzip; `crc32::Crc32` computes it faster.
+pub static crc_32: Algorithm<'static> = Algorithm {
+  params: Params { width: 32, poly: 0x04c1_1db7, init: 0xffff_ffff,
+    refin: true, refout: true, xorout: 0xffff_ffff, check: 0xcbf4_3926 },
+  table: &crc_32_table,
+};
+static crc_32_table: [u64, ..256] = table_256!(reflected_entry, 0xedb8_8320);
+
+pub static crc_32_bzip2: Algorithm<'static> = Algorithm {
+  params: Params { width: 32, poly: 0x04c1_1db7, init: 0xffff_ffff,
+    refin: false, refout: false, xorout: 0xffff_ffff, check: 0xfc89_1918 },
+  table: &crc_32_bzip2_table,
+};
+static crc_32_bzip2_table: [u64, ..256] = table_256!(normal_entry, 0x04c1_1db7, 32);
+
+/// CRC-32C (Castagnoli), used by iSCSI, SCTP and ext4.
+pub static crc_32c: Algorithm<'static> = Algorithm {
+  params: Params { width: 32, poly: 0x1edc_6f41, init: 0xffff_ffff,
+    refin: true, refout: true, xorout: 0xffff_ffff, check: 0xe306_9283 },
+  table: &crc_32c_table,
+};
+static crc_32c_table: [u64, ..256] = table_256!(reflected_entry, 0x82f6_3b78);
+
+/// The CRC-64 of xz.
+pub static crc_64_xz: Algorithm<'static> = Algorithm {
+  params: Params { width: 64, poly: 0x42f0_e1eb_a9ea_3693,
+    init: 0xffff_ffff_ffff_ffff, refin: true, refout: true,
+    xorout: 0xffff_ffff_ffff_ffff, check: 0x995d_c9bb_df19_39fa },
+  table: &crc_64_xz_table,
+};
+static crc_64_xz_table: [u64, ..256] =
+  table_256!(reflected_entry, 0xc96c_5795_d787_0f42);
+
+pub static crc_64_ecma: Algorithm<'static> = Algorithm {
+  params: Params { width: 64, poly: 0x42f0_e1eb_a9ea_3693, init: 0,
+    refin: false, refout: false, xorout: 0, check: 0x6c40_df5f_0b49_7347 },
+  table: &crc_64_ecma_table,
+};
+static crc_64_ecma_table: [u64, ..256] =
+  table_256!(normal_entry, 0x42f0_e1eb_a9ea_3693, 64);
+
+#[cfg(test)]
+mod test {
+  use checksums::crc;
+  use checksums::crc32;
+  use bits::recv::{Recv};
+  use std::rand;
+  use std::rand::{RngUtil};
+
+  fn algorithms() -> ~[&'static crc::Algorithm<'static>] {
+    ~[&crc::crc_8, &crc::crc_16_arc, &crc::crc_16_ccitt_false,
+      &crc::crc_16_xmodem, &crc::crc_16_kermit, &crc::crc_16_x25,
+      &crc::crc_32, &crc::crc_32_bzip2, &crc::crc_32c,
+      &crc::crc_64_xz, &crc::crc_64_ecma]
+  }
+
+  fn crc_of(algo: &crc::Algorithm, bytes: &[u8]) -> u64 {
+    crc::Crc::new(algo).update(bytes).crc()
+  }
+
+  #[test]
+  fn test_check() {
+    for algorithms().iter().advance |&algo| {
+      assert_eq!(crc_of(algo, "123456789".as_bytes()), algo.params.check);
+    }
+  }
+
+  #[test]
+  fn test_tables() {
+    for algorithms().iter().advance |&algo| {
+      let table = crc::make_table(&algo.params);
+      assert_eq!(table.slice(0, 256).to_owned(),
+        algo.table.slice(0, 256).to_owned());
+    }
+  }
+
+  #[test]
+  fn test_custom() {
+    // CRC-12/UMTS reflects only the output
+    let params = crc::Params { width: 12, poly: 0x80f, init: 0,
+      refin: false, refout: true, xorout: 0, check: 0xdaf };
+    let table = crc::make_table(&params);
+    let algo = crc::Algorithm { params: params, table: &table };
+    assert_eq!(crc_of(&algo, "123456789".as_bytes()), 0xdaf);
+  }
+
+  #[test]
+  fn test_chunked() {
+    let mut rng = rand::IsaacRng::new_seeded(&[42]);
+    let bytes = rng.gen_bytes(3_000);
+
+    for algorithms().iter().advance |&algo| {
+      let mut c = crc::Crc::new(algo);
+      for bytes.chunk_iter(7).advance |chunk| {
+        c = c.receive(chunk);
+      }
+      assert_eq!(c.crc(), crc_of(algo, bytes));
+    }
+
+    assert_eq!(crc_of(&crc::crc_32, bytes),
+      crc32::Crc32::new().update(bytes).crc32() as u64);
+  }
+}
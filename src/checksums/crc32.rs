@@ -1,5 +1,8 @@
 use bits::recv;
+use checksums::checksum;
+mod tables;
 
+#[deriving(Clone)]
 pub struct Crc32 {
   priv crc: u32,
 }
@@ -10,14 +13,84 @@ impl Crc32 {
   }
 
   pub fn update(self, chunk: &[u8]) -> Crc32 {
-    let crc = do chunk.iter().fold(self.crc ^ 0xffff_ffff) 
-      |c, &byte| { crc_table[c as u8 ^ byte] ^ (c >> 8) } ^ 0xffff_ffff;
-    Crc32 { crc: crc }
+    let mut c = self.crc ^ 0xffff_ffff;
+    let mut i = 0;
+
+    // slice-by-8: the first four bytes are combined with the CRC
+    while i + 8 <= chunk.len() {
+      let lo = c ^ (chunk[i] as u32 | (chunk[i + 1] as u32 << 8)
+        | (chunk[i + 2] as u32 << 16) | (chunk[i + 3] as u32 << 24));
+      c = crc_table_7[lo & 0xff] ^ crc_table_6[(lo >> 8) & 0xff]
+        ^ crc_table_5[(lo >> 16) & 0xff] ^ crc_table_4[lo >> 24]
+        ^ crc_table_3[chunk[i + 4]] ^ crc_table_2[chunk[i + 5]]
+        ^ crc_table_1[chunk[i + 6]] ^ crc_table_0[chunk[i + 7]];
+      i = i + 8;
+    }
+
+    while i < chunk.len() {
+      c = crc_table_0[c as u8 ^ chunk[i]] ^ (c >> 8);
+      i = i + 1;
+    }
+
+    Crc32 { crc: c ^ 0xffff_ffff }
   }
 
   pub fn crc32(&self) -> u32 {
     self.crc
   }
+
+  /// Returns the CRC of the data of `self` followed by the `other_len` bytes
+  /// of `other`.
+  pub fn combine(self, other: &Crc32, other_len: u64) -> Crc32 {
+    Crc32 { crc: combine(self.crc, other.crc, other_len) }
+  }
+}
+
+/// Computes the CRC32 of joined data from the CRC32 `crc1` of the first part,
+/// `crc2` of the second part and the length of the second part, like
+/// `crc32_combine` from zlib.
+pub fn combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+  // appending len2 bytes multiplies the first CRC by x^(8 * len2) modulo the
+  // polynomial, the power is computed by repeated squaring
+  let mut power = 1u32 << 31; // x^0
+  let mut square = 1u32 << 23; // x^8
+  let mut n = len2;
+  while n != 0 {
+    if n & 1 != 0 {
+      power = mult_mod(square, power);
+    }
+    square = mult_mod(square, square);
+    n = n >> 1;
+  }
+  mult_mod(power, crc1) ^ crc2
+}
+
+// multiplies two polynomials (in the reflected bit order) modulo the CRC
+// polynomial
+fn mult_mod(a: u32, b: u32) -> u32 {
+  let mut m = 1u32 << 31;
+  let mut b = b;
+  let mut product = 0;
+  while m != 0 {
+    if a & m != 0 {
+      product = product ^ b;
+    }
+    m = m >> 1;
+    b = if b & 1 != 0 { (b >> 1) ^ 0xedb8_8320 } else { b >> 1 };
+  }
+  product
+}
+
+impl checksum::Checksum for Crc32 {
+  fn update(self, chunk: &[u8]) -> Crc32 { self.update(chunk) }
+  fn reset(self) -> Crc32 { Crc32::new() }
+  fn value(&self) -> u64 { self.crc32() as u64 }
+  fn to_bytes(&self) -> ~[u8] { checksum::be_bytes(self.value(), 32) }
+  fn width(&self) -> uint { 32 }
+  fn verify(&self, stored: u64) -> bool { self.value() == stored }
+  fn combine(self, other: &Crc32, other_len: u64) -> Crc32 {
+    self.combine(other, other_len)
+  }
 }
 
 impl recv::Recv<u8> for Crc32 {
@@ -27,58 +100,60 @@ impl recv::Recv<u8> for Crc32 {
   }
 }
 
+// The tables are computed by the compiler from the polynomial. `crc_table_0`
+// is the usual byte-at-a-time table, `crc_table_k` advances the CRC of a byte
+// followed by `k` zero bytes, so that eight bytes can be processed at once.
+
+// one bit of the CRC division (RFC 1952, section 8)
+macro_rules! crc_bit(
+  ($c:expr) => ((($c) >> 1) ^ (0xedb8_8320 * (($c) & 1)))
+)
+
+macro_rules! crc_entry_0(
+  ($n:expr) => (crc_bit!(crc_bit!(crc_bit!(crc_bit!(
+    crc_bit!(crc_bit!(crc_bit!(crc_bit!($n as u32)))))))))
+)
 
-// autogenerated by make_crc_table.rb
-static crc_table: [u32, ..256] = [
-   0x00000000, 0x77073096, 0xee0e612c, 0x990951ba, 0x076dc419, 0x706af48f, 
-   0xe963a535, 0x9e6495a3, 0x0edb8832, 0x79dcb8a4, 0xe0d5e91e, 0x97d2d988, 
-   0x09b64c2b, 0x7eb17cbd, 0xe7b82d07, 0x90bf1d91, 0x1db71064, 0x6ab020f2, 
-   0xf3b97148, 0x84be41de, 0x1adad47d, 0x6ddde4eb, 0xf4d4b551, 0x83d385c7, 
-   0x136c9856, 0x646ba8c0, 0xfd62f97a, 0x8a65c9ec, 0x14015c4f, 0x63066cd9, 
-   0xfa0f3d63, 0x8d080df5, 0x3b6e20c8, 0x4c69105e, 0xd56041e4, 0xa2677172, 
-   0x3c03e4d1, 0x4b04d447, 0xd20d85fd, 0xa50ab56b, 0x35b5a8fa, 0x42b2986c, 
-   0xdbbbc9d6, 0xacbcf940, 0x32d86ce3, 0x45df5c75, 0xdcd60dcf, 0xabd13d59, 
-   0x26d930ac, 0x51de003a, 0xc8d75180, 0xbfd06116, 0x21b4f4b5, 0x56b3c423, 
-   0xcfba9599, 0xb8bda50f, 0x2802b89e, 0x5f058808, 0xc60cd9b2, 0xb10be924, 
-   0x2f6f7c87, 0x58684c11, 0xc1611dab, 0xb6662d3d, 0x76dc4190, 0x01db7106, 
-   0x98d220bc, 0xefd5102a, 0x71b18589, 0x06b6b51f, 0x9fbfe4a5, 0xe8b8d433, 
-   0x7807c9a2, 0x0f00f934, 0x9609a88e, 0xe10e9818, 0x7f6a0dbb, 0x086d3d2d, 
-   0x91646c97, 0xe6635c01, 0x6b6b51f4, 0x1c6c6162, 0x856530d8, 0xf262004e, 
-   0x6c0695ed, 0x1b01a57b, 0x8208f4c1, 0xf50fc457, 0x65b0d9c6, 0x12b7e950, 
-   0x8bbeb8ea, 0xfcb9887c, 0x62dd1ddf, 0x15da2d49, 0x8cd37cf3, 0xfbd44c65, 
-   0x4db26158, 0x3ab551ce, 0xa3bc0074, 0xd4bb30e2, 0x4adfa541, 0x3dd895d7, 
-   0xa4d1c46d, 0xd3d6f4fb, 0x4369e96a, 0x346ed9fc, 0xad678846, 0xda60b8d0, 
-   0x44042d73, 0x33031de5, 0xaa0a4c5f, 0xdd0d7cc9, 0x5005713c, 0x270241aa, 
-   0xbe0b1010, 0xc90c2086, 0x5768b525, 0x206f85b3, 0xb966d409, 0xce61e49f, 
-   0x5edef90e, 0x29d9c998, 0xb0d09822, 0xc7d7a8b4, 0x59b33d17, 0x2eb40d81, 
-   0xb7bd5c3b, 0xc0ba6cad, 0xedb88320, 0x9abfb3b6, 0x03b6e20c, 0x74b1d29a, 
-   0xead54739, 0x9dd277af, 0x04db2615, 0x73dc1683, 0xe3630b12, 0x94643b84, 
-   0x0d6d6a3e, 0x7a6a5aa8, 0xe40ecf0b, 0x9309ff9d, 0x0a00ae27, 0x7d079eb1, 
-   0xf00f9344, 0x8708a3d2, 0x1e01f268, 0x6906c2fe, 0xf762575d, 0x806567cb, 
-   0x196c3671, 0x6e6b06e7, 0xfed41b76, 0x89d32be0, 0x10da7a5a, 0x67dd4acc, 
-   0xf9b9df6f, 0x8ebeeff9, 0x17b7be43, 0x60b08ed5, 0xd6d6a3e8, 0xa1d1937e, 
-   0x38d8c2c4, 0x4fdff252, 0xd1bb67f1, 0xa6bc5767, 0x3fb506dd, 0x48b2364b, 
-   0xd80d2bda, 0xaf0a1b4c, 0x36034af6, 0x41047a60, 0xdf60efc3, 0xa867df55, 
-   0x316e8eef, 0x4669be79, 0xcb61b38c, 0xbc66831a, 0x256fd2a0, 0x5268e236, 
-   0xcc0c7795, 0xbb0b4703, 0x220216b9, 0x5505262f, 0xc5ba3bbe, 0xb2bd0b28, 
-   0x2bb45a92, 0x5cb36a04, 0xc2d7ffa7, 0xb5d0cf31, 0x2cd99e8b, 0x5bdeae1d, 
-   0x9b64c2b0, 0xec63f226, 0x756aa39c, 0x026d930a, 0x9c0906a9, 0xeb0e363f, 
-   0x72076785, 0x05005713, 0x95bf4a82, 0xe2b87a14, 0x7bb12bae, 0x0cb61b38, 
-   0x92d28e9b, 0xe5d5be0d, 0x7cdcefb7, 0x0bdbdf21, 0x86d3d2d4, 0xf1d4e242, 
-   0x68ddb3f8, 0x1fda836e, 0x81be16cd, 0xf6b9265b, 0x6fb077e1, 0x18b74777, 
-   0x88085ae6, 0xff0f6a70, 0x66063bca, 0x11010b5c, 0x8f659eff, 0xf862ae69, 
-   0x616bffd3, 0x166ccf45, 0xa00ae278, 0xd70dd2ee, 0x4e048354, 0x3903b3c2, 
-   0xa7672661, 0xd06016f7, 0x4969474d, 0x3e6e77db, 0xaed16a4a, 0xd9d65adc, 
-   0x40df0b66, 0x37d83bf0, 0xa9bcae53, 0xdebb9ec5, 0x47b2cf7f, 0x30b5ffe9, 
-   0xbdbdf21c, 0xcabac28a, 0x53b39330, 0x24b4a3a6, 0xbad03605, 0xcdd70693, 
-   0x54de5729, 0x23d967bf, 0xb3667a2e, 0xc4614ab8, 0x5d681b02, 0x2a6f2b94, 
-   0xb40bbe37, 0xc30c8ea1, 0x5a05df1b, 0x2d02ef8d, 
-];
+macro_rules! crc_slice(
+  ($prev:expr) => ((($prev) >> 8) ^ crc_table_0[($prev) & 0xff])
+)
+
+macro_rules! crc_entry_1( ($n:expr) => (crc_slice!(crc_table_0[$n])) )
+macro_rules! crc_entry_2( ($n:expr) => (crc_slice!(crc_table_1[$n])) )
+macro_rules! crc_entry_3( ($n:expr) => (crc_slice!(crc_table_2[$n])) )
+macro_rules! crc_entry_4( ($n:expr) => (crc_slice!(crc_table_3[$n])) )
+macro_rules! crc_entry_5( ($n:expr) => (crc_slice!(crc_table_4[$n])) )
+macro_rules! crc_entry_6( ($n:expr) => (crc_slice!(crc_table_5[$n])) )
+macro_rules! crc_entry_7( ($n:expr) => (crc_slice!(crc_table_6[$n])) )
+
+static crc_table_0: [u32, ..256] = table_256!(crc_entry_0);
+static crc_table_1: [u32, ..256] = table_256!(crc_entry_1);
+static crc_table_2: [u32, ..256] = table_256!(crc_entry_2);
+static crc_table_3: [u32, ..256] = table_256!(crc_entry_3);
+static crc_table_4: [u32, ..256] = table_256!(crc_entry_4);
+static crc_table_5: [u32, ..256] = table_256!(crc_entry_5);
+static crc_table_6: [u32, ..256] = table_256!(crc_entry_6);
+static crc_table_7: [u32, ..256] = table_256!(crc_entry_7);
 
 #[cfg(test)]
 mod test {
   use checksums::crc32;
   use bits::recv::{Recv};
+  use extra::test;
+  use std::{rand, uint, vec};
+  use std::rand::{RngUtil};
+
+  // bit-by-bit CRC from RFC 1952, without tables
+  fn crc32_bitwise(bytes: &[u8]) -> u32 {
+    let mut c = 0xffff_ffffu32;
+    for bytes.iter().advance |&byte| {
+      c = c ^ byte as u32;
+      for 8.times {
+        c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+      }
+    }
+    c ^ 0xffff_ffff
+  }
 
   fn crc32(bytes: &[u8]) -> u32 {
     crc32::Crc32::new().update(bytes).crc32()
@@ -131,4 +206,84 @@ mod test {
 
     assert_eq!(crc.crc32(), 0x94507ff3);
   }
+
+  #[test]
+  fn test_tables() {
+    assert_eq!(crc32::crc_table_0[1], 0x7707_3096);
+    assert_eq!(crc32::crc_table_0[255], 0x2d02_ef8d);
+    for uint::range(0, 256) |n| {
+      let mut c = n as u32;
+      for 8.times {
+        c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+      }
+      assert_eq!(crc32::crc_table_0[n], c);
+    }
+  }
+
+  #[test]
+  fn test_chunk_boundaries() {
+    let mut rng = rand::IsaacRng::new_seeded(&[42]);
+    let bytes = rng.gen_bytes(1_000);
+    let whole = crc32_bitwise(bytes);
+    assert_eq!(crc32(bytes), whole);
+
+    for uint::range(0, 40) |split| {
+      let crc = crc32::Crc32::new()
+        .update(bytes.slice(0, split))
+        .update(bytes.slice(split, bytes.len()));
+      assert_eq!(crc.crc32(), whole);
+
+      let mut crc = crc32::Crc32::new();
+      for bytes.chunk_iter(split + 1).advance |chunk| {
+        crc = crc.receive(chunk);
+      }
+      assert_eq!(crc.crc32(), whole);
+    }
+
+    for uint::range(0, 20) |len| {
+      assert_eq!(crc32(bytes.slice(3, 3 + len)),
+        crc32_bitwise(bytes.slice(3, 3 + len)));
+    }
+  }
+
+  #[test]
+  fn test_combine() {
+    let mut rng = rand::IsaacRng::new_seeded(&[42]);
+    let bytes = rng.gen_bytes(3_000);
+
+    for (&[0u, 1, 7, 8, 100, 2_999, 3_000]).iter().advance |&split| {
+      let (a, b) = (bytes.slice(0, split), bytes.slice(split, bytes.len()));
+      assert_eq!(crc32::combine(crc32(a), crc32(b), b.len() as u64), crc32(bytes));
+
+      let crc_a = crc32::Crc32::new().update(a);
+      let crc_b = crc32::Crc32::new().update(b);
+      let crc = crc_a.combine(&crc_b, b.len() as u64).update(&[1, 2, 3]);
+      assert_eq!(crc.crc32(), crc32(vec::append(bytes.clone(), &[1, 2, 3])));
+    }
+  }
+
+  #[test]
+  fn test_combine_long() {
+    // CRCs of runs of 2^k zero bytes, by doubling
+    let mut zeros = ~[crc32(&[0])];
+    for uint::range(1, 35) |k| {
+      let half = zeros[k - 1];
+      zeros.push(crc32::combine(half, half, 1 << (k - 1)));
+    }
+    assert_eq!(zeros[10], crc32(vec::from_elem(1024, 0u8)));
+
+    // 3 * 2^32 zeros in two ways
+    assert_eq!(crc32::combine(zeros[33], zeros[32], 1 << 32),
+      crc32::combine(zeros[32], zeros[33], 1 << 33));
+    assert!(zeros[33] != zeros[32]);
+  }
+
+  #[bench]
+  fn bench_crc32(b: &mut test::BenchHarness) {
+    let mut rng = rand::IsaacRng::new_seeded(&[42]);
+    let bytes = rng.gen_bytes(100_000);
+    do b.iter {
+      crc32(bytes);
+    }
+  }
 }
@@ -0,0 +1,108 @@
+#[macro_escape];
+
+// Macros that build CRC tables in static initializers, so that the tables are
+// computed by the compiler.
+
+// expands to `[$entry!(0, args...), $entry!(1, args...), ..., $entry!(255, args...)]`
+macro_rules! table_256(
+  ($entry:ident $(, $arg:expr)*) => ([
+    $entry!(0 $(, $arg)*), $entry!(1 $(, $arg)*), $entry!(2 $(, $arg)*), $entry!(3 $(, $arg)*),
+    $entry!(4 $(, $arg)*), $entry!(5 $(, $arg)*), $entry!(6 $(, $arg)*), $entry!(7 $(, $arg)*),
+    $entry!(8 $(, $arg)*), $entry!(9 $(, $arg)*), $entry!(10 $(, $arg)*), $entry!(11 $(, $arg)*),
+    $entry!(12 $(, $arg)*), $entry!(13 $(, $arg)*), $entry!(14 $(, $arg)*), $entry!(15 $(, $arg)*),
+    $entry!(16 $(, $arg)*), $entry!(17 $(, $arg)*), $entry!(18 $(, $arg)*), $entry!(19 $(, $arg)*),
+    $entry!(20 $(, $arg)*), $entry!(21 $(, $arg)*), $entry!(22 $(, $arg)*), $entry!(23 $(, $arg)*),
+    $entry!(24 $(, $arg)*), $entry!(25 $(, $arg)*), $entry!(26 $(, $arg)*), $entry!(27 $(, $arg)*),
+    $entry!(28 $(, $arg)*), $entry!(29 $(, $arg)*), $entry!(30 $(, $arg)*), $entry!(31 $(, $arg)*),
+    $entry!(32 $(, $arg)*), $entry!(33 $(, $arg)*), $entry!(34 $(, $arg)*), $entry!(35 $(, $arg)*),
+    $entry!(36 $(, $arg)*), $entry!(37 $(, $arg)*), $entry!(38 $(, $arg)*), $entry!(39 $(, $arg)*),
+    $entry!(40 $(, $arg)*), $entry!(41 $(, $arg)*), $entry!(42 $(, $arg)*), $entry!(43 $(, $arg)*),
+    $entry!(44 $(, $arg)*), $entry!(45 $(, $arg)*), $entry!(46 $(, $arg)*), $entry!(47 $(, $arg)*),
+    $entry!(48 $(, $arg)*), $entry!(49 $(, $arg)*), $entry!(50 $(, $arg)*), $entry!(51 $(, $arg)*),
+    $entry!(52 $(, $arg)*), $entry!(53 $(, $arg)*), $entry!(54 $(, $arg)*), $entry!(55 $(, $arg)*),
+    $entry!(56 $(, $arg)*), $entry!(57 $(, $arg)*), $entry!(58 $(, $arg)*), $entry!(59 $(, $arg)*),
+    $entry!(60 $(, $arg)*), $entry!(61 $(, $arg)*), $entry!(62 $(, $arg)*), $entry!(63 $(, $arg)*),
+    $entry!(64 $(, $arg)*), $entry!(65 $(, $arg)*), $entry!(66 $(, $arg)*), $entry!(67 $(, $arg)*),
+    $entry!(68 $(, $arg)*), $entry!(69 $(, $arg)*), $entry!(70 $(, $arg)*), $entry!(71 $(, $arg)*),
+    $entry!(72 $(, $arg)*), $entry!(73 $(, $arg)*), $entry!(74 $(, $arg)*), $entry!(75 $(, $arg)*),
+    $entry!(76 $(, $arg)*), $entry!(77 $(, $arg)*), $entry!(78 $(, $arg)*), $entry!(79 $(, $arg)*),
+    $entry!(80 $(, $arg)*), $entry!(81 $(, $arg)*), $entry!(82 $(, $arg)*), $entry!(83 $(, $arg)*),
+    $entry!(84 $(, $arg)*), $entry!(85 $(, $arg)*), $entry!(86 $(, $arg)*), $entry!(87 $(, $arg)*),
+    $entry!(88 $(, $arg)*), $entry!(89 $(, $arg)*), $entry!(90 $(, $arg)*), $entry!(91 $(, $arg)*),
+    $entry!(92 $(, $arg)*), $entry!(93 $(, $arg)*), $entry!(94 $(, $arg)*), $entry!(95 $(, $arg)*),
+    $entry!(96 $(, $arg)*), $entry!(97 $(, $arg)*), $entry!(98 $(, $arg)*), $entry!(99 $(, $arg)*),
+    $entry!(100 $(, $arg)*), $entry!(101 $(, $arg)*), $entry!(102 $(, $arg)*), $entry!(103 $(, $arg)*),
+    $entry!(104 $(, $arg)*), $entry!(105 $(, $arg)*), $entry!(106 $(, $arg)*), $entry!(107 $(, $arg)*),
+    $entry!(108 $(, $arg)*), $entry!(109 $(, $arg)*), $entry!(110 $(, $arg)*), $entry!(111 $(, $arg)*),
+    $entry!(112 $(, $arg)*), $entry!(113 $(, $arg)*), $entry!(114 $(, $arg)*), $entry!(115 $(, $arg)*),
+    $entry!(116 $(, $arg)*), $entry!(117 $(, $arg)*), $entry!(118 $(, $arg)*), $entry!(119 $(, $arg)*),
+    $entry!(120 $(, $arg)*), $entry!(121 $(, $arg)*), $entry!(122 $(, $arg)*), $entry!(123 $(, $arg)*),
+    $entry!(124 $(, $arg)*), $entry!(125 $(, $arg)*), $entry!(126 $(, $arg)*), $entry!(127 $(, $arg)*),
+    $entry!(128 $(, $arg)*), $entry!(129 $(, $arg)*), $entry!(130 $(, $arg)*), $entry!(131 $(, $arg)*),
+    $entry!(132 $(, $arg)*), $entry!(133 $(, $arg)*), $entry!(134 $(, $arg)*), $entry!(135 $(, $arg)*),
+    $entry!(136 $(, $arg)*), $entry!(137 $(, $arg)*), $entry!(138 $(, $arg)*), $entry!(139 $(, $arg)*),
+    $entry!(140 $(, $arg)*), $entry!(141 $(, $arg)*), $entry!(142 $(, $arg)*), $entry!(143 $(, $arg)*),
+    $entry!(144 $(, $arg)*), $entry!(145 $(, $arg)*), $entry!(146 $(, $arg)*), $entry!(147 $(, $arg)*),
+    $entry!(148 $(, $arg)*), $entry!(149 $(, $arg)*), $entry!(150 $(, $arg)*), $entry!(151 $(, $arg)*),
+    $entry!(152 $(, $arg)*), $entry!(153 $(, $arg)*), $entry!(154 $(, $arg)*), $entry!(155 $(, $arg)*),
+    $entry!(156 $(, $arg)*), $entry!(157 $(, $arg)*), $entry!(158 $(, $arg)*), $entry!(159 $(, $arg)*),
+    $entry!(160 $(, $arg)*), $entry!(161 $(, $arg)*), $entry!(162 $(, $arg)*), $entry!(163 $(, $arg)*),
+    $entry!(164 $(, $arg)*), $entry!(165 $(, $arg)*), $entry!(166 $(, $arg)*), $entry!(167 $(, $arg)*),
+    $entry!(168 $(, $arg)*), $entry!(169 $(, $arg)*), $entry!(170 $(, $arg)*), $entry!(171 $(, $arg)*),
+    $entry!(172 $(, $arg)*), $entry!(173 $(, $arg)*), $entry!(174 $(, $arg)*), $entry!(175 $(, $arg)*),
+    $entry!(176 $(, $arg)*), $entry!(177 $(, $arg)*), $entry!(178 $(, $arg)*), $entry!(179 $(, $arg)*),
+    $entry!(180 $(, $arg)*), $entry!(181 $(, $arg)*), $entry!(182 $(, $arg)*), $entry!(183 $(, $arg)*),
+    $entry!(184 $(, $arg)*), $entry!(185 $(, $arg)*), $entry!(186 $(, $arg)*), $entry!(187 $(, $arg)*),
+    $entry!(188 $(, $arg)*), $entry!(189 $(, $arg)*), $entry!(190 $(, $arg)*), $entry!(191 $(, $arg)*),
+    $entry!(192 $(, $arg)*), $entry!(193 $(, $arg)*), $entry!(194 $(, $arg)*), $entry!(195 $(, $arg)*),
+    $entry!(196 $(, $arg)*), $entry!(197 $(, $arg)*), $entry!(198 $(, $arg)*), $entry!(199 $(, $arg)*),
+    $entry!(200 $(, $arg)*), $entry!(201 $(, $arg)*), $entry!(202 $(, $arg)*), $entry!(203 $(, $arg)*),
+    $entry!(204 $(, $arg)*), $entry!(205 $(, $arg)*), $entry!(206 $(, $arg)*), $entry!(207 $(, $arg)*),
+    $entry!(208 $(, $arg)*), $entry!(209 $(, $arg)*), $entry!(210 $(, $arg)*), $entry!(211 $(, $arg)*),
+    $entry!(212 $(, $arg)*), $entry!(213 $(, $arg)*), $entry!(214 $(, $arg)*), $entry!(215 $(, $arg)*),
+    $entry!(216 $(, $arg)*), $entry!(217 $(, $arg)*), $entry!(218 $(, $arg)*), $entry!(219 $(, $arg)*),
+    $entry!(220 $(, $arg)*), $entry!(221 $(, $arg)*), $entry!(222 $(, $arg)*), $entry!(223 $(, $arg)*),
+    $entry!(224 $(, $arg)*), $entry!(225 $(, $arg)*), $entry!(226 $(, $arg)*), $entry!(227 $(, $arg)*),
+    $entry!(228 $(, $arg)*), $entry!(229 $(, $arg)*), $entry!(230 $(, $arg)*), $entry!(231 $(, $arg)*),
+    $entry!(232 $(, $arg)*), $entry!(233 $(, $arg)*), $entry!(234 $(, $arg)*), $entry!(235 $(, $arg)*),
+    $entry!(236 $(, $arg)*), $entry!(237 $(, $arg)*), $entry!(238 $(, $arg)*), $entry!(239 $(, $arg)*),
+    $entry!(240 $(, $arg)*), $entry!(241 $(, $arg)*), $entry!(242 $(, $arg)*), $entry!(243 $(, $arg)*),
+    $entry!(244 $(, $arg)*), $entry!(245 $(, $arg)*), $entry!(246 $(, $arg)*), $entry!(247 $(, $arg)*),
+    $entry!(248 $(, $arg)*), $entry!(249 $(, $arg)*), $entry!(250 $(, $arg)*), $entry!(251 $(, $arg)*),
+    $entry!(252 $(, $arg)*), $entry!(253 $(, $arg)*), $entry!(254 $(, $arg)*), $entry!(255 $(, $arg)*),
+  ])
+)
+
+// one bit of the division for CRCs with reflected input (the polynomial is
+// also reflected)
+macro_rules! reflected_bit(
+  ($c:expr, $poly:expr) => ((($c) >> 1) ^ (($poly) * (($c) & 1)))
+)
+
+macro_rules! reflected_entry(
+  ($n:expr, $poly:expr) => (
+    reflected_bit!(reflected_bit!(reflected_bit!(reflected_bit!(
+    reflected_bit!(reflected_bit!(reflected_bit!(reflected_bit!(
+      $n as u64, $poly), $poly), $poly), $poly),
+      $poly), $poly), $poly), $poly)
+  )
+)
+
+// one bit of the division for CRCs of `$width` bits with the most
+// significant bit first
+macro_rules! normal_bit(
+  ($c:expr, $poly:expr, $width:expr) => (
+    ((($c) << 1) ^ (($poly) * ((($c) >> (($width) - 1)) & 1)))
+      & (!0u64 >> (64 - ($width)))
+  )
+)
+
+macro_rules! normal_entry(
+  ($n:expr, $poly:expr, $width:expr) => (
+    normal_bit!(normal_bit!(normal_bit!(normal_bit!(
+    normal_bit!(normal_bit!(normal_bit!(normal_bit!(
+      ($n as u64) << (($width) - 8),
+      $poly, $width), $poly, $width), $poly, $width), $poly, $width),
+      $poly, $width), $poly, $width), $poly, $width), $poly, $width)
+  )
+)
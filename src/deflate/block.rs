@@ -0,0 +1,367 @@
+use std::{vec, uint, cmp};
+use bits;
+use deflate::huff;
+use deflate::lz77;
+
+pub static max_symbols: uint = 16_384;
+
+static litlen_count: uint = 286;
+static dist_count: uint = 30;
+static meta_count: uint = 19;
+static block_end: uint = 256;
+static max_stored: uint = 65_535;
+
+static len_base: [uint, ..29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+  35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+static len_extra: [uint, ..29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+  3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+static dist_base: [uint, ..30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+  257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+  8193, 12289, 16385, 24577,
+];
+static dist_extra: [uint, ..30] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+  7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+static meta_len_order: [uint, ..19] = [
+  16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Symbols of one block together with their frequencies.
+pub struct Block {
+  priv symbols: ~[lz77::Symbol],
+  priv litlen_freqs: ~[uint],
+  priv dist_freqs: ~[uint],
+  priv extra_bits: uint,
+}
+
+struct Codes {
+  litlen_lens: ~[u8],
+  litlen_codes: ~[u16],
+  dist_lens: ~[u8],
+  dist_codes: ~[u16],
+}
+
+impl Block {
+  pub fn new() -> Block {
+    Block {
+      symbols: vec::with_capacity(max_symbols),
+      litlen_freqs: vec::from_elem(litlen_count, 0u),
+      dist_freqs: vec::from_elem(dist_count, 0u),
+      extra_bits: 0,
+    }
+  }
+
+  pub fn push(&mut self, symbol: lz77::Symbol) {
+    match symbol {
+      lz77::Literal(byte) => {
+        self.litlen_freqs[byte as uint] += 1;
+      },
+      lz77::Match(len, dist) => {
+        let len_idx = len_code(len);
+        let dist_idx = dist_code(dist);
+        self.litlen_freqs[257 + len_idx] += 1;
+        self.dist_freqs[dist_idx] += 1;
+        self.extra_bits += len_extra[len_idx] + dist_extra[dist_idx];
+      },
+    }
+    self.symbols.push(symbol);
+  }
+
+  pub fn is_full(&self) -> bool {
+    self.symbols.len() >= max_symbols
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.symbols.is_empty()
+  }
+
+  pub fn symbols<'a>(&'a self) -> &'a [lz77::Symbol] {
+    self.symbols.slice(0, self.symbols.len())
+  }
+
+  pub fn clear(&mut self) {
+    self.symbols.truncate(0);
+    for self.litlen_freqs.mut_iter().advance |freq| { *freq = 0; }
+    for self.dist_freqs.mut_iter().advance |freq| { *freq = 0; }
+    self.extra_bits = 0;
+  }
+
+  /// Writes the block as a stored, fixed or dynamic block, whichever is the
+  /// shortest. `raw` are the bytes that the symbols encode.
+  pub fn write(&self, raw: &[u8], last: bool, stored_only: bool,
+    out: &mut bits::BitWriter)
+  {
+    if stored_only {
+      return write_stored(raw, last, out);
+    }
+
+    let mut litlen_freqs = self.litlen_freqs.clone();
+    litlen_freqs[block_end] = 1;
+
+    let fixed = fixed_codes();
+    let fixed_cost = 3 + self.extra_bits +
+      codes_cost(litlen_freqs, fixed.litlen_lens) +
+      codes_cost(self.dist_freqs, fixed.dist_lens);
+
+    let dynamic = Codes::new(huff::build_lens(litlen_freqs, 15),
+      huff::build_lens(self.dist_freqs, 15));
+    let header = DynamicHeader::new(&dynamic);
+    let dynamic_cost = 3 + self.extra_bits + header.cost() +
+      codes_cost(litlen_freqs, dynamic.litlen_lens) +
+      codes_cost(self.dist_freqs, dynamic.dist_lens);
+
+    let stored_cost = stored_cost(raw.len());
+    if stored_cost <= fixed_cost && stored_cost <= dynamic_cost {
+      write_stored(raw, last, out);
+    } else if fixed_cost <= dynamic_cost {
+      out.write_bits(1, bit(last));
+      out.write_bits(2, 0b01);
+      self.write_symbols(&fixed, out);
+    } else {
+      out.write_bits(1, bit(last));
+      out.write_bits(2, 0b10);
+      header.write(out);
+      self.write_symbols(&dynamic, out);
+    }
+  }
+
+  fn write_symbols(&self, codes: &Codes, out: &mut bits::BitWriter) {
+    for self.symbols.iter().advance |&symbol| {
+      match symbol {
+        lz77::Literal(byte) => {
+          let sym = byte as uint;
+          out.write_code(codes.litlen_lens[sym], codes.litlen_codes[sym]);
+        },
+        lz77::Match(len, dist) => {
+          let len_idx = len_code(len);
+          let sym = 257 + len_idx;
+          out.write_code(codes.litlen_lens[sym], codes.litlen_codes[sym]);
+          out.write_bits(len_extra[len_idx], (len - len_base[len_idx]) as u32);
+
+          let dist_idx = dist_code(dist);
+          out.write_code(codes.dist_lens[dist_idx], codes.dist_codes[dist_idx]);
+          out.write_bits(dist_extra[dist_idx], (dist - dist_base[dist_idx]) as u32);
+        },
+      }
+    }
+    out.write_code(codes.litlen_lens[block_end], codes.litlen_codes[block_end]);
+  }
+}
+
+impl Codes {
+  fn new(litlen_lens: ~[u8], dist_lens: ~[u8]) -> Codes {
+    let litlen_codes = huff::build_codes(litlen_lens);
+    let dist_codes = huff::build_codes(dist_lens);
+    Codes {
+      litlen_lens: litlen_lens, litlen_codes: litlen_codes,
+      dist_lens: dist_lens, dist_codes: dist_codes,
+    }
+  }
+}
+
+struct DynamicHeader {
+  hlit: uint,
+  hdist: uint,
+  hclen: uint,
+  meta_lens: ~[u8],
+  meta_codes: ~[u16],
+  rle: ~[(u8, u8)],
+}
+
+impl DynamicHeader {
+  fn new(codes: &Codes) -> DynamicHeader {
+    let hlit = cmp::max(257, used_count(codes.litlen_lens));
+    let hdist = cmp::max(1, used_count(codes.dist_lens));
+
+    let lens = vec::append(codes.litlen_lens.slice(0, hlit).to_owned(),
+      codes.dist_lens.slice(0, hdist));
+    let rle = huff::rle_lens(lens);
+
+    let mut meta_freqs = vec::from_elem(meta_count, 0u);
+    for rle.iter().advance |&(code, _)| {
+      meta_freqs[code as uint] += 1;
+    }
+    let meta_lens = huff::build_lens(meta_freqs, 7);
+    let meta_codes = huff::build_codes(meta_lens);
+
+    let mut hclen = meta_count;
+    while hclen > 4 && meta_lens[meta_len_order[hclen - 1]] == 0 {
+      hclen = hclen - 1;
+    }
+
+    DynamicHeader {
+      hlit: hlit, hdist: hdist, hclen: hclen,
+      meta_lens: meta_lens, meta_codes: meta_codes, rle: rle,
+    }
+  }
+
+  fn cost(&self) -> uint {
+    let mut cost = 5 + 5 + 4 + 3 * self.hclen;
+    for self.rle.iter().advance |&(code, _)| {
+      cost += self.meta_lens[code as uint] as uint + meta_extra_bits(code);
+    }
+    cost
+  }
+
+  fn write(&self, out: &mut bits::BitWriter) {
+    out.write_bits(5, (self.hlit - 257) as u32);
+    out.write_bits(5, (self.hdist - 1) as u32);
+    out.write_bits(4, (self.hclen - 4) as u32);
+    for uint::range(0, self.hclen) |i| {
+      out.write_bits(3, self.meta_lens[meta_len_order[i]] as u32);
+    }
+
+    for self.rle.iter().advance |&(code, extra)| {
+      out.write_code(self.meta_lens[code as uint], self.meta_codes[code as uint]);
+      out.write_bits(meta_extra_bits(code), extra as u32);
+    }
+  }
+}
+
+fn bit(flag: bool) -> u32 {
+  if flag { 1 } else { 0 }
+}
+
+fn meta_extra_bits(code: u8) -> uint {
+  match code {
+    16 => 2,
+    17 => 3,
+    18 => 7,
+    _  => 0,
+  }
+}
+
+fn used_count(lens: &[u8]) -> uint {
+  let mut count = lens.len();
+  while count > 0 && lens[count - 1] == 0 {
+    count = count - 1;
+  }
+  count
+}
+
+fn codes_cost(freqs: &[uint], lens: &[u8]) -> uint {
+  let mut cost = 0;
+  for uint::range(0, freqs.len()) |i| {
+    cost += freqs[i] * (lens[i] as uint);
+  }
+  cost
+}
+
+fn fixed_codes() -> Codes {
+  let mut litlen_lens = vec::with_capacity(288);
+  for uint::range(0, 288) |sym| {
+    litlen_lens.push(match sym {
+      0..143   => 8u8,
+      144..255 => 9,
+      256..279 => 7,
+      _        => 8,
+    });
+  }
+  Codes::new(litlen_lens, vec::from_elem(dist_count, 5u8))
+}
+
+// every stored block costs the header, padding and LEN with NLEN
+fn stored_cost(len: uint) -> uint {
+  let blocks = cmp::max(1, (len + max_stored - 1) / max_stored);
+  blocks * (3 + 7 + 32) + 8 * len
+}
+
+fn write_stored(raw: &[u8], last: bool, out: &mut bits::BitWriter) {
+  let mut begin = 0;
+  loop {
+    let end = cmp::min(raw.len(), begin + max_stored);
+    let last_chunk = end == raw.len();
+
+    out.write_bits(1, bit(last && last_chunk));
+    out.write_bits(2, 0b00);
+    out.align();
+    out.write_u16((end - begin) as u16);
+    out.write_u16(!(end - begin) as u16);
+    out.write_bytes(raw.slice(begin, end));
+
+    if last_chunk {
+      break;
+    }
+    begin = end;
+  }
+}
+
+// the tables are short, so they are searched from the top
+pub fn len_code(len: uint) -> uint {
+  let mut idx = len_base.len() - 1;
+  while len_base[idx] > len {
+    idx = idx - 1;
+  }
+  idx
+}
+
+pub fn dist_code(dist: uint) -> uint {
+  let mut idx = dist_base.len() - 1;
+  while dist_base[idx] > dist {
+    idx = idx - 1;
+  }
+  idx
+}
+
+#[cfg(test)]
+mod test {
+  use bits;
+  use deflate::block;
+  use deflate::lz77;
+  use deflate::test_helpers::*;
+
+  #[test]
+  fn test_len_code() {
+    assert_eq!(block::len_code(3), 0);
+    assert_eq!(block::len_code(10), 7);
+    assert_eq!(block::len_code(12), 8);
+    assert_eq!(block::len_code(257), 27);
+    assert_eq!(block::len_code(258), 28);
+  }
+
+  #[test]
+  fn test_dist_code() {
+    assert_eq!(block::dist_code(1), 0);
+    assert_eq!(block::dist_code(6), 4);
+    assert_eq!(block::dist_code(24576), 28);
+    assert_eq!(block::dist_code(24577), 29);
+    assert_eq!(block::dist_code(32768), 29);
+  }
+
+  #[test]
+  fn test_write_stored() {
+    let mut block = block::Block::new();
+    let raw = bytes!("abc");
+    for raw.iter().advance |&byte| {
+      block.push(lz77::Literal(byte));
+    }
+
+    let mut out = bits::BitWriter::new();
+    block.write(raw, true, true, &mut out);
+    assert_eq!(out.send(~[]), ~[0b001, 3, 0, 0xfc, 0xff, 97, 98, 99]);
+  }
+
+  #[test]
+  fn test_write_compressed() {
+    let mut block = block::Block::new();
+    let raw = bytes!("abcabcabcabcabcabcabcabcx");
+    for [lz77::Literal(97), lz77::Literal(98), lz77::Literal(99),
+        lz77::Match(21, 3), lz77::Literal(120)].iter().advance |&symbol|
+    {
+      block.push(symbol);
+    }
+
+    let mut out = bits::BitWriter::new();
+    block.write(raw, true, false, &mut out);
+    out.align();
+    let compressed = out.send(~[]);
+    assert!(compressed.len() < raw.len());
+    assert_eq!(inflate(compressed), raw.to_owned());
+  }
+}
@@ -0,0 +1,156 @@
+use bits;
+use deflate::block;
+use deflate::level;
+use deflate::lz77;
+
+/// Streaming DEFLATE compressor (RFC 1951). The input is buffered until a
+/// block is full, so the output lags behind the input; `finish` writes out
+/// everything.
+pub struct Deflater {
+  priv params: level::Params,
+  priv matcher: ~lz77::Matcher,
+  priv block: ~block::Block,
+  priv block_start: uint,
+  priv bit_writer: bits::BitWriter,
+}
+
+impl Deflater {
+  /// Creates a deflater with compression level from 0 (no compression) to 9
+  /// (best compression), see `level::default_level`. Returns `None` if the
+  /// level is out of range.
+  pub fn new(level: uint) -> Option<Deflater> {
+    do level::Params::for_level(level).map_consume |params| {
+      Deflater {
+        params: params,
+        matcher: ~lz77::Matcher::new(),
+        block: ~block::Block::new(),
+        block_start: 0,
+        bit_writer: bits::BitWriter::new(),
+      }
+    }
+  }
+
+  pub fn input<R: bits::recv::Recv<u8>>(self, chunk: &[u8], recv: R)
+    -> (Deflater, R)
+  {
+    let mut deflater = self;
+    let mut chunk = chunk;
+
+    loop {
+      let appended = deflater.matcher.append(chunk);
+      chunk = chunk.slice(appended, chunk.len());
+      deflater.compress(false);
+
+      if chunk.is_empty() {
+        break;
+      }
+      deflater.slide();
+    }
+
+    let recv = deflater.bit_writer.send(recv);
+    (deflater, recv)
+  }
+
+  pub fn finish<R: bits::recv::Recv<u8>>(self, recv: R) -> R {
+    let mut deflater = self;
+    deflater.compress(true);
+    deflater.flush_block(true);
+    deflater.bit_writer.align();
+    deflater.bit_writer.send(recv)
+  }
+
+  fn compress(&mut self, flush: bool) {
+    loop {
+      self.matcher.compress(&self.params, flush, &mut *self.block);
+      if self.block.is_full() {
+        self.flush_block(false);
+      } else {
+        break;
+      }
+    }
+  }
+
+  fn flush_block(&mut self, last: bool) {
+    let end = self.matcher.emitted_pos();
+    self.block.write(self.matcher.slice(self.block_start, end),
+      last, self.params.stored, &mut self.bit_writer);
+    self.block.clear();
+    self.block_start = end;
+  }
+
+  // the buffer is full, so the bytes of the current block that would be
+  // discarded by the slide must be written out first
+  fn slide(&mut self) {
+    if self.block_start < lz77::window_size {
+      self.flush_block(false);
+    }
+    self.matcher.slide();
+    self.block_start = self.block_start - lz77::window_size;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use extra::test;
+  use std::{vec, uint};
+  use deflate::level;
+  use deflate::test_helpers::*;
+
+  #[test]
+  fn test_deflate_empty() {
+    for uint::range(0, level::max_level + 1) |level| {
+      assert_eq!(inflate(deflate(level, &[])), ~[]);
+    }
+    assert_eq!(deflate(0, &[]), ~[0b001, 0, 0, 0xff, 0xff]);
+  }
+
+  #[test]
+  fn test_deflate_levels() {
+    let text = text_bytes(100_000);
+    let random = random_bytes(100_000);
+    for uint::range(0, level::max_level + 1) |level| {
+      assert_eq!(inflate(deflate(level, text)), text.clone());
+      assert_eq!(inflate(deflate(level, random)), random.clone());
+    }
+  }
+
+  #[test]
+  fn test_deflate_repetitive() {
+    let bytes = vec::from_elem(200_000, 42u8);
+    for uint::range(0, level::max_level + 1) |level| {
+      assert_eq!(inflate(deflate(level, bytes)), bytes.clone());
+    }
+    assert!(deflate(6, bytes).len() < 1000);
+  }
+
+  #[test]
+  fn test_deflate_chunked() {
+    let text = text_bytes(50_000);
+    let chunk_lens = [1u, 7, 1000, 65_536];
+    for chunk_lens.iter().advance |&chunk_len| {
+      assert_eq!(deflate_chunked(6, chunk_len, text), deflate(6, text));
+      assert_eq!(inflate(deflate_chunked(1, chunk_len, text)), text.clone());
+    }
+  }
+
+  #[test]
+  fn test_deflate_ratio() {
+    let text = text_bytes(100_000);
+    let fast = deflate(1, text).len();
+    let best = deflate(9, text).len();
+    assert!(best <= fast);
+    assert!(best < text.len() / 2);
+
+    // random data should not grow much (stored blocks)
+    let random = random_bytes(100_000);
+    assert!(deflate(6, random).len() < random.len() + 100);
+  }
+
+  #[bench]
+  fn bench_deflate_text(b: &mut test::BenchHarness) {
+    let text = text_bytes(100_000);
+    do b.iter {
+      deflate(level::default_level, text);
+    }
+  }
+}
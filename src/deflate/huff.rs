@@ -0,0 +1,291 @@
+use std::{vec, uint, cmp};
+use std::iterator::{IteratorUtil};
+
+/// Computes lengths of a length-limited Huffman code for the given symbol
+/// frequencies. At least two symbols always get a code (like in zlib), so the
+/// resulting code is complete even if there is only one used symbol.
+pub fn build_lens(freqs: &[uint], max_len: uint) -> ~[u8] {
+  let mut syms: ~[(uint, uint)] = ~[];
+  for freqs.iter().enumerate().advance |(sym, &freq)| {
+    if freq > 0 {
+      syms.push((freq, sym));
+    }
+  }
+
+  let mut filler = 0;
+  while syms.len() < 2 && filler < freqs.len() {
+    if freqs[filler] == 0 {
+      syms.push((1, filler));
+    }
+    filler = filler + 1;
+  }
+
+  let mut lens = vec::from_elem(freqs.len(), 0u8);
+  if syms.len() < 2 {
+    return lens;
+  }
+
+  sort_syms(syms);
+  let depths = leaf_depths(syms);
+
+  let max_depth = do depths.iter().fold(0) |m, &d| { cmp::max(m, d) };
+  let mut counts = vec::from_elem(cmp::max(max_depth, max_len) + 1, 0u);
+  for depths.iter().advance |&depth| {
+    counts[depth] += 1;
+  }
+
+  if max_depth > max_len {
+    limit_counts(counts, max_len);
+  }
+
+  // the least frequent symbols get the longest codes
+  let mut idx = 0;
+  let mut len = cmp::min(max_depth, max_len);
+  while len >= 1 {
+    for counts[len].times {
+      let (_, sym) = syms[idx];
+      lens[sym] = len as u8;
+      idx = idx + 1;
+    }
+    len = len - 1;
+  }
+
+  lens
+}
+
+// insertion sort by (freq, sym), the arrays are small
+fn sort_syms(syms: &mut [(uint, uint)]) {
+  for uint::range(1, syms.len()) |i| {
+    let mut j = i;
+    while j > 0 && syms[j] < syms[j - 1] {
+      syms.swap(j, j - 1);
+      j = j - 1;
+    }
+  }
+}
+
+// builds the Huffman tree using two queues (the sorted leaves and the
+// internal nodes, which are created in nondecreasing order of weights) and
+// returns the depth of every leaf
+fn leaf_depths(sorted_syms: &[(uint, uint)]) -> ~[uint] {
+  let leaf_count = sorted_syms.len();
+  let mut weights: ~[uint] = vec::with_capacity(2 * leaf_count);
+  let mut parents: ~[uint] = vec::with_capacity(2 * leaf_count);
+
+  for sorted_syms.iter().advance |&(freq, _)| {
+    weights.push(freq);
+    parents.push(0);
+  }
+
+  let mut next_leaf = 0;
+  let mut next_inner = leaf_count;
+  for (leaf_count - 1).times {
+    let a = pick_lightest(weights, leaf_count, &mut next_leaf, &mut next_inner);
+    let b = pick_lightest(weights, leaf_count, &mut next_leaf, &mut next_inner);
+    let node = weights.len();
+    weights.push(weights[a] + weights[b]);
+    parents.push(0);
+    parents[a] = node;
+    parents[b] = node;
+  }
+
+  // parents are always created after their children
+  let root = weights.len() - 1;
+  let mut depths = vec::from_elem(weights.len(), 0u);
+  let mut node = root;
+  while node > 0 {
+    node = node - 1;
+    depths[node] = depths[parents[node]] + 1;
+  }
+
+  depths.truncate(leaf_count);
+  depths
+}
+
+fn pick_lightest(weights: &[uint], leaf_count: uint,
+  next_leaf: &mut uint, next_inner: &mut uint) -> uint
+{
+  let take_leaf = *next_leaf < leaf_count &&
+    (*next_inner >= weights.len() || weights[*next_leaf] <= weights[*next_inner]);
+
+  if take_leaf {
+    *next_leaf = *next_leaf + 1;
+    *next_leaf - 1
+  } else {
+    *next_inner = *next_inner + 1;
+    *next_inner - 1
+  }
+}
+
+// moves the codes longer than max_len to max_len and then makes the code
+// complete again by lengthening the shorter codes (the same as in miniz)
+fn limit_counts(counts: &mut [uint], max_len: uint) {
+  for uint::range(max_len + 1, counts.len()) |len| {
+    counts[max_len] += counts[len];
+    counts[len] = 0;
+  }
+
+  let mut total = 0;
+  for uint::range(1, max_len + 1) |len| {
+    total += counts[len] << (max_len - len);
+  }
+
+  while total > (1 << max_len) {
+    counts[max_len] -= 1;
+    let mut len = max_len - 1;
+    while len > 0 {
+      if counts[len] > 0 {
+        counts[len] -= 1;
+        counts[len + 1] += 2;
+        break;
+      }
+      len = len - 1;
+    }
+    total = total - 1;
+  }
+}
+
+/// Assigns canonical codes (RFC 1951, 3.2.2) to the code lengths. The codes
+/// are returned bit-reversed, so they can be written to the LSB-first stream.
+pub fn build_codes(lens: &[u8]) -> ~[u16] {
+  let max_len = do lens.iter().fold(0u) |m, &len| { cmp::max(m, len as uint) };
+  let mut bl_count = vec::from_elem(max_len + 1, 0u);
+  for lens.iter().advance |&len| {
+    if len > 0 {
+      bl_count[len] += 1;
+    }
+  }
+
+  let mut next_code = vec::from_elem(max_len + 1, 0u);
+  let mut code = 0;
+  for uint::range(1, max_len + 1) |bits| {
+    code = (code + bl_count[bits - 1]) << 1;
+    next_code[bits] = code;
+  }
+
+  let mut codes = vec::from_elem(lens.len(), 0u16);
+  for lens.iter().enumerate().advance |(sym, &len)| {
+    if len > 0 {
+      codes[sym] = reverse_bits(next_code[len] as u16, len as uint);
+      next_code[len] += 1;
+    }
+  }
+  codes
+}
+
+pub fn reverse_bits(code: u16, len: uint) -> u16 {
+  let mut rev = 0;
+  let mut code = code;
+  for len.times {
+    rev = (rev << 1) | (code & 1);
+    code = code >> 1;
+  }
+  rev
+}
+
+/// Encodes the code lengths using the run-length meta codes 16, 17 and 18
+/// (RFC 1951, 3.2.7). Returns pairs (meta code, extra bits value).
+pub fn rle_lens(lens: &[u8]) -> ~[(u8, u8)] {
+  let mut out = ~[];
+  let mut i = 0;
+
+  while i < lens.len() {
+    let len = lens[i];
+    let mut run = 1;
+    while i + run < lens.len() && lens[i + run] == len {
+      run = run + 1;
+    }
+    i = i + run;
+
+    if len == 0 {
+      while run >= 11 {
+        let r = cmp::min(run, 138);
+        out.push((18, (r - 11) as u8));
+        run = run - r;
+      }
+      if run >= 3 {
+        out.push((17, (run - 3) as u8));
+        run = 0;
+      }
+    } else {
+      out.push((len, 0));
+      run = run - 1;
+      while run >= 3 {
+        let r = cmp::min(run, 6);
+        out.push((16, (r - 3) as u8));
+        run = run - r;
+      }
+    }
+
+    for run.times {
+      out.push((len, 0));
+    }
+  }
+
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use deflate::huff;
+  use std::uint;
+
+  fn kraft_sum(lens: &[u8], max_len: uint) -> uint {
+    do lens.iter().fold(0) |sum, &len| {
+      if len > 0 { sum + (1 << (max_len - len as uint)) } else { sum }
+    }
+  }
+
+  #[test]
+  fn test_build_lens() {
+    assert_eq!(huff::build_lens(&[10, 1, 1, 5], 15), ~[1, 3, 3, 2]);
+    assert_eq!(huff::build_lens(&[0, 7, 0, 7, 7, 7], 15), ~[0, 2, 0, 2, 2, 2]);
+  }
+
+  #[test]
+  fn test_build_lens_few_symbols() {
+    assert_eq!(huff::build_lens(&[0, 0, 5, 0], 15), ~[1, 0, 1, 0]);
+    assert_eq!(huff::build_lens(&[0, 0, 0], 15), ~[1, 1, 0]);
+  }
+
+  #[test]
+  fn test_build_lens_limited() {
+    // fibonacci frequencies give the most skewed tree
+    let mut freqs = ~[1u, 1];
+    for 20.times {
+      let len = freqs.len();
+      freqs.push(freqs[len - 1] + freqs[len - 2]);
+    }
+
+    let unlimited = huff::build_lens(freqs, 30);
+    assert_eq!(unlimited[0], 21);
+
+    for uint::range(5, 16) |max_len| {
+      let lens = huff::build_lens(freqs, max_len);
+      assert!(lens.iter().all(|&len| len >= 1 && len as uint <= max_len));
+      assert_eq!(kraft_sum(lens, max_len), 1 << max_len);
+    }
+  }
+
+  #[test]
+  fn test_build_codes() {
+    // example from RFC 1951
+    assert_eq!(huff::build_codes(&[3, 3, 3, 3, 3, 2, 4, 4]), ~[
+        0b010, 0b110, 0b001, 0b101, 0b011, 0b00, 0b0111, 0b1111
+      ]);
+  }
+
+  #[test]
+  fn test_rle_lens() {
+    assert_eq!(huff::rle_lens(&[3, 3, 3, 3, 3, 3, 3, 3, 0, 0, 5]),
+      ~[(3, 0), (16, 3), (3, 0), (0, 0), (0, 0), (5, 0)]);
+
+    let mut lens = ~[7u8];
+    for 150.times { lens.push(0); }
+    lens.push(2);
+    assert_eq!(huff::rle_lens(lens), ~[(7, 0), (18, 127), (18, 1), (2, 0)]);
+
+    assert_eq!(huff::rle_lens(&[4, 4, 4, 4, 0, 0, 0]),
+      ~[(4, 0), (16, 0), (17, 0)]);
+  }
+}
@@ -0,0 +1,65 @@
+pub static default_level: uint = 6;
+pub static max_level: uint = 9;
+
+pub struct Params {
+  stored: bool,
+  lazy: bool,
+  good_len: uint,
+  max_lazy: uint,
+  nice_len: uint,
+  max_chain: uint,
+}
+
+// the same trade-offs as in zlib:
+// (lazy, good_len, max_lazy, nice_len, max_chain)
+static level_table: [(bool, uint, uint, uint, uint), ..9] = [
+  (false,  4,   4,   8,    4),
+  (false,  4,   5,  16,    8),
+  (false,  4,   6,  32,   32),
+  (true,   4,   4,  16,   16),
+  (true,   8,  16,  32,   32),
+  (true,   8,  16, 128,  128),
+  (true,   8,  32, 128,  256),
+  (true,  32, 128, 258, 1024),
+  (true,  32, 258, 258, 4096),
+];
+
+impl Params {
+  /// Returns the parameters for `level`, or `None` if it is greater than
+  /// `max_level`.
+  pub fn for_level(level: uint) -> Option<Params> {
+    if level > max_level {
+      None
+    } else if level == 0 {
+      Some(Params {
+        stored: true, lazy: false,
+        good_len: 0, max_lazy: 0, nice_len: 0, max_chain: 0,
+      })
+    } else {
+      let (lazy, good_len, max_lazy, nice_len, max_chain) = level_table[level - 1];
+      Some(Params {
+        stored: false, lazy: lazy,
+        good_len: good_len, max_lazy: max_lazy,
+        nice_len: nice_len, max_chain: max_chain,
+      })
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use deflate::level;
+
+  #[test]
+  fn test_params_for_level() {
+    assert!(level::Params::for_level(0).unwrap().stored);
+    assert!(!level::Params::for_level(1).unwrap().lazy);
+    assert!(level::Params::for_level(6).unwrap().lazy);
+    assert_eq!(level::Params::for_level(9).unwrap().max_chain, 4096);
+  }
+
+  #[test]
+  fn test_params_bad_level() {
+    assert!(level::Params::for_level(10).is_none());
+  }
+}
@@ -0,0 +1,329 @@
+use std::{vec, uint, cmp};
+use deflate::block;
+use deflate::level;
+
+pub static window_size: uint = 32_768;
+pub static buf_size: uint = 2 * window_size;
+pub static min_match: uint = 3;
+pub static max_match: uint = 258;
+pub static min_lookahead: uint = max_match + min_match + 1;
+pub static max_dist: uint = window_size - min_lookahead;
+
+static hash_bits: uint = 15;
+static hash_size: uint = 1 << hash_bits;
+// matches of length 3 that are farther away are not worth it (as in zlib)
+static too_far: uint = 4096;
+
+#[deriving(Eq, Clone)]
+pub enum Symbol {
+  Literal(u8),
+  Match(uint, uint), /* (len, dist) */
+}
+
+/// Finds LZ77 matches in a sliding buffer of `buf_size` bytes. The positions
+/// in `head` and `prev` are stored incremented by one, so that zero means "no
+/// position".
+pub struct Matcher {
+  priv buf: ~[u8],
+  priv pos: uint,
+  priv end: uint,
+  priv head: ~[uint],
+  priv prev: ~[uint],
+  priv match_available: bool,
+  priv prev_len: uint,
+  priv prev_dist: uint,
+}
+
+impl Matcher {
+  pub fn new() -> Matcher {
+    Matcher {
+      buf: vec::from_elem(buf_size, 0u8),
+      pos: 0,
+      end: 0,
+      head: vec::from_elem(hash_size, 0u),
+      prev: vec::from_elem(window_size, 0u),
+      match_available: false,
+      prev_len: 0,
+      prev_dist: 0,
+    }
+  }
+
+  /// Appends as many bytes from `bytes` as fit into the buffer and returns
+  /// their count.
+  pub fn append(&mut self, bytes: &[u8]) -> uint {
+    let count = cmp::min(bytes.len(), self.free_space());
+    vec::bytes::copy_memory(self.buf.mut_slice(self.end, self.end + count),
+      bytes, count);
+    self.end = self.end + count;
+    count
+  }
+
+  pub fn free_space(&self) -> uint {
+    buf_size - self.end
+  }
+
+  pub fn is_full(&self) -> bool {
+    self.end == buf_size
+  }
+
+  pub fn slice<'a>(&'a self, begin: uint, end: uint) -> &'a [u8] {
+    self.buf.slice(begin, end)
+  }
+
+  /// Position of the first byte that was not yet emitted as a symbol (one
+  /// byte may be held back during lazy matching).
+  pub fn emitted_pos(&self) -> uint {
+    if self.match_available { self.pos - 1 } else { self.pos }
+  }
+
+  /// Moves the upper half of the buffer to the lower half. All positions are
+  /// decreased by `window_size`.
+  pub fn slide(&mut self) {
+    if self.pos < window_size {
+      fail!(fmt!("Matcher::slide: position %u is in the lower half", self.pos));
+    }
+
+    for uint::range(0, self.end - window_size) |i| {
+      self.buf[i] = self.buf[i + window_size];
+    }
+    self.pos = self.pos - window_size;
+    self.end = self.end - window_size;
+
+    fn rebase(links: &mut [uint]) {
+      for uint::range(0, links.len()) |i| {
+        links[i] = if links[i] > window_size { links[i] - window_size } else { 0 };
+      }
+    }
+    rebase(self.head);
+    rebase(self.prev);
+  }
+
+  /// Emits the symbols for the buffered bytes into `block`. Unless `flush` is
+  /// set, at least `min_lookahead` bytes are kept unprocessed, so that the
+  /// matches are not cut short. Stops early if the block gets full.
+  pub fn compress(&mut self, params: &level::Params, flush: bool,
+    block: &mut block::Block)
+  {
+    if params.stored {
+      while self.pos < self.end && !block.is_full() {
+        block.push(Literal(self.buf[self.pos]));
+        self.pos = self.pos + 1;
+      }
+    } else if params.lazy {
+      self.compress_lazy(params, flush, block);
+    } else {
+      self.compress_greedy(params, flush, block);
+    }
+  }
+
+  fn compress_greedy(&mut self, params: &level::Params, flush: bool,
+    block: &mut block::Block)
+  {
+    while !block.is_full() {
+      let avail = self.end - self.pos;
+      if avail == 0 || (avail < min_lookahead && !flush) {
+        break;
+      }
+
+      let (len, dist) = self.find_match(params, 0);
+      if len >= min_match {
+        block.push(Match(len, dist));
+        if len <= params.max_lazy {
+          self.insert_range(self.pos + 1, self.pos + len);
+        }
+        self.pos = self.pos + len;
+      } else {
+        block.push(Literal(self.buf[self.pos]));
+        self.pos = self.pos + 1;
+      }
+    }
+  }
+
+  // the match found at `pos - 1` is emitted only if there is no longer match
+  // at `pos`, otherwise the byte at `pos - 1` becomes a literal
+  fn compress_lazy(&mut self, params: &level::Params, flush: bool,
+    block: &mut block::Block)
+  {
+    while !block.is_full() {
+      let avail = self.end - self.pos;
+      if avail < min_lookahead && !flush {
+        break;
+      } else if avail == 0 {
+        if self.match_available {
+          if self.prev_len >= min_match {
+            block.push(Match(self.prev_len, self.prev_dist));
+          } else {
+            block.push(Literal(self.buf[self.pos - 1]));
+          }
+          self.match_available = false;
+          self.prev_len = 0;
+        }
+        break;
+      }
+
+      let (len, dist) = if self.prev_len < params.max_lazy {
+          self.find_match(params, self.prev_len)
+        } else {
+          self.insert_range(self.pos, self.pos + 1);
+          (0, 0)
+        };
+
+      if self.prev_len >= min_match && len <= self.prev_len {
+        let match_end = self.pos - 1 + self.prev_len;
+        block.push(Match(self.prev_len, self.prev_dist));
+        self.insert_range(self.pos + 1, match_end);
+        self.pos = match_end;
+        self.match_available = false;
+        self.prev_len = 0;
+      } else {
+        if self.match_available {
+          block.push(Literal(self.buf[self.pos - 1]));
+        }
+        self.match_available = true;
+        self.prev_len = len;
+        self.prev_dist = dist;
+        self.pos = self.pos + 1;
+      }
+    }
+  }
+
+  // inserts the positions from `begin` to `end` into the hash chains
+  fn insert_range(&mut self, begin: uint, end: uint) {
+    for uint::range(begin, end) |pos| {
+      if pos + min_match <= self.end {
+        self.insert(pos);
+      }
+    }
+  }
+
+  #[inline]
+  fn insert(&mut self, pos: uint) {
+    let hash = self.hash(pos);
+    self.prev[pos & (window_size - 1)] = self.head[hash];
+    self.head[hash] = pos + 1;
+  }
+
+  #[inline]
+  fn hash(&self, pos: uint) -> uint {
+    let (a, b, c) = (self.buf[pos] as uint, self.buf[pos + 1] as uint,
+      self.buf[pos + 2] as uint);
+    ((a << 10) ^ (b << 5) ^ c) & (hash_size - 1)
+  }
+
+  // inserts the current position and returns the longest match (len, dist)
+  // that is longer than `prev_len`, or (0, 0) if there is none
+  fn find_match(&mut self, params: &level::Params, prev_len: uint)
+    -> (uint, uint)
+  {
+    let max_len = cmp::min(self.end - self.pos, max_match);
+    if max_len < min_match {
+      return (0, 0);
+    }
+
+    self.insert(self.pos);
+    let mut chain = if prev_len >= params.good_len {
+        params.max_chain >> 2
+      } else {
+        params.max_chain
+      };
+
+    let pos = self.pos;
+    let mut best_len = cmp::max(prev_len, min_match - 1);
+    let mut best_dist = 0;
+    let mut link = self.prev[pos & (window_size - 1)];
+
+    while link > 0 && chain > 0 && best_len < max_len {
+      let cand = link - 1;
+      if cand >= pos || pos - cand > max_dist {
+        break;
+      }
+
+      if self.buf[cand + best_len] == self.buf[pos + best_len] {
+        let mut len = 0;
+        while len < max_len && self.buf[cand + len] == self.buf[pos + len] {
+          len = len + 1;
+        }
+
+        if len > best_len {
+          best_len = len;
+          best_dist = pos - cand;
+          if len >= params.nice_len {
+            break;
+          }
+        }
+      }
+
+      let next = self.prev[cand & (window_size - 1)];
+      if next >= link {
+        break;
+      }
+      link = next;
+      chain = chain - 1;
+    }
+
+    if best_dist == 0 || (best_len == min_match && best_dist > too_far) {
+      (0, 0)
+    } else {
+      (best_len, best_dist)
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use deflate::lz77::*;
+  use deflate::block;
+  use deflate::level;
+  use std::vec;
+
+  fn compress_all(bytes: &[u8], level: uint) -> ~[Symbol] {
+    let params = level::Params::for_level(level).unwrap();
+    let mut matcher = Matcher::new();
+    let mut block = block::Block::new();
+    assert_eq!(matcher.append(bytes), bytes.len());
+    matcher.compress(&params, true, &mut block);
+    block.symbols().to_owned()
+  }
+
+  #[test]
+  fn test_compress_repeated() {
+    assert_eq!(compress_all(bytes!("abcabcabcabc"), 6),
+      ~[Literal(97), Literal(98), Literal(99), Match(9, 3)]);
+    assert_eq!(compress_all(bytes!("aaaaaaaa"), 1),
+      ~[Literal(97), Match(7, 1)]);
+  }
+
+  #[test]
+  fn test_compress_lazy() {
+    // the lazy matcher drops "abc" in favor of the longer "bcde"
+    assert_eq!(compress_all(bytes!("abc-bcde-abcde"), 6), ~[
+        Literal(97), Literal(98), Literal(99), Literal(45),
+        Literal(98), Literal(99), Literal(100), Literal(101), Literal(45),
+        Literal(97), Match(4, 6),
+      ]);
+  }
+
+  #[test]
+  fn test_compress_stored() {
+    assert_eq!(compress_all(bytes!("aaaa"), 0),
+      ~[Literal(97), Literal(97), Literal(97), Literal(97)]);
+  }
+
+  #[test]
+  fn test_append_slide() {
+    let mut matcher = Matcher::new();
+    let bytes = vec::from_elem(buf_size + 10, 7u8);
+    assert_eq!(matcher.append(bytes), buf_size);
+    assert!(matcher.is_full());
+
+    let params = level::Params::for_level(6).unwrap();
+    let mut block = block::Block::new();
+    while matcher.emitted_pos() < window_size {
+      matcher.compress(&params, false, &mut block);
+      block.clear();
+    }
+    matcher.slide();
+    assert_eq!(matcher.free_space(), window_size);
+    assert_eq!(matcher.slice(0, 3), &[7u8, 7, 7]);
+  }
+}
@@ -0,0 +1,50 @@
+#[cfg(test)];
+use std::rand;
+use std::rand::{RngUtil};
+use deflate::deflater;
+use inflate;
+
+pub fn deflate(level: uint, bytes: &[u8]) -> ~[u8] {
+  let deflater = deflater::Deflater::new(level).unwrap();
+  let (deflater, out) = deflater.input(bytes, ~[]);
+  deflater.finish(out)
+}
+
+pub fn deflate_chunked(level: uint, chunk_len: uint, bytes: &[u8]) -> ~[u8] {
+  let mut deflater = deflater::Deflater::new(level).unwrap();
+  let mut out: ~[u8] = ~[];
+
+  for bytes.chunk_iter(chunk_len).advance |chunk| {
+    let (new_deflater, new_out) = deflater.input(chunk, out);
+    deflater = new_deflater;
+    out = new_out;
+  }
+  deflater.finish(out)
+}
+
+pub fn inflate(bytes: &[u8]) -> ~[u8] {
+  let inflater = inflate::Inflater::new();
+  match inflater.input(bytes, ~[]) {
+    (Right((Ok(()), [])), inflated) => inflated,
+    other => fail!(fmt!("inflate: unexpected Res %?", other)),
+  }
+}
+
+pub fn random_bytes(len: uint) -> ~[u8] {
+  rand::IsaacRng::new_seeded(&[42]).gen_bytes(len)
+}
+
+// pseudo-random text from a small vocabulary, compresses well
+pub fn text_bytes(len: uint) -> ~[u8] {
+  let words = ["lorem ", "ipsum ", "dolor ", "sit ", "amet, ", "deflate ",
+    "inflate ", "window ", "huffman ", "code ", "block.\n"];
+  let mut rng = rand::IsaacRng::new_seeded(&[42]);
+  let mut bytes = ~[];
+
+  while bytes.len() < len {
+    let word = words[rng.gen_uint_range(0, words.len())];
+    bytes.push_all(word.as_bytes());
+  }
+  bytes.truncate(len);
+  bytes
+}
@@ -1,101 +1,272 @@
+use checksums::checksum;
 use checksums::crc32;
 use bits;
 use bits::recv;
+use bits::status;
 use gzip::error;
+use inflate;
 use inflate::inflater;
+use std::{uint, util};
 
-pub struct BodyDecoder {
-  priv stage: Stage,
+mod check;
+
+/// Decodes the compressed data and the trailer of a gzip member. The CRC32 of
+/// the data is verified by a `checksum::Checksum`, which is `crc32::Crc32`
+/// unless replaced by `with_checksum`.
+pub struct BodyDecoder<C> {
+  priv stage: Stage<C>,
   priv byte_buf: bits::ByteBuf,
 }
 
-enum Stage {
-  DataStage(inflater::Inflater, crc32::Crc32, u32),
-  Crc32Stage(u32, u32),
+enum Stage<C> {
+  DataStage(inflater::Inflater, C, u32),
+  Crc32Stage(C, u32),
   ISizeStage(u32),
   ErrorStage(~error::Error),
   EndStage(),
 }
 
-impl BodyDecoder {
-  pub fn new() -> BodyDecoder {
+impl BodyDecoder<crc32::Crc32> {
+  pub fn new() -> BodyDecoder<crc32::Crc32> {
     BodyDecoder { 
       stage: DataStage(inflater::Inflater::new(), crc32::Crc32::new(), 0),
       byte_buf: bits::ByteBuf::new(),
     }
   }
+}
+
+impl<C: checksum::Checksum> BodyDecoder<C> {
+  /// Sets the limits on the decompressed data. Must be called before any
+  /// input.
+  pub fn with_limits(self, limits: inflate::limits::Limits) -> BodyDecoder<C> {
+    match self {
+      BodyDecoder { stage: DataStage(inflater, crc, isize), byte_buf } =>
+        BodyDecoder {
+          stage: DataStage(inflater.with_limits(limits), crc, isize),
+          byte_buf: byte_buf,
+        },
+      _ => fail!("BodyDecoder::with_limits: the decoding has already finished"),
+    }
+  }
+
+  /// Replaces the checksum that verifies the data, for example with
+  /// `checksum::NoCheck` for trusted data. Must be called before the data
+  /// starts, fails otherwise.
+  pub fn with_checksum<D: checksum::Checksum>(self, checksum: D)
+    -> BodyDecoder<D>
+  {
+    match self {
+      BodyDecoder { stage: DataStage(inflater, _, 0), byte_buf } =>
+        BodyDecoder {
+          stage: DataStage(inflater, checksum.reset(), 0),
+          byte_buf: byte_buf,
+        },
+      _ => fail!("BodyDecoder::with_checksum: the data has already started"),
+    }
+  }
+
+  /// Decodes a chunk of input and sends the data to `recv`, see
+  /// `inflater::Inflater::feed`.
+  pub fn feed<'a, R: recv::Recv<u8>>(&mut self, chunk: &'a [u8], recv: R)
+    -> (status::Status<'a, (), ~error::Error>, R)
+  {
+    let (res, recv, _) = self.decode_chunk(chunk, uint::max_value,
+      recv::Infallible(recv));
+    let recv = match recv { Ok(recv) | Err(recv) => recv.unwrap() };
+    match res {
+      Left(_) => (status::NeedInput, recv),
+      Right((Ok(()), rest)) => (status::Finished((), rest), recv),
+      Right((Err(err), rest)) => (status::Error(err, rest), recv),
+    }
+  }
 
   pub fn input<'a, R: recv::Recv<u8>>(self, chunk: &'a [u8], recv: R)
-    -> (Either<BodyDecoder, (Result<(), ~error::Error>, &'a [u8])>, R)
+    -> (Either<BodyDecoder<C>, (Result<(), ~error::Error>, &'a [u8])>, R)
   {
-    let BodyDecoder { stage, byte_buf } = self;
-    let mut stage = stage;
+    let mut decoder = self;
+    let (status, recv) = decoder.feed(chunk, recv);
+    match status {
+      status::NeedInput => (Left(decoder), recv),
+      status::Finished((), rest) => (Right((Ok(()), rest)), recv),
+      status::Error(err, rest) => (Right((Err(err), rest)), recv),
+    }
+  }
+
+  /// Decodes into `buf` and stops when it is full, see
+  /// `inflater::Inflater::input_into`.
+  pub fn input_into(self, chunk: &[u8], buf: &mut [u8])
+    -> (Either<BodyDecoder<C>, Result<(), ~error::Error>>, uint, uint)
+  {
+    let avail = buf.len();
+    match self.input_limited(chunk, avail, recv::SliceRecv::new(buf)) {
+      (Left((decoder, rest)), _, written) =>
+        (Left(decoder), chunk.len() - rest.len(), written),
+      (Right((res, rest)), _, written) =>
+        (Right(res), chunk.len() - rest.len(), written),
+    }
+  }
+
+  /// Like `input`, but sends at most `avail` bytes to `recv`, see
+  /// `inflater::Inflater::input_limited`.
+  pub fn input_limited<'a, R: recv::Recv<u8>>
+    (self, chunk: &'a [u8], avail: uint, recv: R)
+    -> (Either<(BodyDecoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      R, uint)
+  {
+    match self.try_input_limited(chunk, avail, recv::Infallible(recv)) {
+      (res, Ok(recv), written) | (res, Err(recv), written) =>
+        (res, recv.unwrap(), written),
+    }
+  }
+
+  /// Like `input`, but the receiver may fail, see
+  /// `inflater::Inflater::try_input`.
+  pub fn try_input<'a, R: recv::TryRecv<u8>>(self, chunk: &'a [u8], recv: R)
+    -> (Either<(BodyDecoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>)
+  {
+    match self.try_input_limited(chunk, uint::max_value, recv) {
+      (res, recv, _) => (res, recv),
+    }
+  }
+
+  /// Combines `try_input` and `input_limited`, see
+  /// `inflater::Inflater::try_input_limited`.
+  pub fn try_input_limited<'a, R: recv::TryRecv<u8>>
+    (self, chunk: &'a [u8], avail: uint, recv: R)
+    -> (Either<(BodyDecoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, uint)
+  {
+    let mut decoder = self;
+    let (res, recv, written) = decoder.decode_chunk(chunk, avail, recv);
+    match res {
+      Left(rest) => (Left((decoder, rest)), recv, written),
+      Right(res) => (Right(res), recv, written),
+    }
+  }
+
+  // returns either the unconsumed input when the decoder stopped, or the
+  // result when the member ended
+  priv fn decode_chunk<'a, R: recv::TryRecv<u8>>
+    (&mut self, chunk: &'a [u8], avail: uint, recv: R)
+    -> (Either<&'a [u8], (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, uint)
+  {
+    let mut stage = util::replace(&mut self.stage, EndStage);
+    let byte_buf = util::replace(&mut self.byte_buf, bits::ByteBuf::new());
     let mut recv = recv;
+    let mut recv_failed = false;
+    let mut avail_left = avail;
     let mut byte_reader = bits::ByteReader::new(byte_buf, chunk);
 
     loop {
       let (continue, new_stage) = match stage {
         DataStage(inflater, crc, isize) => {
-          let (new_recv, continue, new_stage) = BodyDecoder::data_stage(
-            inflater, crc, isize, recv, &mut byte_reader);
-          recv = new_recv;
-          (continue, new_stage)
+          let mut inflater = inflater;
+          let (recv_res, new_avail, continue, next) =
+            BodyDecoder::data_stage(&mut inflater, crc, isize, avail_left,
+              recv, &mut byte_reader);
+          recv = match recv_res {
+              Ok(new_recv) => new_recv,
+              Err(new_recv) => { recv_failed = true; new_recv },
+            };
+          avail_left = new_avail;
+          (continue, match next {
+              Left((crc, isize)) => DataStage(inflater, crc, isize),
+              Right(stage) => stage,
+            })
         },
         Crc32Stage(computed_crc, isize) =>
           BodyDecoder::crc32_stage(computed_crc, isize, &mut byte_reader),
         ISizeStage(isize) => 
           BodyDecoder::isize_stage(isize, &mut byte_reader),
-        ErrorStage(err) => 
-          return (Right((Err(err), byte_reader.close_to_rest())), recv),
-        EndStage => 
-          return (Right((Ok(()), byte_reader.close_to_rest())), recv),
+        ErrorStage(err) => {
+          let (_, rest) = byte_reader.close();
+          self.stage = ErrorStage(err.clone());
+          return (Right((Err(err), rest)), Ok(recv), avail - avail_left)
+        },
+        EndStage => {
+          let res = match byte_reader.close_to_rest() {
+              Ok(rest) => (Ok(()), rest),
+              Err(err) => {
+                let err = ~error::InternalError(err);
+                self.stage = ErrorStage(err.clone());
+                (Err(err), chunk.slice(chunk.len(), chunk.len()))
+              },
+            };
+          return (Right(res), Ok(recv), avail - avail_left)
+        },
       };
 
       if !continue {
-        let byte_buf = byte_reader.close_to_buf();
-        let decoder = BodyDecoder { stage: new_stage, byte_buf: byte_buf };
-        return (Left(decoder), recv)
+        // when the output is full or the receiver failed, the rest of the
+        // input is left to the caller
+        let (byte_buf, rest) = if avail_left == 0 || recv_failed {
+            byte_reader.close()
+          } else {
+            (byte_reader.close_to_buf(), chunk.slice(chunk.len(), chunk.len()))
+          };
+        self.stage = new_stage;
+        self.byte_buf = byte_buf;
+        let recv = if recv_failed { Err(recv) } else { Ok(recv) };
+        return (Left(rest), recv, avail - avail_left)
       } else {
         stage = new_stage;
       }
     }
   }
 
-  fn data_stage<R: recv::Recv<u8>>(
-    inflater: inflater::Inflater, crc: crc32::Crc32, isize: u32,
+  // the checksum and the size count only the data that the receiver accepted;
+  // returns them while the data continue, or the next stage
+  fn data_stage<R: recv::TryRecv<u8>>(
+    inflater: &mut inflater::Inflater, crc: C, isize: u32, avail: uint,
     recv: R, byte_reader: &mut bits::ByteReader)
-    -> (R, bool, Stage)
+    -> (Result<R, R>, uint, bool, Either<(C, u32), Stage<C>>)
   {
     if byte_reader.has_some_bytes() {
-      do byte_reader.consume_chunk((inflater, crc, isize, recv))
-        |(inflater, crc, isize, recv), chunk|
+      let ((n_recv, n_avail, continue, next), consumed) =
+        do byte_reader.consume_chunk((inflater, crc, isize, recv))
+          |(inflater, crc, isize, recv), chunk|
       {
-        let (res, (n_recv, n_crc, n_isize)) =
-          inflater.input(chunk, (recv, crc, isize));
+        let (res, recv_res, written) = inflater.try_feed_limited(chunk, avail,
+          (recv, (crc, isize)));
+        let (n_recv, (n_crc, n_isize)) = match recv_res {
+            Ok((recv, checks)) => (Ok(recv), checks),
+            Err((recv, checks)) => (Err(recv), checks),
+          };
+        let n_avail = avail - written;
 
         match res {
-          Left(n_inflater) =>
-            ((n_recv, false, DataStage(n_inflater, n_crc, n_isize)), None),
+          Left(rest) =>
+            ((n_recv, n_avail, false, Left((n_crc, n_isize))), Some(rest)),
           Right((Ok(()), rest)) =>
-            ((n_recv, true, Crc32Stage(n_crc.crc32(), n_isize)), Some(rest)),
+            ((n_recv, n_avail, true,
+              Right(Crc32Stage(n_crc, n_isize))), Some(rest)),
           Right((Err(err), rest)) =>
-            ((n_recv, true, ErrorStage(~error::InflateError(err))), Some(rest)),
+            ((n_recv, n_avail, true,
+              Right(ErrorStage(~error::InflateError(err)))), Some(rest)),
         }
+      };
+      match consumed {
+        Ok(()) => (n_recv, n_avail, continue, next),
+        Err(err) => (n_recv, n_avail, true,
+          Right(ErrorStage(~error::InternalError(err)))),
       }
     } else {
-      (recv, false, DataStage(inflater, crc, isize))
+      (Ok(recv), avail, false, Left((crc, isize)))
     }
   }
 
-  fn crc32_stage(computed_crc: u32, isize: u32,
-    byte_reader: &mut bits::ByteReader) -> (bool, Stage) 
+  fn crc32_stage(computed_crc: C, isize: u32,
+    byte_reader: &mut bits::ByteReader) -> (bool, Stage<C>) 
   {
     if byte_reader.has_bytes(4) {
-      let read_crc = byte_reader.read_u32_le();
-      if read_crc == computed_crc {
+      let read_crc = try_read!(byte_reader.read_u32_le());
+      if computed_crc.verify(read_crc as u64) {
         (true, ISizeStage(isize))
       } else {
-        (true, ErrorStage(~error::BadDataChecksum(computed_crc, read_crc)))
+        (true, ErrorStage(~error::BadDataChecksum(
+          computed_crc.value(), read_crc as u64)))
       }
     } else {
       (false, Crc32Stage(computed_crc, isize))
@@ -103,10 +274,10 @@ impl BodyDecoder {
   }
 
   fn isize_stage(isize: u32, byte_reader: &mut bits::ByteReader) 
-    -> (bool, Stage) 
+    -> (bool, Stage<C>) 
   {
     if byte_reader.has_bytes(4) {
-      let read_isize = byte_reader.read_u32_le();
+      let read_isize = try_read!(byte_reader.read_u32_le());
       if read_isize == isize {
         (true, EndStage)
       } else {
@@ -122,8 +293,11 @@ impl BodyDecoder {
 #[cfg(test)]
 mod test {
   use gzip::test_helpers::*;
+  use gzip::body_decoder;
   use gzip::error;
-  use std::uint;
+  use checksums::checksum;
+  use bits::status;
+  use std::{vec, uint};
 
   #[test]
   fn test_decode_body_ok() {
@@ -162,6 +336,82 @@ mod test {
     }
   }
 
+  #[test]
+  fn test_decode_body_feed() {
+    let bytes = [
+        0x63, 0x64, 0x64, 0x62, 0x66, 0xe5,
+        0xe0, 0x15, 0x55, 0x32, 0x07, 0x00,
+        0xf0, 0x8a, 0xcb, 0xff,
+        0x0a, 0x00, 0x00, 0x00,
+        42,
+      ];
+    let mut decoder = body_decoder::BodyDecoder::new();
+    let mut out = ~[];
+    let mut pos = 0;
+    while pos < 20 {
+      let (status, new_out) = decoder.feed(bytes.slice(pos, pos + 1), out);
+      out = new_out;
+      match status {
+        status::NeedInput if pos < 19 => { },
+        status::Finished((), []) if pos == 19 => { },
+        other => fail!(fmt!("unexpected %? at %u", other, pos)),
+      }
+      pos = pos + 1;
+    }
+    assert_eq!(out, ~[1, 1, 2, 3, 5, 8, 13, 21, 34, 55]);
+
+    match decoder.feed(bytes.slice(20, 21), ()) {
+      (status::Finished((), [42]), ()) => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_try_input() {
+    let data = vec::from_fn(100_000, |i| (i % 251) as u8);
+    let bytes = encode_blank(data);
+
+    let mut decoder = body_decoder::BodyDecoder::new();
+    let mut recv = FlakyRecv::new();
+    // skip the blank header
+    let mut rest = bytes.slice(10, bytes.len());
+    let mut failures = 0u;
+    loop {
+      match decoder.try_input(rest, recv) {
+        (Left((new_decoder, new_rest)), Err(new_recv)) => {
+          decoder = new_decoder;
+          rest = new_rest;
+          recv = new_recv;
+          failures = failures + 1;
+        },
+        (Right((Ok(()), [])), Ok(new_recv)) => {
+          // the CRC32 and the ISIZE were verified, so no data were lost or
+          // repeated
+          assert_eq!(new_recv.buf, data);
+          break;
+        },
+        other => fail!(fmt!("unexpected %?", other)),
+      }
+    }
+    assert!(failures > 0);
+  }
+
+  #[test]
+  fn test_decode_body_no_check() {
+    let decoder = body_decoder::BodyDecoder::new()
+      .with_checksum(checksum::NoCheck);
+    match decoder.input(&[
+        0x63, 0x64, 0x64, 0x62, 0x66, 0xe5,
+        0xe0, 0x15, 0x55, 0x32, 0x07, 0x00,
+        0xef, 0xbe, 0xad, 0xde,
+        0x0a, 0x00, 0x00, 0x00,
+      ], ~[]) {
+      (Right((Ok(()), [])), buf) =>
+        assert_eq!(buf, ~[1, 1, 2, 3, 5, 8, 13, 21, 34, 55]),
+      x => fail!(fmt!("unexpected %?", x)),
+    }
+  }
+
   #[test]
   fn test_decode_body_chunked() {
     for uint::range(1, 10) |chunk_len| {
@@ -174,4 +424,22 @@ mod test {
         ~[1, 1, 2, 3, 5, 8, 13, 21, 34, 55]);
     }
   }
+
+  #[test]
+  fn test_decode_body_into() {
+    let bytes = [
+        0x63u8, 0x64, 0x64, 0x62, 0x66, 0xe5,
+        0xe0, 0x15, 0x55, 0x32, 0x07, 0x00,
+        0xf0, 0x8a, 0xcb, 0xff,
+        0x0a, 0x00, 0x00, 0x00,
+      ];
+
+    let buf_lens = [1u, 3, 10];
+    for uint::range(1, 10) |chunk_len| {
+      for buf_lens.iter().advance |&buf_len| {
+        assert_eq!(decode_body_into_ok(chunk_len, buf_len, bytes),
+          ~[1, 1, 2, 3, 5, 8, 13, 21, 34, 55]);
+      }
+    }
+  }
 }
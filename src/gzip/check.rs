@@ -0,0 +1,11 @@
+#[macro_escape];
+
+// Unwraps the result of a read from a `ByteReader`, an error ends the stage.
+macro_rules! try_read(
+  ($res:expr) => (
+    match $res {
+      Ok(x) => x,
+      Err(err) => return (true, ErrorStage(~error::InternalError(err))),
+    }
+  )
+)
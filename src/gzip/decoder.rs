@@ -0,0 +1,655 @@
+use bits;
+use bits::recv;
+use bits::status;
+use checksums::checksum;
+use checksums::crc32;
+use gzip::body_decoder;
+use gzip::error;
+use gzip::hdr_decoder;
+use gzip::header;
+use gzip::limits;
+use inflate;
+use std::{uint, util};
+
+/// Decodes a whole gzip file, including multiple concatenated members (as
+/// `gzip -d` does). The data of all members is sent to one receiver, the
+/// headers are sent to another one, each with the input offset where its
+/// member starts.
+///
+/// After a member ends, anything that does not start with both bytes of the
+/// gzip magic number is trailing garbage; the decoder then finishes
+/// successfully and returns the garbage as the rest (without its first byte
+/// if that was `0x1f` at the end of a chunk). Because the stream may always
+/// continue with another member, the decoder finishes only on error or
+/// garbage; call `finish` once the input is exhausted.
+///
+/// The output limit applies to all members together, the ratio limit to each
+/// member separately.
+///
+/// The data of each member is verified by a copy of the `checksum::Checksum`,
+/// `crc32::Crc32` unless replaced by `with_checksum`.
+pub struct Decoder<C> {
+  priv stage: Stage<C>,
+  priv offset: u64,
+  priv limits: limits::Limits,
+  priv total_out: u64,
+  priv checksum: C,
+}
+
+#[deriving(Clone, Eq)]
+pub struct Member {
+  header: ~header::Header,
+  offset: u64,
+}
+
+enum Stage<C> {
+  HeaderStage(hdr_decoder::HeaderDecoder, u64),
+  BodyStage(body_decoder::BodyDecoder<C>),
+  MemberEndStage(),
+  // the first magic byte ended a chunk, the next member would start at the
+  // offset
+  MagicStage(u64),
+  ErrorStage(~error::Error),
+  EndStage(),
+}
+
+impl Decoder<crc32::Crc32> {
+  pub fn new() -> Decoder<crc32::Crc32> {
+    Decoder {
+      stage: HeaderStage(hdr_decoder::HeaderDecoder::new(), 0),
+      offset: 0,
+      limits: limits::Limits::unlimited(),
+      total_out: 0,
+      checksum: crc32::Crc32::new(),
+    }
+  }
+}
+
+impl<C: checksum::Checksum> Decoder<C> {
+  /// Sets the limits. Must be called before any input.
+  pub fn with_limits(self, limits: limits::Limits) -> Decoder<C> {
+    Decoder {
+      stage: HeaderStage(Decoder::header_decoder(&limits), 0),
+      offset: 0,
+      limits: limits,
+      total_out: 0,
+      checksum: self.checksum,
+    }
+  }
+
+  /// Replaces the checksum that verifies the data of the members, for example
+  /// with `checksum::NoCheck` for trusted data. Must be called before the
+  /// data of the first member starts, fails otherwise.
+  pub fn with_checksum<D: checksum::Checksum>(self, checksum: D) -> Decoder<D> {
+    let Decoder { stage, offset, limits, total_out, checksum: _ } = self;
+    let stage = match stage {
+        HeaderStage(hdr_decoder, 0) => HeaderStage(hdr_decoder, 0),
+        _ => fail!("Decoder::with_checksum: the data has already started"),
+      };
+    Decoder {
+      stage: stage,
+      offset: offset,
+      limits: limits,
+      total_out: total_out,
+      checksum: checksum,
+    }
+  }
+
+  /// Decodes a chunk of input and sends the data and the headers to the
+  /// receivers, see `inflater::Inflater::feed`.
+  pub fn feed<'a, R: recv::Recv<u8>, M: recv::Recv<Member>>
+    (&mut self, chunk: &'a [u8], recv: R, members: M)
+    -> (status::Status<'a, (), ~error::Error>, R, M)
+  {
+    let (res, recv, members, _) = self.decode_chunk(chunk, uint::max_value,
+      recv::Infallible(recv), members);
+    let recv = match recv { Ok(recv) | Err(recv) => recv.unwrap() };
+    match res {
+      Left(_) => (status::NeedInput, recv, members),
+      Right((Ok(()), rest)) => (status::Finished((), rest), recv, members),
+      Right((Err(err), rest)) => (status::Error(err, rest), recv, members),
+    }
+  }
+
+  pub fn input<'a, R: recv::Recv<u8>, M: recv::Recv<Member>>
+    (self, chunk: &'a [u8], recv: R, members: M)
+    -> (Either<Decoder<C>, (Result<(), ~error::Error>, &'a [u8])>, R, M)
+  {
+    let mut decoder = self;
+    let (status, recv, members) = decoder.feed(chunk, recv, members);
+    match status {
+      status::NeedInput => (Left(decoder), recv, members),
+      status::Finished((), rest) => (Right((Ok(()), rest)), recv, members),
+      status::Error(err, rest) => (Right((Err(err), rest)), recv, members),
+    }
+  }
+
+  /// Decodes the data into `buf` and stops when it is full, see
+  /// `inflater::Inflater::input_into`. The headers are dropped.
+  pub fn input_into(self, chunk: &[u8], buf: &mut [u8])
+    -> (Either<Decoder<C>, Result<(), ~error::Error>>, uint, uint)
+  {
+    let avail = buf.len();
+    let recv = recv::SliceRecv::new(buf);
+    match self.input_limited(chunk, avail, recv, ()) {
+      (Left((decoder, rest)), _, (), written) =>
+        (Left(decoder), chunk.len() - rest.len(), written),
+      (Right((res, rest)), _, (), written) =>
+        (Right(res), chunk.len() - rest.len(), written),
+    }
+  }
+
+  /// Like `input`, but sends at most `avail` bytes to `recv`, see
+  /// `inflater::Inflater::input_limited`.
+  pub fn input_limited<'a, R: recv::Recv<u8>, M: recv::Recv<Member>>
+    (self, chunk: &'a [u8], avail: uint, recv: R, members: M)
+    -> (Either<(Decoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      R, M, uint)
+  {
+    match self.try_input_limited(chunk, avail, recv::Infallible(recv), members) {
+      (res, Ok(recv), members, written) | (res, Err(recv), members, written) =>
+        (res, recv.unwrap(), members, written),
+    }
+  }
+
+  /// Like `input`, but the data receiver may fail, see
+  /// `inflater::Inflater::try_input`.
+  pub fn try_input<'a, R: recv::TryRecv<u8>, M: recv::Recv<Member>>
+    (self, chunk: &'a [u8], recv: R, members: M)
+    -> (Either<(Decoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, M)
+  {
+    match self.try_input_limited(chunk, uint::max_value, recv, members) {
+      (res, recv, members, _) => (res, recv, members),
+    }
+  }
+
+  /// Combines `try_input` and `input_limited`, see
+  /// `inflater::Inflater::try_input_limited`.
+  pub fn try_input_limited<'a, R: recv::TryRecv<u8>, M: recv::Recv<Member>>
+    (self, chunk: &'a [u8], avail: uint, recv: R, members: M)
+    -> (Either<(Decoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, M, uint)
+  {
+    let mut decoder = self;
+    let (res, recv, members, written) =
+      decoder.decode_chunk(chunk, avail, recv, members);
+    match res {
+      Left(rest) => (Left((decoder, rest)), recv, members, written),
+      Right(res) => (Right(res), recv, members, written),
+    }
+  }
+
+  // returns either the unconsumed input when the decoder stopped, or the
+  // result when the file ended
+  priv fn decode_chunk<'a, R: recv::TryRecv<u8>, M: recv::Recv<Member>>
+    (&mut self, chunk: &'a [u8], avail: uint, recv: R, members: M)
+    -> (Either<&'a [u8], (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, M, uint)
+  {
+    let mut stage = util::replace(&mut self.stage, EndStage);
+    let mut total_out = self.total_out;
+    let mut avail_left = avail;
+    let mut recv = recv;
+    let mut recv_failed = false;
+    let mut members = members;
+    let mut rest = chunk;
+
+    loop {
+      let (continue, new_stage) = match stage {
+        HeaderStage(hdr_decoder, start) => match hdr_decoder.input(rest) {
+          Left(new_hdr_decoder) => {
+            rest = rest.slice(rest.len(), rest.len());
+            (false, HeaderStage(new_hdr_decoder, start))
+          },
+          Right((Ok(header), new_rest)) => {
+            members = members.receive(&[Member { header: header, offset: start }]);
+            rest = new_rest;
+            let body_decoder = body_decoder::BodyDecoder::new()
+              .with_limits(Decoder::member_limits(&self.limits, total_out))
+              .with_checksum(self.checksum.clone());
+            (true, BodyStage(body_decoder))
+          },
+          Right((Err(err), new_rest)) => {
+            rest = new_rest;
+            (true, ErrorStage(err))
+          },
+        },
+        BodyStage(body_decoder) => {
+          let (res, recv_res, written) =
+            body_decoder.try_input_limited(rest, avail_left, recv);
+          recv = match recv_res {
+              Ok(new_recv) => new_recv,
+              Err(new_recv) => { recv_failed = true; new_recv },
+            };
+          total_out = total_out + written as u64;
+          avail_left = avail_left - written;
+          match res {
+            Left((new_body_decoder, new_rest)) => {
+              rest = new_rest;
+              (false, BodyStage(new_body_decoder))
+            },
+            Right((Ok(()), new_rest)) => {
+              rest = new_rest;
+              (true, MemberEndStage)
+            },
+            Right((Err(err), new_rest)) => {
+              rest = new_rest;
+              (true, ErrorStage(Decoder::total_limit_error(&self.limits, err)))
+            },
+          }
+        },
+        MemberEndStage() => {
+          let start = self.offset + (chunk.len() - rest.len()) as u64;
+          if rest.is_empty() {
+            (false, MemberEndStage)
+          } else if rest.len() == 1 && rest[0] == 0x1f {
+            rest = rest.slice(1, 1);
+            (false, MagicStage(start))
+          } else if rest[0] == 0x1f && rest[1] == 0x8b {
+            (true, HeaderStage(Decoder::header_decoder(&self.limits), start))
+          } else {
+            (true, EndStage)
+          }
+        },
+        MagicStage(start) =>
+          if rest.is_empty() {
+            (false, MagicStage(start))
+          } else if rest[0] == 0x8b {
+            // the header decoder needs more input after the first byte
+            let mut hdr_decoder = Decoder::header_decoder(&self.limits);
+            let _ = hdr_decoder.feed(&[0x1f]);
+            (true, HeaderStage(hdr_decoder, start))
+          } else {
+            (true, EndStage)
+          },
+        EndStage() => {
+          self.advance(chunk.len() - rest.len(), total_out);
+          return (Right((Ok(()), rest)), Ok(recv), members, avail - avail_left)
+        },
+        ErrorStage(err) => {
+          self.stage = ErrorStage(err.clone());
+          self.advance(chunk.len() - rest.len(), total_out);
+          return (Right((Err(err), rest)), Ok(recv), members, avail - avail_left)
+        },
+      };
+
+      if continue {
+        stage = new_stage;
+      } else {
+        // when the output is full or the receiver failed, the rest of the
+        // input is left to the caller
+        self.stage = new_stage;
+        self.advance(chunk.len() - rest.len(), total_out);
+        let recv = if recv_failed { Err(recv) } else { Ok(recv) };
+        return (Left(rest), recv, members, avail - avail_left)
+      }
+    }
+  }
+
+  fn header_decoder(limits: &limits::Limits) -> hdr_decoder::HeaderDecoder {
+    hdr_decoder::HeaderDecoder::new().with_limits(limits.clone())
+  }
+
+  priv fn advance(&mut self, consumed: uint, total_out: u64) {
+    self.offset = self.offset + consumed as u64;
+    self.total_out = total_out;
+  }
+
+  // the output limit of a member is what remains from the total limit
+  fn member_limits(limits: &limits::Limits, total_out: u64)
+    -> inflate::limits::Limits
+  {
+    let mut member_limits = limits.inflate.clone();
+    member_limits.max_output = match member_limits.max_output {
+        Some(max) if max > total_out => Some(max - total_out),
+        Some(_) => Some(0),
+        None => None,
+      };
+    member_limits
+  }
+
+  // reports the total output limit instead of the limit of the member
+  fn total_limit_error(limits: &limits::Limits, err: ~error::Error)
+    -> ~error::Error
+  {
+    match (err, limits.inflate.max_output) {
+      (~error::InflateError(~inflate::OutputLimitExceeded(_)), Some(max)) =>
+        ~error::InflateError(~inflate::OutputLimitExceeded(max)),
+      (err, _) => err,
+    }
+  }
+
+  /// Checks that the input did not end inside a member.
+  pub fn finish(self) -> Result<(), ~error::Error> {
+    match self.stage {
+      MemberEndStage() | MagicStage(_) => Ok(()),
+      EndStage() => Ok(()),
+      ErrorStage(err) => Err(err),
+      HeaderStage(_, _) | BodyStage(_) => Err(~error::UnexpectedEnd),
+    }
+  }
+}
+
+impl<C: checksum::Checksum> bits::io::StreamDecoder<~error::Error>
+  for Decoder<C>
+{
+  pub fn end_input(self) -> Result<(), ~error::Error> {
+    self.finish()
+  }
+}
+
+impl<C: checksum::Checksum> bits::io::BufDecoder<~error::Error>
+  for Decoder<C>
+{
+  pub fn decode_into(self, chunk: &[u8], buf: &mut [u8])
+    -> (Either<Decoder<C>, Result<(), ~error::Error>>, uint, uint)
+  {
+    self.input_into(chunk, buf)
+  }
+}
+
+impl<C: checksum::Checksum> bits::io::RecvDecoder<~error::Error>
+  for Decoder<C>
+{
+  pub fn decode<'a, R: recv::TryRecv<u8>>(self, chunk: &'a [u8], recv: R)
+    -> (Either<(Decoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>)
+  {
+    match self.try_input(chunk, recv, ()) {
+      (res, recv, ()) => (res, recv),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use gzip::test_helpers::*;
+  use gzip::decoder;
+  use gzip::error;
+  use gzip::header;
+  use gzip::limits;
+  use inflate;
+  use bits;
+  use bits::status;
+  use checksums::checksum;
+  use std::{vec, uint};
+
+  static blank_header: [u8, ..10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+  static fib_body: [u8, ..20] = [
+      0x63, 0x64, 0x64, 0x62, 0x66, 0xe5,
+      0xe0, 0x15, 0x55, 0x32, 0x07, 0x00,
+      0xf0, 0x8a, 0xcb, 0xff,
+      0x0a, 0x00, 0x00, 0x00,
+    ];
+  static fib: [u8, ..10] = [1, 1, 2, 3, 5, 8, 13, 21, 34, 55];
+
+  fn member(offset: u64) -> decoder::Member {
+    decoder::Member { header: ~header::Header::blank(), offset: offset }
+  }
+
+  #[test]
+  fn test_decode_single_member() {
+    let bytes = vec::append(blank_header.to_owned(), fib_body);
+    assert_eq!(decode_ok(bytes), (fib.to_owned(), ~[member(0)]));
+  }
+
+  #[test]
+  fn test_decode_multiple_members() {
+    let mut bytes = ~[];
+    for 3.times {
+      bytes.push_all(blank_header);
+      bytes.push_all(fib_body);
+    }
+
+    let data = vec::append(vec::append(fib.to_owned(), fib), fib);
+    assert_eq!(decode_ok(bytes), (data.clone(), ~[member(0), member(30), member(60)]));
+
+    for uint::range(1, 12) |chunk_len| {
+      assert_eq!(decode_chunked_ok(chunk_len, bytes),
+        (data.clone(), ~[member(0), member(30), member(60)]));
+    }
+  }
+
+  #[test]
+  fn test_with_checksum() {
+    // the first header may already be in progress
+    let mut bytes = vec::append(blank_header.to_owned(), fib_body);
+    bytes[22] = 0xde;
+    let decoder = decoder::Decoder::new();
+    let decoder = match decoder.input(bytes.slice(0, 5), (), ()) {
+        (Left(decoder), (), ()) => decoder.with_checksum(checksum::NoCheck),
+        other => fail!(fmt!("unexpected %?", other)),
+      };
+    match decoder.input(bytes.slice(5, bytes.len()), ~[], ()) {
+      (Left(decoder), data, ()) => {
+        assert_eq!(data, fib.to_owned());
+        assert_eq!(decoder.finish(), Ok(()));
+      },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_try_input() {
+    let data = vec::from_fn(100_000, |i| (i % 251) as u8);
+    let bytes = encode_blank(data);
+
+    let mut decoder = decoder::Decoder::new();
+    let mut recv = FlakyRecv::new();
+    let mut rest = bytes.slice(0, bytes.len());
+    let mut failures = 0u;
+    loop {
+      match decoder.try_input(rest, recv, ()) {
+        (Left((new_decoder, new_rest)), Err(new_recv), ()) => {
+          decoder = new_decoder;
+          rest = new_rest;
+          recv = new_recv;
+          failures = failures + 1;
+        },
+        (Left((new_decoder, [])), Ok(new_recv), ()) => {
+          // the CRC32 and the ISIZE were verified, so no data were lost or
+          // repeated
+          assert_eq!(new_decoder.finish(), Ok(()));
+          assert_eq!(new_recv.buf, data);
+          break;
+        },
+        other => fail!(fmt!("unexpected %?", other)),
+      }
+    }
+    assert!(failures > 0);
+  }
+
+  #[test]
+  #[should_fail]
+  fn test_with_checksum_after_data() {
+    let bytes = vec::append(blank_header.to_owned(), fib_body);
+    match decoder::Decoder::new().input(bytes.slice(0, 15), (), ()) {
+      (Left(decoder), (), ()) => { decoder.with_checksum(checksum::NoCheck); },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_decode_feed() {
+    let mut bytes = ~[];
+    for 2.times {
+      bytes.push_all(blank_header);
+      bytes.push_all(fib_body);
+    }
+    bytes.push(0);
+
+    let mut decoder = decoder::Decoder::new();
+    let (data, members) = match decoder.feed(bytes.slice(0, 35), ~[], ~[]) {
+        (status::NeedInput, data, members) => (data, members),
+        other => fail!(fmt!("unexpected %?", other)),
+      };
+    assert_eq!(members, ~[member(0)]);
+
+    // the offsets continue from the first call
+    match decoder.feed(bytes.slice(35, bytes.len()), data, members) {
+      (status::Finished((), [0]), data, members) => {
+        assert_eq!(data, vec::append(fib.to_owned(), fib));
+        assert_eq!(members, ~[member(0), member(30)]);
+      },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+    assert_eq!(decoder.finish(), Ok(()));
+  }
+
+  #[test]
+  fn test_decode_trailing_garbage() {
+    let bytes = vec::append(vec::append(blank_header.to_owned(), fib_body),
+      &[0, 0, 0x1f, 0x8b]);
+    let decoder = decoder::Decoder::new();
+
+    match decoder.input(bytes, ~[], ~[]) {
+      (Right((Ok(()), rest)), data, members) => {
+        assert_eq!(rest, &[0, 0, 0x1f, 0x8b]);
+        assert_eq!(data, fib.to_owned());
+        assert_eq!(members, ~[member(0)]);
+      },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+
+    // only both magic bytes start another member
+    let bytes = vec::append(vec::append(blank_header.to_owned(), fib_body),
+      &[0x1f, 0x00]);
+    match decoder::Decoder::new().input(bytes, ~[], ()) {
+      (Right((Ok(()), rest)), data, ()) => {
+        assert_eq!(rest, &[0x1f, 0x00]);
+        assert_eq!(data, fib.to_owned());
+      },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+
+    let bytes = vec::append(vec::append(blank_header.to_owned(), fib_body),
+      &[0x1f]);
+    match decoder::Decoder::new().input(bytes, ~[], ()) {
+      (Left(decoder), data, ()) => {
+        assert_eq!(data, fib.to_owned());
+        assert_eq!(decoder.finish(), Ok(()));
+      },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_decode_magic_split() {
+    let member_bytes = vec::append(blank_header.to_owned(), fib_body);
+    let bytes = vec::append(member_bytes.clone(), member_bytes);
+
+    // the second member starts with the last byte of the first chunk
+    let (decoder, data, members) =
+      match decoder::Decoder::new().input(bytes.slice(0, 31), ~[], ~[]) {
+        (Left(decoder), data, members) => (decoder, data, members),
+        other => fail!(fmt!("unexpected %?", other)),
+      };
+    match decoder.input(bytes.slice(31, bytes.len()), data, members) {
+      (Left(decoder), data, members) => {
+        assert_eq!(decoder.finish(), Ok(()));
+        assert_eq!(data, vec::append(fib.to_owned(), fib));
+        assert_eq!(members, ~[member(0), member(30)]);
+      },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+
+    // the garbage after a split first magic byte ends the decoding
+    let decoder = decoder::Decoder::new();
+    let decoder = match decoder.input(bytes.slice(0, 31), (), ()) {
+        (Left(decoder), (), ()) => decoder,
+        other => fail!(fmt!("unexpected %?", other)),
+      };
+    match decoder.input(&[0x00, 0x1f], (), ()) {
+      (Right((Ok(()), [0x00, 0x1f])), (), ()) => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_decode_truncated() {
+    let bytes = vec::append(blank_header.to_owned(), fib_body);
+
+    let lens = [0u, 5, 10, 25, 29];
+    for lens.iter().advance |&len| {
+      let decoder = decoder::Decoder::new();
+      match decoder.input(bytes.slice(0, len), (), ()) {
+        (Left(decoder), (), ()) =>
+          assert_eq!(decoder.finish(), Err(~error::UnexpectedEnd)),
+        other => fail!(fmt!("unexpected %?", other)),
+      }
+    }
+  }
+
+  #[test]
+  fn test_decode_error_in_second_member() {
+    let bytes = vec::append(vec::append(blank_header.to_owned(), fib_body),
+      &[0x1f, 0x8b, 77, 0, 0, 0, 0, 0, 0, 255, 42]);
+    let decoder = decoder::Decoder::new();
+
+    match decoder.input(bytes, (), ~[]) {
+      (Right((Err(err), rest)), (), members) => {
+        assert_eq!(err, ~error::BadCompressionMethod(77));
+        assert_eq!(rest, &[42]);
+        assert_eq!(members, ~[member(0)]);
+      },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_decode_limits() {
+    let mut bytes = ~[];
+    for 3.times {
+      bytes.push_all(blank_header);
+      bytes.push_all(fib_body);
+    }
+
+    let mut limits = limits::Limits::unlimited();
+    limits.inflate.max_output = Some(30);
+    let decoder = decoder::Decoder::new().with_limits(limits.clone());
+    match decoder.input(bytes, 0u32, ()) {
+      (Left(decoder), 30, ()) => assert_eq!(decoder.finish(), Ok(())),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+
+    limits.inflate.max_output = Some(25);
+    let decoder = decoder::Decoder::new().with_limits(limits.clone());
+    match decoder.input(bytes, 0u32, ()) {
+      (Right((Err(err), _)), 25, ()) => assert_eq!(err,
+        ~error::InflateError(~inflate::OutputLimitExceeded(25))),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+
+    let mut limits = limits::Limits::unlimited();
+    limits.max_comment_len = Some(0);
+    let decoder = decoder::Decoder::new().with_limits(limits.clone());
+    let bytes = vec::append(~[0x1f, 0x8b, 8, 0b000_10000, 0, 0, 0, 0, 0, 255],
+      &[97, 0]);
+    match decoder.input(bytes, (), ()) {
+      (Right((Err(err), _)), (), ()) =>
+        assert_eq!(err, ~error::CommentTooLong(0)),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_decompress_reader() {
+    fn read_all(bytes: ~[u8]) -> (~[u8], Option<~error::Error>, ~[u8]) {
+      decompress_bytes(decoder::Decoder::new(), bytes, 7)
+    }
+
+    let mut bytes = ~[];
+    for 2.times {
+      bytes.push_all(blank_header);
+      bytes.push_all(fib_body);
+    }
+    let data = vec::append(fib.to_owned(), fib);
+
+    assert_eq!(read_all(bytes.clone()), (data.clone(), None, ~[]));
+    assert_eq!(read_all(vec::append(bytes.clone(), [0, 0x1f])),
+      (data.clone(), None, ~[0, 0x1f]));
+    assert_eq!(read_all(bytes.slice(0, 38).to_owned()),
+      (fib.to_owned(), Some(~error::UnexpectedEnd), ~[]));
+  }
+}
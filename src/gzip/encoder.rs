@@ -0,0 +1,223 @@
+use bits;
+use bits::recv;
+use checksums::crc32;
+use deflate;
+use gzip::error;
+use gzip::header;
+
+/// Compresses data into a single gzip member (RFC 1952). The header is written
+/// before the first compressed byte, the CRC32 and ISIZE trailer by `finish`.
+pub struct Encoder {
+  priv header: Option<~header::Header>,
+  priv deflater: deflate::Deflater,
+  priv crc: crc32::Crc32,
+  priv isize: u32,
+}
+
+impl Encoder {
+  /// Creates an encoder that writes `header` and compresses with the given
+  /// level (see `deflate::Deflater::new`). Fails if the level is out of range
+  /// or the header cannot be encoded (see `Header::check`).
+  pub fn new(header: ~header::Header, level: uint)
+    -> Result<Encoder, ~error::Error>
+  {
+    match header.check() {
+      Ok(()) => { },
+      Err(err) => return Err(err),
+    }
+
+    match deflate::Deflater::new(level) {
+      Some(deflater) => Ok(Encoder {
+          header: Some(header),
+          deflater: deflater,
+          crc: crc32::Crc32::new(),
+          isize: 0,
+        }),
+      None => Err(~error::BadCompressionLevel(level)),
+    }
+  }
+
+  pub fn input<R: recv::Recv<u8>>(self, chunk: &[u8], recv: R) -> (Encoder, R) {
+    let Encoder { header, deflater, crc, isize } = self;
+    let recv = Encoder::write_header(header, recv);
+    let (deflater, recv) = deflater.input(chunk, recv);
+
+    let encoder = Encoder {
+      header: None,
+      deflater: deflater,
+      crc: crc.update(chunk),
+      isize: isize + chunk.len() as u32,
+    };
+    (encoder, recv)
+  }
+
+  pub fn finish<R: recv::Recv<u8>>(self, recv: R) -> R {
+    let Encoder { header, deflater, crc, isize } = self;
+    let recv = Encoder::write_header(header, recv);
+    let recv = deflater.finish(recv);
+
+    let mut trailer = bits::ByteWriter::new();
+    trailer.write_u32_le(crc.crc32());
+    trailer.write_u32_le(isize);
+    trailer.send(recv)
+  }
+
+  fn write_header<R: recv::Recv<u8>>(header: Option<~header::Header>, recv: R)
+    -> R
+  {
+    match header {
+      // the header was checked by `new`
+      Some(header) => { let (_, recv) = header.write(recv); recv },
+      None => recv,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use gzip::test_helpers::*;
+  use gzip::decoder;
+  use gzip::encoder;
+  use gzip::error;
+  use gzip::header;
+  use std::{vec, rand};
+  use std::rand::{RngUtil};
+
+  fn encode(header: ~header::Header, level: uint, bytes: &[u8]) -> ~[u8] {
+    let encoder = encoder::Encoder::new(header, level).unwrap();
+    let (encoder, out) = encoder.input(bytes, ~[]);
+    encoder.finish(out)
+  }
+
+  fn encode_chunked(chunk_len: uint, bytes: &[u8]) -> ~[u8] {
+    let mut encoder =
+      encoder::Encoder::new(~header::Header::blank(), 6).unwrap();
+    let mut out = ~[];
+    for bytes.chunk_iter(chunk_len).advance |chunk| {
+      let (new_encoder, new_out) = encoder.input(chunk, out);
+      encoder = new_encoder;
+      out = new_out;
+    }
+    encoder.finish(out)
+  }
+
+  fn write(header: &header::Header) -> ~[u8] {
+    match header.write(~[]) {
+      (Ok(()), bytes) => bytes,
+      (Err(err), _) => fail!(fmt!("write: unexpected error %s", err.to_str())),
+    }
+  }
+
+  fn full_header() -> ~header::Header {
+    let mut header = ~header::Header::blank();
+    header.is_text = true;
+    header.has_crc = true;
+    header.extras = Some(~[
+        header::Extra { id: (11, 22), data: ~[110, 120, 130] },
+        header::Extra { id: (44, 2), data: ~[] },
+      ]);
+    header.file_name = Some("fib.txt".as_bytes().to_owned());
+    header.comment = Some("numbers".as_bytes().to_owned());
+    header.extra_flags = 2;
+    header.system = Some(header::Unix);
+    header.mtime = Some(0x87654321);
+    header
+  }
+
+  #[test]
+  fn test_write_header() {
+    let mut header = ~header::Header::blank();
+    header.file_name = Some("ab".as_bytes().to_owned());
+    header.mtime = Some(0x87654321);
+    header.system = Some(header::Unix);
+
+    assert_eq!(write(header), ~[
+        0x1f, 0x8b, 8, 0b000_01000,
+        0x21, 0x43, 0x65, 0x87,
+        0, 3,
+        97, 98, 0,
+      ]);
+    assert_eq!(decode_hdr_ok(write(header)), header.clone());
+
+    let header = full_header();
+    assert_eq!(decode_hdr_ok(write(header)), header.clone());
+  }
+
+  #[test]
+  fn test_write_header_extras_too_long() {
+    let mut header = ~header::Header::blank();
+    header.extras = Some(~[
+        header::Extra { id: (1, 2), data: vec::from_elem(65_531, 0u8) },
+      ]);
+    assert_eq!(write(header).len(), 10 + 2 + 65_535);
+
+    header.extras = Some(~[
+        header::Extra { id: (1, 2), data: vec::from_elem(65_532, 0u8) },
+      ]);
+    assert_eq!(header.write(~[]),
+      (Err(~error::ExtrasTooLong(65_536, 65_535)), ~[]));
+    match encoder::Encoder::new(header, 6) {
+      Err(~error::ExtrasTooLong(65_536, 65_535)) => { },
+      _ => fail!(~"the encoder accepted a header it cannot write"),
+    }
+  }
+
+  #[test]
+  fn test_encode_bad_level() {
+    match encoder::Encoder::new(~header::Header::blank(), 10) {
+      Err(~error::BadCompressionLevel(10)) => { },
+      _ => fail!(~"the encoder accepted level 10"),
+    }
+  }
+
+  #[test]
+  fn test_write_header_zero_in_name() {
+    let mut header = ~header::Header::blank();
+    header.file_name = Some(~[97, 0, 98]);
+    assert_eq!(header.write(~[]), (Err(~error::ZeroInFileName), ~[]));
+
+    header.file_name = Some(~[97, 98]);
+    header.comment = Some(~[0]);
+    assert_eq!(header.write(~[]), (Err(~error::ZeroInComment), ~[]));
+    match encoder::Encoder::new(header, 6) {
+      Err(~error::ZeroInComment) => { },
+      _ => fail!(~"the encoder accepted a zero byte in the comment"),
+    }
+  }
+
+  #[test]
+  fn test_encode_empty() {
+    assert_eq!(encode(~header::Header::blank(), 6, &[]), ~[
+        0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255,
+        0b011, 0,
+        0, 0, 0, 0,
+        0, 0, 0, 0,
+      ]);
+  }
+
+  #[test]
+  fn test_encode_round_trip() {
+    let mut rng = rand::IsaacRng::new_seeded(&[42]);
+    let bytes = rng.gen_bytes(50_000);
+
+    let members = ~[decoder::Member { header: full_header(), offset: 0 }];
+    let levels = [0u, 1, 6, 9];
+    for levels.iter().advance |&level| {
+      assert_eq!(decode_ok(encode(full_header(), level, bytes)),
+        (bytes.clone(), members.clone()));
+    }
+  }
+
+  #[test]
+  fn test_encode_chunked() {
+    let bytes = do vec::from_fn(30_000) |i| { (i % 251 + i / 1000) as u8 };
+    let whole = encode(~header::Header::blank(), 6, bytes);
+    let chunk_lens = [1u, 100, 4096];
+    for chunk_lens.iter().advance |&chunk_len| {
+      assert_eq!(encode_chunked(chunk_len, bytes), whole.clone());
+    }
+
+    let (data, _) = decode_ok(whole);
+    assert_eq!(data, bytes);
+  }
+}
@@ -1,4 +1,6 @@
+use bits;
 use inflate;
+use std::u64;
 
 #[deriving(Clone,Eq)]
 pub enum Error {
@@ -6,11 +8,19 @@ pub enum Error {
   BadMagicNumber(u16, u16),
   BadCompressionMethod(uint),
   BadHeaderChecksum(u16, u16),
-  BadDataChecksum(u32, u32),
+  BadDataChecksum(u64, u64),
   BadDataSize(uint, uint),
   ReservedFlagUsed(uint),
   TrailingExtraBytes(uint),
   ExtraTooLong(uint, uint),
+  ExtrasTooLong(uint, uint),
+  FileNameTooLong(uint),
+  CommentTooLong(uint),
+  UnexpectedEnd(),
+  BadCompressionLevel(uint),
+  ZeroInFileName(),
+  ZeroInComment(),
+  InternalError(bits::error::Error),
 }
 
 impl ToStr for Error {
@@ -27,8 +37,8 @@ impl ToStr for Error {
         fmt!("Bad header checksum, computed %04x, but in file is %04x",
           computed as uint, read as uint),
       BadDataChecksum(computed, read) =>
-        fmt!("Bad data checksum, decompressed %08x, in trailer %08x",
-          computed as uint, read as uint),
+        fmt!("Bad data checksum, decompressed %s, in trailer %s",
+          u64::to_str_radix(computed, 16), u64::to_str_radix(read, 16)),
       BadDataSize(actual, from_file) =>
         fmt!("Bad data size, decompressed %u bytes, in trailer %u bytes",
           actual, from_file),
@@ -39,6 +49,22 @@ impl ToStr for Error {
       ExtraTooLong(remained, requested) =>
         fmt!("An extra subfield too long, only %u bytes remained but %u requested",
           remained, requested),
+      ExtrasTooLong(xlen, max_len) =>
+        fmt!("Extra field has %u bytes, the limit is %u", xlen, max_len),
+      FileNameTooLong(max_len) =>
+        fmt!("File name is longer than the limit of %u bytes", max_len),
+      CommentTooLong(max_len) =>
+        fmt!("Comment is longer than the limit of %u bytes", max_len),
+      UnexpectedEnd() =>
+        fmt!("Unexpected end of input inside a gzip member"),
+      BadCompressionLevel(level) =>
+        fmt!("Compression level %u is out of range", level),
+      ZeroInFileName() =>
+        fmt!("File name contains a zero byte"),
+      ZeroInComment() =>
+        fmt!("Comment contains a zero byte"),
+      InternalError(ref err) =>
+        fmt!("Internal error: %s", err.to_str()),
     }
   }
 }
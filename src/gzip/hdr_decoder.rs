@@ -1,14 +1,18 @@
 use bits;
+use bits::status;
 use gzip::header;
 use gzip::error;
+use gzip::limits;
 use std::vec;
-use std::uint;
-use std::str; 
+use std::{uint, util};
+
+mod check;
 
 pub struct HeaderDecoder {
   priv stage: Stage,
   priv byte_buf: bits::ByteBuf,
   priv header: ~header::Header,
+  priv limits: limits::Limits,
 }
 
 enum Stage {
@@ -31,67 +35,97 @@ impl HeaderDecoder {
       stage: BeginStage,
       byte_buf: bits::ByteBuf::new(),
       header: ~header::Header::blank(),
+      limits: limits::Limits::unlimited(),
     }
   }
 
-  pub fn input<'a>(self, chunk: &'a [u8]) 
-    -> Either<HeaderDecoder, (Result<~header::Header, ~error::Error>, &'a [u8])>
+  /// Sets the limits on the header fields. Must be called before any input.
+  pub fn with_limits(self, limits: limits::Limits) -> HeaderDecoder {
+    let mut decoder = self;
+    decoder.limits = limits;
+    decoder
+  }
+
+  /// Decodes a chunk of input. Unlike `input`, the decoder is borrowed and
+  /// the finished header is cloned, so later calls return the same header.
+  pub fn feed<'a>(&mut self, chunk: &'a [u8])
+    -> status::Status<'a, ~header::Header, ~error::Error>
   {
-    let HeaderDecoder { stage, byte_buf, header } = self;
-    let mut stage = stage;
-    let mut header = header;
+    let mut stage = util::replace(&mut self.stage, EndStage);
+    let byte_buf = util::replace(&mut self.byte_buf, bits::ByteBuf::new());
     let mut reader = bits::ByteReader::new(byte_buf, chunk);
 
     loop {
       let (continue, new_stage) = match stage {
         BeginStage() => 
-          HeaderDecoder::begin_stage(&mut reader, header),
+          HeaderDecoder::begin_stage(&mut reader, &mut *self.header),
         ExtraStage() => 
-          HeaderDecoder::extra_stage(&mut reader, header),
+          HeaderDecoder::extra_stage(&mut reader, &*self.header,
+            &self.limits),
         ExtraHeaderStage(xlen_rem) =>
           HeaderDecoder::extra_header_stage(&mut reader, xlen_rem),
         ExtraDataStage(id, data, len_rem, xlen_rem) => 
           HeaderDecoder::extra_data_stage(
-            id, data, len_rem, xlen_rem, &mut reader, header),
+            id, data, len_rem, xlen_rem, &mut reader, &mut *self.header),
         FileNameStage() => 
-          HeaderDecoder::file_name_stage(header),
+          HeaderDecoder::file_name_stage(&*self.header),
         FileNameDataStage(read_yet) => 
-          HeaderDecoder::file_name_data_stage(read_yet, &mut reader, header),
+          HeaderDecoder::file_name_data_stage(read_yet, &mut reader,
+            &mut *self.header, &self.limits),
         CommentStage() => 
-          HeaderDecoder::comment_stage(header),
+          HeaderDecoder::comment_stage(&*self.header),
         CommentDataStage(read_yet) => 
-          HeaderDecoder::comment_data_stage(read_yet, &mut reader, header),
+          HeaderDecoder::comment_data_stage(read_yet, &mut reader,
+            &mut *self.header, &self.limits),
         CrcStage() => 
-          HeaderDecoder::crc_stage(&mut reader, header),
-        ErrorStage(err) => 
-          return Right((Err(err), reader.close_to_rest())),
+          HeaderDecoder::crc_stage(&mut reader, &*self.header),
+        ErrorStage(err) => {
+          let (_, rest) = reader.close();
+          self.stage = ErrorStage(err.clone());
+          return status::Error(err, rest)
+        },
         EndStage() => 
-          return Right((Ok(header), reader.close_to_rest())),
+          return match reader.close_to_rest() {
+            Ok(rest) => status::Finished(self.header.clone(), rest),
+            Err(err) => {
+              let err = ~error::InternalError(err);
+              self.stage = ErrorStage(err.clone());
+              status::Error(err, chunk.slice(chunk.len(), chunk.len()))
+            },
+          },
       };
 
       if !continue {
-        let decoder = HeaderDecoder {
-          stage: new_stage,
-          byte_buf: reader.close_to_buf(),
-          header: header
-        };
-        return Left(decoder)
+        self.stage = new_stage;
+        self.byte_buf = reader.close_to_buf();
+        return status::NeedInput
       } else {
         stage = new_stage;
       }
     }
   }
 
+  pub fn input<'a>(self, chunk: &'a [u8]) 
+    -> Either<HeaderDecoder, (Result<~header::Header, ~error::Error>, &'a [u8])>
+  {
+    let mut decoder = self;
+    match decoder.feed(chunk) {
+      status::NeedInput => Left(decoder),
+      status::Finished(header, rest) => Right((Ok(header), rest)),
+      status::Error(err, rest) => Right((Err(err), rest)),
+    }
+  }
+
   fn begin_stage(reader: &mut bits::ByteReader, header: &mut header::Header) 
     -> (bool, Stage)
   {
     if reader.has_bytes(10) {
-      let id    = reader.read_u16_le();
-      let cm    = reader.read_byte();
-      let flg   = reader.read_byte();
-      let mtime = reader.read_u32_le();
-      let xfl   = reader.read_byte();
-      let os    = reader.read_byte();
+      let id    = try_read!(reader.read_u16_le());
+      let cm    = try_read!(reader.read_byte());
+      let flg   = try_read!(reader.read_byte());
+      let mtime = try_read!(reader.read_u32_le());
+      let xfl   = try_read!(reader.read_byte());
+      let os    = try_read!(reader.read_byte());
 
       let ftext     = flg & 0b1;
       let fhcrc     = flg & 0b10;
@@ -116,8 +150,8 @@ impl HeaderDecoder {
         header.is_text     = ftext != 0;
         header.has_crc     = fhcrc != 0;
         header.extras      = if fextra != 0 { Some(~[]) } else { None };
-        header.file_name   = if fname != 0 { Some(~"") } else { None };
-        header.comment     = if fcomment != 0 { Some(~"") } else { None };
+        header.file_name   = if fname != 0 { Some(~[]) } else { None };
+        header.comment     = if fcomment != 0 { Some(~[]) } else { None };
         header.extra_flags = xfl;
         header.system      = header::System::from_number(os);
         header.mtime       = if mtime != 0 { Some(mtime) } else { None };
@@ -128,13 +162,18 @@ impl HeaderDecoder {
     }
   }
 
-  fn extra_stage(reader: &mut bits::ByteReader, header: &header::Header)
+  fn extra_stage(reader: &mut bits::ByteReader, header: &header::Header,
+    limits: &limits::Limits)
     -> (bool, Stage) 
   {
     if header.extras.is_some() {
       if reader.has_bytes(2) {
-        let xlen = reader.read_u16_le();
-        (true, ExtraHeaderStage(xlen as uint))
+        let xlen = try_read!(reader.read_u16_le()) as uint;
+        match limits.max_extras_len {
+          Some(max_len) if xlen > max_len =>
+            (true, ErrorStage(~error::ExtrasTooLong(xlen, max_len))),
+          _ => (true, ExtraHeaderStage(xlen)),
+        }
       } else {
         (false, ExtraStage)
       }
@@ -151,9 +190,9 @@ impl HeaderDecoder {
     } else if xlen_rem < 4 {
       (true, ErrorStage(~error::TrailingExtraBytes(xlen_rem)))
     } else if reader.has_bytes(4) {
-      let si1 = reader.read_byte();
-      let si2 = reader.read_byte();
-      let len = reader.read_u16_le();
+      let si1 = try_read!(reader.read_byte());
+      let si2 = try_read!(reader.read_byte());
+      let len = try_read!(reader.read_u16_le());
 
       if len as uint <= xlen_rem - 4 {
         (true, ExtraDataStage((si1, si2), ~[],
@@ -175,7 +214,7 @@ impl HeaderDecoder {
       header.extras.get_mut_ref().push(extra);
       (true, ExtraHeaderStage(xlen_rem))
     } else if reader.has_some_bytes() {
-      do reader.consume_chunk(data) |data, whole_chunk| {
+      let (res, consumed) = do reader.consume_chunk(data) |data, whole_chunk| {
         let (chunk, opt_rest) =
           if whole_chunk.len() <= len_rem {
             (whole_chunk, None)
@@ -186,7 +225,9 @@ impl HeaderDecoder {
 
         ((true, ExtraDataStage(id, vec::append(data, chunk),
           len_rem - chunk.len(), xlen_rem - chunk.len())), opt_rest)
-      }
+      };
+      try_read!(consumed);
+      res
     } else {
       (false, ExtraDataStage(id, data, len_rem, xlen_rem))
     }
@@ -203,14 +244,21 @@ impl HeaderDecoder {
   }
 
   fn file_name_data_stage(read_yet: ~[u8],
-    reader: &mut bits::ByteReader, header: &mut header::Header)
+    reader: &mut bits::ByteReader, header: &mut header::Header,
+    limits: &limits::Limits)
     -> (bool, Stage)
   {
-    match HeaderDecoder::null_term_str(reader, read_yet) {
+    let max_len = match limits.max_file_name_len {
+        Some(max_len) => max_len,
+        None => uint::max_value,
+      };
+    match try_read!(HeaderDecoder::null_term_bytes(reader, read_yet, max_len)) {
       Right(file_name) => {
         header.file_name = Some(file_name);
         (true, CommentStage)
       },
+      Left((_, read_now)) if read_now.len() > max_len =>
+        (true, ErrorStage(~error::FileNameTooLong(max_len))),
       Left((continue, read_now)) =>
         (continue, FileNameDataStage(read_now)),
     }
@@ -227,27 +275,40 @@ impl HeaderDecoder {
   }
 
   fn comment_data_stage(read_yet: ~[u8],
-    reader: &mut bits::ByteReader, header: &mut header::Header)
+    reader: &mut bits::ByteReader, header: &mut header::Header,
+    limits: &limits::Limits)
     -> (bool, Stage)
   {
-    match HeaderDecoder::null_term_str(reader, read_yet) {
+    let max_len = match limits.max_comment_len {
+        Some(max_len) => max_len,
+        None => uint::max_value,
+      };
+    match try_read!(HeaderDecoder::null_term_bytes(reader, read_yet, max_len)) {
       Right(comment) => {
         header.comment = Some(comment);
         (true, CrcStage)
       },
+      Left((_, read_now)) if read_now.len() > max_len =>
+        (true, ErrorStage(~error::CommentTooLong(max_len))),
       Left((continue, read_now)) =>
         (continue, CommentDataStage(read_now)),
     }
   }
 
-  // TODO: SECURITY ISSUE: if the bytes don't form valid utf8, this function fails!
-  fn null_term_str(reader: &mut bits::ByteReader, read_yet: ~[u8])
-    -> Either<(bool, ~[u8]), ~str>
+  // Reads the raw bytes up to a zero byte, their encoding is left to
+  // `header::Charset`. Stops reading after more than `max_len` bytes, the
+  // caller then reports the error.
+  fn null_term_bytes(reader: &mut bits::ByteReader, read_yet: ~[u8],
+    max_len: uint)
+    -> Result<Either<(bool, ~[u8]), ~[u8]>, bits::error::Error>
   {
     if reader.has_some_bytes() {
-      do reader.consume_chunk(read_yet) |read_yet, whole_chunk| {
+      let (res, consumed) = do reader.consume_chunk(read_yet)
+        |read_yet, whole_chunk|
+      {
         let mut data = read_yet;
         let mut opt_rest = None;
+        let mut too_long_rest = None;
 
         for uint::range(0, whole_chunk.len()) |i| {
           if whole_chunk[i] == 0 {
@@ -255,16 +316,24 @@ impl HeaderDecoder {
             break
           } else {
             data.push(whole_chunk[i]);
+            if data.len() > max_len {
+              too_long_rest = Some(whole_chunk.slice(i + 1, whole_chunk.len()));
+              break
+            }
           }
         }
 
         match opt_rest {
-          None       => (Left((true, data)), None),
-          Some(rest) => (Right(str::from_bytes_owned(data)), Some(rest)),
+          None       => (Left((true, data)), too_long_rest),
+          Some(rest) => (Right(data), Some(rest)),
         }
+      };
+      match consumed {
+        Ok(()) => Ok(res),
+        Err(err) => Err(err),
       }
     } else {
-      Left((false, read_yet))
+      Ok(Left((false, read_yet)))
     }
   }
     
@@ -273,7 +342,7 @@ impl HeaderDecoder {
   {
     if header.has_crc {
       if reader.has_bytes(2) {
-        let read_crc  = reader.read_u16_le();
+        let read_crc  = try_read!(reader.read_u16_le());
         let actual_crc = (header.crc32().crc32() & 0xff_ff) as u16;
         if read_crc == actual_crc {
           (true, EndStage)
@@ -293,8 +362,11 @@ impl HeaderDecoder {
 #[cfg(test)]
 mod test {
   use gzip::test_helpers::*;
+  use gzip::hdr_decoder;
   use gzip::header;
   use gzip::error;
+  use gzip::limits;
+  use bits::status;
   use std::uint;
 
   fn header(f: &once fn(&mut header::Header)) -> ~header::Header {
@@ -303,6 +375,40 @@ mod test {
     header
   }
 
+  #[test]
+  fn test_decode_header_feed() {
+    let mut decoder = hdr_decoder::HeaderDecoder::new();
+    match decoder.feed(&[0x1f, 0x8b, 0x08, 0x00, 0x21, 0x43]) {
+      status::NeedInput => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+    let expected = do header |h| {
+        h.extra_flags = 0xab;
+        h.mtime = Some(0x87654321);
+        h.system = Some(header::Amiga);
+      };
+    match decoder.feed(&[0x65, 0x87, 0xab, 0x01, 42]) {
+      status::Finished(h, [42]) => assert_eq!(h, expected.clone()),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+    // later calls return the same header
+    match decoder.feed(&[43]) {
+      status::Finished(h, [43]) => assert_eq!(h, expected.clone()),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+
+    let mut decoder = hdr_decoder::HeaderDecoder::new();
+    match decoder.feed(&[0x1f, 0x8a, 3, 4, 5, 6, 7, 8, 9, 10, 11]) {
+      status::Error(~error::BadMagicNumber(0x8b_1f, 0x8a_1f), [11]) => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+    // the error stays
+    match decoder.feed(&[12]) {
+      status::Error(~error::BadMagicNumber(0x8b_1f, 0x8a_1f), [12]) => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
   #[test]
   fn test_decode_header_ok() {
     { // blank header
@@ -441,7 +547,7 @@ mod test {
           100, 101, 99, 111, 100, 101, 114, 46, 114, 115, 0,
       ]),
       do header |h| {
-        h.file_name = Some(~"decoder.rs");
+        h.file_name = Some("decoder.rs".as_bytes().to_owned());
       }
     );
   }
@@ -455,11 +561,28 @@ mod test {
           121, 32, 99, 111, 109, 112, 114, 115, 114, 0,
       ]),
       do header |h| {
-        h.comment = Some(~"Created by comprsr");
+        h.comment = Some("Created by comprsr".as_bytes().to_owned());
       }
     );
   }
 
+  #[test]
+  fn test_decode_header_latin1() {
+    let h = decode_hdr_ok(&[
+        0x1f, 0x8b, 8, 0b000_11000,
+        0, 0, 0, 0, 0, 255,
+          99, 97, 102, 0xe9, 0,
+          0xc3, 0xa9, 0xff, 0,
+      ]);
+    assert_eq!(h.file_name, Some(~[99, 97, 102, 0xe9]));
+    assert_eq!(h.comment, Some(~[0xc3, 0xa9, 0xff]));
+
+    assert_eq!(h.file_name_str(header::Latin1), Some(~"caf\u00e9"));
+    assert_eq!(h.file_name_str(header::Utf8OrLatin1), Some(~"caf\u00e9"));
+    assert_eq!(h.comment_str(header::Latin1), Some(~"\u00c3\u00a9\u00ff"));
+    assert_eq!(h.comment_str(header::Utf8OrLatin1), Some(~"\u00c3\u00a9\u00ff"));
+  }
+
   #[test] 
   fn test_decode_header_crc() {
     { // CRC is ok
@@ -477,7 +600,7 @@ mod test {
           h.extras = Some(~[
               header::Extra { id: (2, 3), data: ~[1, 2, 3, 5, 8], }
             ]);
-          h.comment = Some(~"Fibonacci");
+          h.comment = Some("Fibonacci".as_bytes().to_owned());
           h.has_crc = true;
         }
       );
@@ -540,9 +663,51 @@ mod test {
             121, 32, 99, 111, 109, 112, 114, 115, 114, 0,
         ]),
         do header |h| {
-          h.comment = Some(~"Created by comprsr");
+          h.comment = Some("Created by comprsr".as_bytes().to_owned());
         }
       );
     }
   }
+
+  #[test]
+  fn test_decode_header_limits() {
+    fn decode_limited(limits: limits::Limits, bytes: &[u8])
+      -> Result<~header::Header, ~error::Error>
+    {
+      let decoder = hdr_decoder::HeaderDecoder::new().with_limits(limits);
+      match decoder.input(bytes) {
+        Right((res, _)) => res,
+        other => fail!(fmt!("unexpected %?", other)),
+      }
+    }
+
+    let bytes = [
+        0x1fu8, 0x8b, 8, 0b000_11100,
+        0, 0, 0, 0, 0, 255,
+        4, 0,
+          1, 2, 0, 0,
+        97, 98, 99, 0,
+        100, 101, 0,
+      ];
+    let unlimited = limits::Limits::unlimited();
+    assert!(decode_limited(unlimited.clone(), bytes).is_ok());
+
+    let mut limits = unlimited.clone();
+    limits.max_extras_len = Some(4);
+    limits.max_file_name_len = Some(3);
+    limits.max_comment_len = Some(2);
+    assert!(decode_limited(limits, bytes).is_ok());
+
+    let mut limits = unlimited.clone();
+    limits.max_extras_len = Some(3);
+    assert_eq!(decode_limited(limits, bytes), Err(~error::ExtrasTooLong(4, 3)));
+
+    let mut limits = unlimited.clone();
+    limits.max_file_name_len = Some(2);
+    assert_eq!(decode_limited(limits, bytes), Err(~error::FileNameTooLong(2)));
+
+    let mut limits = unlimited.clone();
+    limits.max_comment_len = Some(1);
+    assert_eq!(decode_limited(limits, bytes), Err(~error::CommentTooLong(1)));
+  }
 }
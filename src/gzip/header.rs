@@ -1,12 +1,15 @@
+use bits::recv;
 use checksums::crc32;
+use gzip::error;
+use std::{str, vec};
 
 #[deriving(Eq, Clone)]
 pub struct Header {
   is_text: bool,
   has_crc: bool,
   extras: Option<~[Extra]>,
-  file_name: Option<~str>,
-  comment: Option<~str>,
+  file_name: Option<~[u8]>, // raw bytes, see `Charset`
+  comment: Option<~[u8]>, // raw bytes, see `Charset`
   extra_flags: u8,
   system: Option<System>,
   mtime: Option<u32>, // TODO: use some date type
@@ -51,8 +54,80 @@ impl Header {
     }
   }
 
+  /// Decodes the file name, see `Charset::decode`.
+  pub fn file_name_str(&self, charset: Charset) -> Option<~str> {
+    match self.file_name {
+      Some(ref file_name) => Some(charset.decode(*file_name)),
+      None => None,
+    }
+  }
+
+  /// Decodes the comment, see `Charset::decode`.
+  pub fn comment_str(&self, charset: Charset) -> Option<~str> {
+    match self.comment {
+      Some(ref comment) => Some(charset.decode(*comment)),
+      None => None,
+    }
+  }
+
+  pub fn set_file_name_str(&mut self, file_name: &str, charset: Charset) {
+    self.file_name = Some(charset.encode(file_name));
+  }
+
+  pub fn set_comment_str(&mut self, comment: &str, charset: Charset) {
+    self.comment = Some(charset.encode(comment));
+  }
+
+  /// Serializes the header, including the header CRC16 if `has_crc` is set.
+  /// Nothing is written if the header cannot be encoded (see `check`).
+  pub fn write<R: recv::Recv<u8>>(&self, recv: R)
+    -> (Result<(), ~error::Error>, R)
+  {
+    match self.check() {
+      Ok(()) => { },
+      Err(err) => return (Err(err), recv),
+    }
+
+    let recv = self.write_fields(recv);
+    let recv = if self.has_crc {
+        let crc16 = self.crc32().crc32() & 0xff_ff;
+        recv.receive(&[crc16 as u8, (crc16 >> 8) as u8])
+      } else {
+        recv
+      };
+    (Ok(()), recv)
+  }
+
+  /// Checks that the header can be encoded: the extra field must fit into the
+  /// 16-bit XLEN, which also keeps every subfield within its 16-bit LEN, and
+  /// the file name and comment must not contain a zero byte, which would end
+  /// them early.
+  pub fn check(&self) -> Result<(), ~error::Error> {
+    match self.extras {
+      Some(ref extras) if extras_len(*extras) > 0xff_ff =>
+        return Err(~error::ExtrasTooLong(extras_len(*extras), 0xff_ff)),
+      _ => { },
+    }
+    match self.file_name {
+      Some(ref file_name) if file_name.contains(&0) =>
+        return Err(~error::ZeroInFileName),
+      _ => { },
+    }
+    match self.comment {
+      Some(ref comment) if comment.contains(&0) =>
+        return Err(~error::ZeroInComment),
+      _ => { },
+    }
+    Ok(())
+  }
+
+  /// CRC32 of the header bytes that precede the header CRC16.
   pub fn crc32(&self) -> crc32::Crc32 {
-    let mut crc = crc32::Crc32::new();
+    self.write_fields(crc32::Crc32::new())
+  }
+
+  fn write_fields<R: recv::Recv<u8>>(&self, recv: R) -> R {
+    let mut recv = recv;
     let flg = 
         if self.is_text             { 0b1 } else {0}
       | if self.has_crc             { 0b10 } else {0}
@@ -60,8 +135,8 @@ impl Header {
       | if self.file_name.is_some() { 0b1000 } else {0}
       | if self.comment.is_some()   { 0b1_0000 } else {0} ;
 
-    crc = crc.update(&[0x1f, 0x8b, 8, flg]);
-    crc = crc.update(match self.mtime {
+    recv = recv.receive(&[0x1f, 0x8b, 8, flg]);
+    recv = recv.receive(match self.mtime {
       Some(mtime) => &[
           (mtime      ) as u8,
           (mtime >> 8 ) as u8,
@@ -70,21 +145,20 @@ impl Header {
         ],
       None => &[0, 0, 0, 0],
     });
-    crc = crc.update(&[self.extra_flags, System::to_number(self.system)]);
+    recv = recv.receive(&[self.extra_flags, System::to_number(self.system)]);
 
     match self.extras {
       None => { },
       Some(ref extras) => {
-        let xlen = do extras.iter().fold(0) 
-          |xlen, extra| { xlen + 4 + extra.data.len() };
-        crc = crc.update(&[xlen as u8, (xlen >> 8) as u8]);
+        let xlen = extras_len(*extras);
+        recv = recv.receive(&[xlen as u8, (xlen >> 8) as u8]);
 
         for extras.iter().advance |extra| {
           let (id1, id2) = extra.id;
           let len = extra.data.len();
-          crc = crc.update(&[id1, id2]);
-          crc = crc.update(&[len as u8, (len >> 8) as u8]);
-          crc = crc.update(extra.data);
+          recv = recv.receive(&[id1, id2]);
+          recv = recv.receive(&[len as u8, (len >> 8) as u8]);
+          recv = recv.receive(extra.data);
         }
       },
     };
@@ -92,20 +166,78 @@ impl Header {
     match self.file_name {
       None => { },
       Some(ref file_name) => {
-        crc = crc.update(file_name.as_bytes());
-        crc = crc.update(&[0]);
+        recv = recv.receive(*file_name);
+        recv = recv.receive(&[0]);
       },
     };
 
     match self.comment {
       None => { },
       Some(ref comment) => {
-        crc = crc.update(comment.as_bytes());
-        crc = crc.update(&[0]);
+        recv = recv.receive(*comment);
+        recv = recv.receive(&[0]);
       },
     };
 
-    crc
+    recv
+  }
+}
+
+// XLEN of the extra field with the subfield headers
+fn extras_len(extras: &[Extra]) -> uint {
+  do extras.iter().fold(0) |xlen, extra| { xlen + 4 + extra.data.len() }
+}
+
+/// Character set of the file name and comment. RFC 1952 requires ISO 8859-1,
+/// but some programs write UTF-8.
+#[deriving(Eq, Clone)]
+pub enum Charset {
+  Latin1(),
+  /// UTF-8 if the bytes are valid UTF-8, otherwise ISO 8859-1. Encodes to
+  /// UTF-8.
+  Utf8OrLatin1(),
+}
+
+impl Charset {
+  /// Decodes the bytes of a header field. Never fails, because any bytes are
+  /// valid ISO 8859-1.
+  pub fn decode(&self, bytes: &[u8]) -> ~str {
+    match *self {
+      Utf8OrLatin1 if str::is_utf8(bytes) =>
+        str::from_bytes(bytes),
+      _ => {
+        let mut text = str::with_capacity(bytes.len());
+        for bytes.iter().advance |&byte| {
+          text.push_char(byte as char);
+        }
+        text
+      },
+    }
+  }
+
+  /// Encodes text into a header field. Zero characters (which would end the
+  /// field) and characters that ISO 8859-1 lacks are replaced with `?`.
+  pub fn encode(&self, text: &str) -> ~[u8] {
+    match *self {
+      Latin1 => {
+        let mut bytes = vec::with_capacity(text.len());
+        for text.iter().advance |ch| {
+          if ch == '\0' || ch as uint > 0xff {
+            bytes.push('?' as u8);
+          } else {
+            bytes.push(ch as u8);
+          }
+        }
+        bytes
+      },
+      Utf8OrLatin1 => {
+        let mut utf8 = str::with_capacity(text.len());
+        for text.iter().advance |ch| {
+          utf8.push_char(if ch == '\0' { '?' } else { ch });
+        }
+        utf8.as_bytes().to_owned()
+      },
+    }
   }
 }
 
@@ -183,3 +315,36 @@ impl ToStr for System {
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use gzip::header::*;
+
+  #[test]
+  fn test_charset_decode() {
+    assert_eq!(Latin1.decode(&[97, 0xe9, 0xff]), ~"a\u00e9\u00ff");
+    assert_eq!(Latin1.decode(&[0xc3, 0xa9]), ~"\u00c3\u00a9");
+    assert_eq!(Utf8OrLatin1.decode(&[0xc3, 0xa9]), ~"\u00e9");
+    assert_eq!(Utf8OrLatin1.decode(&[97, 0xe9]), ~"a\u00e9");
+  }
+
+  #[test]
+  fn test_charset_encode() {
+    assert_eq!(Latin1.encode("caf\u00e9"), ~[99, 97, 102, 0xe9]);
+    assert_eq!(Latin1.encode("a\u20acb\x00c"), ~[97, 63, 98, 63, 99]);
+    assert_eq!(Utf8OrLatin1.encode("\u00e9\x00\u20ac"),
+      ~[0xc3, 0xa9, 63, 0xe2, 0x82, 0xac]);
+  }
+
+  #[test]
+  fn test_set_file_name_str() {
+    let mut header = Header::blank();
+    header.set_file_name_str("\u00e9t\u00e9.txt", Latin1);
+    assert_eq!(header.file_name, Some(~[0xe9, 116, 0xe9, 46, 116, 120, 116]));
+    assert_eq!(header.file_name_str(Latin1), Some(~"\u00e9t\u00e9.txt"));
+
+    header.set_comment_str("\u00e9", Utf8OrLatin1);
+    assert_eq!(header.comment, Some(~[0xc3, 0xa9]));
+    assert_eq!(header.comment_str(Utf8OrLatin1), Some(~"\u00e9"));
+  }
+}
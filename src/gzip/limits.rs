@@ -0,0 +1,26 @@
+use inflate;
+
+/// Limits for decoding gzip files, see `inflate::limits::Limits`. `None`
+/// means unlimited.
+#[deriving(Clone, Eq)]
+pub struct Limits {
+  /// Limits of the decompressed data.
+  inflate: inflate::limits::Limits,
+  /// Maximal length of the extra field (XLEN).
+  max_extras_len: Option<uint>,
+  /// Maximal length of the file name, without the terminating zero.
+  max_file_name_len: Option<uint>,
+  /// Maximal length of the comment, without the terminating zero.
+  max_comment_len: Option<uint>,
+}
+
+impl Limits {
+  pub fn unlimited() -> Limits {
+    Limits {
+      inflate: inflate::limits::Limits::unlimited(),
+      max_extras_len: None,
+      max_file_name_len: None,
+      max_comment_len: None,
+    }
+  }
+}
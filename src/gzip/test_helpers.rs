@@ -1,7 +1,70 @@
+use gzip::decoder;
+use gzip::encoder;
 use gzip::hdr_decoder;
 use gzip::body_decoder;
 use gzip::header;
 use gzip::error;
+use bits;
+use bits::recv;
+use std::rt::io::{Reader, io_error};
+use std::rt::io::mem::{MemReader};
+use std::{vec, cmp};
+
+/// Receiver that collects the data and fails every other call, starting
+/// with the first.
+pub struct FlakyRecv {
+  buf: ~[u8],
+  priv fail: bool,
+}
+
+impl FlakyRecv {
+  pub fn new() -> FlakyRecv {
+    FlakyRecv { buf: ~[], fail: false }
+  }
+}
+
+impl recv::TryRecv<u8> for FlakyRecv {
+  pub fn try_receive(self, xs: &[u8]) -> Result<FlakyRecv, FlakyRecv> {
+    let mut recv = self;
+    recv.fail = !recv.fail;
+    if recv.fail {
+      Err(recv)
+    } else {
+      recv.buf.push_all(xs);
+      Ok(recv)
+    }
+  }
+}
+
+/// Decodes `bytes` with a `bits::io::DecompressReader` that reads `buf_size`
+/// bytes at a time. Returns the decoded data, the error that stopped the
+/// decoding (which is not raised) and the input after the end of the data.
+pub fn decompress_bytes<E: Clone + ToStr, D: bits::io::BufDecoder<E>>
+  (decoder: D, bytes: ~[u8], buf_size: uint) -> (~[u8], Option<E>, ~[u8])
+{
+  let mut reader = bits::io::DecompressReader::new(MemReader::new(bytes),
+    decoder);
+  let mut out = ~[];
+  let mut buf = vec::from_elem(buf_size, 0u8);
+  do io_error::cond.trap(|_| { }).in {
+    loop {
+      match reader.read(buf.mut_slice(0, buf_size)) {
+        Some(len) => out.push_all(buf.slice(0, len)),
+        None => break,
+      }
+    }
+  }
+  let err = reader.error();
+  let (_, rest) = reader.close();
+  (out, err, rest)
+}
+
+/// Compresses `bytes` into a gzip file with a blank header.
+pub fn encode_blank(bytes: &[u8]) -> ~[u8] {
+  let encoder = encoder::Encoder::new(~header::Header::blank(), 6).unwrap();
+  let (encoder, out) = encoder.input(bytes, ~[]);
+  encoder.finish(out)
+}
 
 pub fn decode_hdr_ok(bytes: &[u8]) -> ~header::Header {
   let decoder = hdr_decoder::HeaderDecoder::new();
@@ -73,3 +136,62 @@ pub fn decode_body_chunked_ok(chunk_len: uint, bytes: &[u8]) -> ~[u8] {
     }
   };
 }
+
+pub fn decode_body_into_ok(chunk_len: uint, buf_len: uint, bytes: &[u8])
+  -> ~[u8]
+{
+  let mut decoder = body_decoder::BodyDecoder::new();
+  let mut buf = vec::from_elem(buf_len, 0u8);
+  let mut out: ~[u8] = ~[];
+  let mut pos = 0;
+
+  loop {
+    let end = cmp::min(pos + chunk_len, bytes.len());
+    let (result, consumed, written) =
+      decoder.input_into(bytes.slice(pos, end), buf.mut_slice(0, buf_len));
+    out.push_all(buf.slice(0, written));
+    pos = pos + consumed;
+
+    match result {
+      Left(_) if pos == bytes.len() && consumed == 0 && written == 0 =>
+        fail!("decode_body_into_ok: decoder did not finish"),
+      Left(new_decoder) => { decoder = new_decoder },
+      Right(Ok(())) if pos == bytes.len() => { return out },
+      x => fail!(fmt!("decode_body_into_ok: unexpected %?", x)),
+    }
+  }
+}
+
+pub fn decode_ok(bytes: &[u8]) -> (~[u8], ~[decoder::Member]) {
+  let decoder = decoder::Decoder::new();
+  match decoder.input(bytes, ~[], ~[]) {
+    (Left(decoder), data, members) => match decoder.finish() {
+      Ok(()) => (data, members),
+      Err(err) => fail!(fmt!("decode_ok: finish failed with %?", err)),
+    },
+    other => fail!(fmt!("decode_ok: unexpected %?", other)),
+  }
+}
+
+pub fn decode_chunked_ok(chunk_len: uint, bytes: &[u8])
+  -> (~[u8], ~[decoder::Member])
+{
+  let mut decoder = decoder::Decoder::new();
+  let mut data: ~[u8] = ~[];
+  let mut members: ~[decoder::Member] = ~[];
+
+  for bytes.chunk_iter(chunk_len).advance |chunk| {
+    let (result, new_data, new_members) = decoder.input(chunk, data, members);
+    data = new_data;
+    members = new_members;
+    match result {
+      Left(new_decoder) => { decoder = new_decoder },
+      x => fail!(fmt!("decode_chunked_ok: unexpected %?", x)),
+    }
+  }
+
+  match decoder.finish() {
+    Ok(()) => (data, members),
+    Err(err) => fail!(fmt!("decode_chunked_ok: finish failed with %?", err)),
+  }
+}
@@ -4,9 +4,11 @@ use inflate::out;
 
 pub enum CompressedPhase {
   LitlenPhase(),
+  LiteralPhase(u8), /* (literal waiting for output space) */
   LenExtraPhase(uint,uint), /* (base_len,extra_bits) */
   DistPhase(uint), /* (len) */
   DistExtraPhase(uint,uint,uint), /* (len,base_dist,extra_bits) */
+  CopyPhase(uint,uint), /* (remaining len,dist) */
   ErrorPhase(~error::Error),
   EndPhase(),
 }
@@ -25,9 +27,9 @@ pub struct ComprState<C> {
 
 pub trait Coder {
   fn read_litlen_code(&self, bit_reader: &mut bits::BitReader)
-    -> Option<uint>;
+    -> Result<Option<uint>, ~error::Error>;
   fn read_dist_code(&self, bit_reader: &mut bits::BitReader) 
-    -> Option<uint>;
+    -> Result<Option<uint>, ~error::Error>;
 }
 
 impl<C: Coder> ComprState<C> {
@@ -38,13 +40,15 @@ impl<C: Coder> ComprState<C> {
     }
   }
 
-  pub fn input<R: bits::recv::Recv<u8>> (
+  /// Stops also when the receiver fails, the data are then sent again when
+  /// the input continues.
+  pub fn input<R: bits::recv::TryRecv<u8>> (
     self,
     bit_reader: &mut bits::BitReader,
     out: &mut out::Output,
     recv: R
   ) 
-    -> (Either<ComprState<C>, Result<(), ~error::Error>>, R)
+    -> (Either<ComprState<C>, Result<(), ~error::Error>>, Result<R, R>)
   {
     let mut st = self;
     let mut recv = recv;
@@ -53,12 +57,22 @@ impl<C: Coder> ComprState<C> {
       let (continue, next_phase) = match st.phase {
         LitlenPhase() => {
           match st.coder.read_litlen_code(bit_reader) {
-            Some(code) => match decode_litlen(code) {
+            Ok(Some(code)) => match decode_litlen(code) {
                 Ok(litlen) => match litlen {
-                  LiteralCode(byte) => {
-                    recv = out.send_literal(byte, recv);
-                    (true, LitlenPhase)
+                  LiteralCode(byte) if out.avail() > 0 => {
+                    match out.send_literal(byte, recv) {
+                      Ok(new_recv) => {
+                        recv = new_recv;
+                        (true, LitlenPhase)
+                      },
+                      Err(new_recv) => {
+                        st.phase = LiteralPhase(byte);
+                        return (Left(st), Err(new_recv))
+                      },
+                    }
                   },
+                  LiteralCode(byte) =>
+                    (false, LiteralPhase(byte)),
                   LengthCode(len, 0) =>
                     (true, DistPhase(len)),
                   LengthCode(len_base, len_extra_bits) =>
@@ -69,58 +83,103 @@ impl<C: Coder> ComprState<C> {
                 Err(err) =>
                   (true, ErrorPhase(err))
               },
-            None => (false, LitlenPhase),
+            Ok(None) => (false, LitlenPhase),
+            Err(err) => (true, ErrorPhase(err)),
+          }
+        },
+        LiteralPhase(byte) => {
+          if out.avail() > 0 {
+            match out.send_literal(byte, recv) {
+              Ok(new_recv) => {
+                recv = new_recv;
+                (true, LitlenPhase)
+              },
+              Err(new_recv) => {
+                st.phase = LiteralPhase(byte);
+                return (Left(st), Err(new_recv))
+              },
+            }
+          } else {
+            (false, LiteralPhase(byte))
           }
         },
         LenExtraPhase(len_base, len_extra_bits) => {
           if bit_reader.has_bits(len_extra_bits) {
-            let extra = bit_reader.read_bits8(len_extra_bits);
-            (true, DistPhase(len_base + extra as uint))
+            match bit_reader.read_bits8(len_extra_bits) {
+              Ok(extra) => (true, DistPhase(len_base + extra as uint)),
+              Err(err) => (true, ErrorPhase(~error::InternalError(err))),
+            }
           } else {
             (false, LenExtraPhase(len_base, len_extra_bits))
           }
         },
         DistPhase(len) => {
           match st.coder.read_dist_code(bit_reader) {
-            Some(dist_code) => match decode_dist(dist_code) {
+            Ok(Some(dist_code)) => match decode_dist(dist_code) {
               Ok((dist_base, dist_extra_bits)) =>
                 (true, DistExtraPhase(len, dist_base, dist_extra_bits)),
               Err(err) =>
                 (true, ErrorPhase(err)),
             },
-            None => (false, DistPhase(len)),
+            Ok(None) => (false, DistPhase(len)),
+            Err(err) => (true, ErrorPhase(err)),
           }
         },
         DistExtraPhase(len, dist_base, dist_extra_bits) => {
           if bit_reader.has_bits(dist_extra_bits) {
-            let dist_extra = bit_reader.read_bits16(dist_extra_bits);
-            let dist = dist_base + dist_extra as uint;
-            let (res, new_recv) = out.back_reference(dist, len, recv);
-            recv = new_recv;
-            match res {
-              Ok(()) =>(true, LitlenPhase),
-              Err(err) =>(true, ErrorPhase(err)),
+            match bit_reader.read_bits16(dist_extra_bits) {
+              Ok(dist_extra) => {
+                let dist = dist_base + dist_extra as uint;
+                (true, CopyPhase(len, dist))
+              },
+              Err(err) => (true, ErrorPhase(~error::InternalError(err))),
             }
           } else {
             (false, DistExtraPhase(len, dist_base, dist_extra_bits))
           }
         },
+        CopyPhase(len, dist) => {
+          let (res, recv_res) = out.back_reference(dist, len, recv);
+          match recv_res {
+            Ok(new_recv) => recv = new_recv,
+            Err(new_recv) => {
+              let copied = match res { Ok(copied) => copied, Err(_) => 0 };
+              st.phase = CopyPhase(len - copied, dist);
+              return (Left(st), Err(new_recv))
+            },
+          }
+          match res {
+            Ok(copied) if copied == len => (true, LitlenPhase),
+            Ok(copied) => (false, CopyPhase(len - copied, dist)),
+            Err(err) => (true, ErrorPhase(err)),
+          }
+        },
         EndPhase() => {
-          return (Right(Ok(())), out.flush(recv))
+          return (Right(Ok(())), Ok(recv))
         },
         ErrorPhase(err) => {
-          return (Right(Err(err)), out.flush(recv))
+          return (Right(Err(err)), Ok(recv))
         },
       };
 
       st.phase = next_phase;
       if !continue {
-        return (Left(st), out.flush(recv))
+        return (Left(st), Ok(recv))
       }
     }
   }
 }
 
+impl<C> ComprState<C> {
+  /// Whether the state is stopped only because there is no space for output.
+  pub fn has_pending_output(&self) -> bool {
+    match self.phase {
+      LiteralPhase(_) | CopyPhase(*) => true,
+      _ => false,
+    }
+  }
+}
+
 pub fn decode_litlen(code: uint) -> Result<LitlenCode,~error::Error> {
   if code < 256 {
     Ok(LiteralCode(code as u8))
@@ -0,0 +1,350 @@
+use bits;
+use inflate::compressed;
+use inflate::dynamic;
+use inflate::error;
+use inflate::fixed;
+use inflate::inflater;
+use inflate::out;
+use bits::recv::Infallible;
+
+/// What the disassembler finds in a deflate stream, in the stream order.
+#[deriving(Clone, Eq)]
+pub enum Event {
+  BlockBegin(u64, bool, uint), /* (bit offset, bfinal, btype) */
+  StoredHeader(uint), /* (len) */
+  DynamicHeader(dynamic::HeaderLens),
+  Literal(u8),
+  Match(uint, uint), /* (len, dist) */
+  BlockEnd(u64, u64), /* (bit offset, output length so far) */
+}
+
+impl ToStr for Event {
+  fn to_str(&self) -> ~str {
+    match *self {
+      BlockBegin(offset, bfinal, btype) =>
+        fmt!("block at bit %s: %s%s", offset.to_str(),
+          match btype {
+            0b00 => ~"stored",
+            0b01 => ~"fixed",
+            0b10 => ~"dynamic",
+            _ => fmt!("type %u", btype),
+          },
+          if bfinal { " (last)" } else { "" }),
+      StoredHeader(len) =>
+        fmt!("  len %u", len),
+      DynamicHeader(ref lens) =>
+        fmt!("  hlit %u, hdist %u, hclen %u\n  meta lens %?\n  \
+              litlen lens %?\n  dist lens %?",
+          lens.hlit(), lens.hdist(), lens.hclen(),
+          lens.meta_lens, lens.litlen_lens, lens.dist_lens),
+      Literal(byte) =>
+        fmt!("  literal %u", byte as uint),
+      Match(len, dist) =>
+        fmt!("  match %u %u", len, dist),
+      BlockEnd(offset, out_len) =>
+        fmt!("end at bit %s, %s bytes of output", offset.to_str(),
+          out_len.to_str()),
+    }
+  }
+}
+
+/// Decodes a whole deflate stream, sending the events to `events` and the
+/// decoded data to `recv`. `Literal` and `Match` events are sent only if
+/// `details` is set. Returns the bit offset of the end of the stream, or the
+/// error with the bit offset where it was found.
+pub fn disassemble<E: bits::recv::Recv<Event>, R: bits::recv::Recv<u8>>
+  (bytes: &[u8], details: bool, events: E, recv: R)
+  -> (Result<u64, (~error::Error, u64)>, E, R)
+{
+  let (res, events, recv) = disassemble_blocks(bytes, details, events,
+    bits::recv::Infallible(recv));
+  (res, events, recv.unwrap())
+}
+
+fn disassemble_blocks<E: bits::recv::Recv<Event>, R: bits::recv::Recv<u8>>
+  (bytes: &[u8], details: bool, events: E, recv: Infallible<R>)
+  -> (Result<u64, (~error::Error, u64)>, E, Infallible<R>)
+{
+  let mut reader = bits::BitReader::new(bits::BitBuf::new(), bytes);
+  let mut output = out::Output::new(inflater::window_size);
+  let mut events = events;
+  let mut recv = recv;
+  let mut out_len = 0u64;
+
+  loop {
+    let offset = bit_offset(bytes, &reader);
+    if !reader.has_bits(3) {
+      return (Err(truncated(bytes)), events, recv);
+    }
+    let header = match reader.read_bits8(3) {
+        Ok(header) => header,
+        Err(err) => return (Err((~error::InternalError(err), offset)), events, recv),
+      };
+    let bfinal = header & 0b1 != 0;
+    let btype = (header >> 1) as uint;
+    events = events.receive(&[BlockBegin(offset, bfinal, btype)]);
+
+    let block = match btype {
+      0b00 => {
+        reader.skip_to_byte();
+        if !reader.has_bytes(4) {
+          return (Err(truncated(bytes)), events, recv);
+        }
+        let (len, nlen) = match reader.read_u32() {
+            Ok(lens) => (lens as u16, (lens >> 16) as u16),
+            Err(err) => return (Err((~error::InternalError(err), offset)),
+              events, recv),
+          };
+        if len != !nlen {
+          let err = ~error::VerbatimLengthMismatch(len, nlen);
+          return (Err((err, bit_offset(bytes, &reader))), events, recv);
+        }
+        events = events.receive(&[StoredHeader(len as uint)]);
+
+        if !reader.has_bytes(len as uint) {
+          return (Err(truncated(bytes)), events, recv);
+        }
+        match reader.read_byte_chunk(len as uint) {
+          Ok(chunk) => {
+            let piece_len = output.chunk_avail();
+            let mut pieces = chunk.chunk_iter(piece_len);
+            loop {
+              match pieces.next() {
+                Some(piece) => {
+                  recv = sent(output.reserve(piece.len(), recv));
+                  output.send_literal_chunk(piece);
+                },
+                None => break,
+              }
+            }
+            out_len = out_len + chunk.len() as u64;
+            Ok(())
+          },
+          Err(err) => Err(~error::InternalError(err)),
+        }
+      },
+      0b01 => {
+        let coder = fixed::FixedCoder::new();
+        let (res, new_events, new_recv, len) = disassemble_codes(&coder,
+          &mut reader, &mut output, bytes.len(), details, events, recv);
+        events = new_events;
+        recv = new_recv;
+        out_len = out_len + len;
+        res
+      },
+      0b10 => {
+        match dynamic::HeaderState::new_keeping_lens().input(&mut reader) {
+          Left(_) => Err(~error::TruncatedInput(bytes.len() as u64)),
+          Right(Err(err)) => Err(err),
+          Right(Ok(coder)) => {
+            let lens = coder.lens().unwrap().clone();
+            events = events.receive(&[DynamicHeader(lens)]);
+            let (res, new_events, new_recv, len) = disassemble_codes(&coder,
+              &mut reader, &mut output, bytes.len(), details, events, recv);
+            events = new_events;
+            recv = new_recv;
+            out_len = out_len + len;
+            res
+          },
+        }
+      },
+      _ => Err(~error::BadBlockType(btype)),
+    };
+
+    recv = sent(output.flush(recv));
+    let offset = bit_offset(bytes, &reader);
+    match block {
+      Ok(()) => { },
+      Err(err) => return (Err((err, offset)), events, recv),
+    }
+
+    events = events.receive(&[BlockEnd(offset, out_len)]);
+    if bfinal {
+      return (Ok(offset), events, recv);
+    }
+  }
+}
+
+fn disassemble_codes<C: compressed::Coder,
+    E: bits::recv::Recv<Event>, R: bits::recv::Recv<u8>>
+  (coder: &C, reader: &mut bits::BitReader, output: &mut out::Output,
+    in_len: uint, details: bool, events: E, recv: Infallible<R>)
+  -> (Result<(), ~error::Error>, E, Infallible<R>, u64)
+{
+  let mut events = events;
+  let mut recv = recv;
+  let mut len_sum = 0u64;
+  let truncated = ~error::TruncatedInput(in_len as u64);
+
+  loop {
+    let code = match coder.read_litlen_code(reader) {
+        Ok(Some(code)) => code,
+        Ok(None) => return (Err(truncated.clone()), events, recv, len_sum),
+        Err(err) => return (Err(err), events, recv, len_sum),
+      };
+
+    match compressed::decode_litlen(code) {
+      Ok(compressed::LiteralCode(byte)) => {
+        recv = sent(output.send_literal(byte, recv));
+        len_sum = len_sum + 1;
+        if details {
+          events = events.receive(&[Literal(byte)]);
+        }
+      },
+      Ok(compressed::LengthCode(len_base, len_extra_bits)) => {
+        if !reader.has_bits(len_extra_bits) {
+          return (Err(truncated.clone()), events, recv, len_sum);
+        }
+        let len = match reader.read_bits8(len_extra_bits) {
+            Ok(extra) => len_base + extra as uint,
+            Err(err) => return (Err(~error::InternalError(err)),
+              events, recv, len_sum),
+          };
+
+        let dist_code = match coder.read_dist_code(reader) {
+            Ok(Some(dist_code)) => dist_code,
+            Ok(None) => return (Err(truncated.clone()), events, recv, len_sum),
+            Err(err) => return (Err(err), events, recv, len_sum),
+          };
+        let (dist_base, dist_extra_bits) = match compressed::decode_dist(dist_code) {
+            Ok(dist) => dist,
+            Err(err) => return (Err(err), events, recv, len_sum),
+          };
+        if !reader.has_bits(dist_extra_bits) {
+          return (Err(truncated.clone()), events, recv, len_sum);
+        }
+        let dist = match reader.read_bits16(dist_extra_bits) {
+            Ok(extra) => dist_base + extra as uint,
+            Err(err) => return (Err(~error::InternalError(err)),
+              events, recv, len_sum),
+          };
+
+        let (res, new_recv) = output.back_reference(dist, len, recv);
+        recv = sent(new_recv);
+        match res {
+          Ok(_) => { },
+          Err(err) => return (Err(err), events, recv, len_sum),
+        }
+        len_sum = len_sum + len as u64;
+        if details {
+          events = events.receive(&[Match(len, dist)]);
+        }
+      },
+      Ok(compressed::BlockEndCode) =>
+        return (Ok(()), events, recv, len_sum),
+      Err(err) =>
+        return (Err(err), events, recv, len_sum),
+    }
+  }
+}
+
+/// The disassembler sends the output to an infallible receiver, so it is
+/// always returned in `Ok`.
+fn sent<R>(res: Result<Infallible<R>, Infallible<R>>) -> Infallible<R> {
+  match res {
+    Ok(recv) | Err(recv) => recv,
+  }
+}
+
+fn bit_offset(bytes: &[u8], reader: &bits::BitReader) -> u64 {
+  (bytes.len() * 8 - reader.available_bits()) as u64
+}
+
+fn truncated(bytes: &[u8]) -> (~error::Error, u64) {
+  (~error::TruncatedInput(bytes.len() as u64), bytes.len() as u64 * 8)
+}
+
+#[cfg(test)]
+mod test {
+  use inflate::test_helpers::*;
+  use inflate::disasm::*;
+  use inflate::dynamic;
+
+  fn disasm_ok(bytes: &[u8], details: bool) -> (~[Event], u64) {
+    match disassemble(bytes, details, ~[], ~[]) {
+      (Ok(end), events, out) => {
+        assert_eq!(out, inflate_ok(bytes));
+        (events, end)
+      },
+      (Err(err), _, _) => fail!(fmt!("unexpected %?", err)),
+    }
+  }
+
+  #[test]
+  fn test_disasm_blocks() {
+    let mut blocks = BlockWriter::new(&[]);
+    blocks.stored(&[2, 3, 5, 7], false);
+    blocks.fixed(&[1, 2], 2, 2, true);
+    let bytes = blocks.finish();
+
+    assert_eq!(disasm_ok(bytes, false), (~[
+        BlockBegin(0, false, 0b00),
+        StoredHeader(4),
+        BlockEnd(72, 4),
+        BlockBegin(72, true, 0b01),
+        BlockEnd(72 + 3 + 2 * 8 + 2 * 13 + 7, 522),
+      ], 72 + 3 + 2 * 8 + 2 * 13 + 7));
+
+    let (events, _) = disasm_ok(bytes, true);
+    assert_eq!(events.slice(4, 8).to_owned(), ~[
+        Literal(1), Literal(2), Match(258, 2), Match(258, 2),
+      ]);
+  }
+
+  #[test]
+  fn test_disasm_dynamic() {
+    let bytes = [
+        0b00001101u8, 0b11000101, 0b10110001, 0b00000001, 0b00000000,
+        0b00000000, 0b00001000, 0b11000010, 0b10110000, 0b01010010,
+        0b11111000, 0b11111111, 0b01100110, 0b11001101, 0b10010010,
+        0b10101100, 0b00000001, 0b11011100, 0b10001100, 0b01100010,
+        0b11111101, 0b01001001, 0b00001111
+      ];
+
+    let (events, end) = disasm_ok(bytes, false);
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0].clone(), BlockBegin(0, true, 0b10));
+    assert_eq!(events[2].clone(), BlockEnd(end, 24));
+    match events[1] {
+      DynamicHeader(ref lens) => {
+        assert_eq!(lens.litlen_lens.len(), lens.hlit() + 257);
+        assert_eq!(lens.dist_lens.len(), lens.hdist() + 1);
+        assert_eq!(lens.meta_lens.len(), lens.hclen() + 4);
+      },
+      ref other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_disasm_errors() {
+    let mut blocks = BlockWriter::new(&[]);
+    blocks.fixed(&[1], 1, 4, true);
+    let bytes = blocks.finish();
+
+    match disassemble(bytes, true, ~[], ()) {
+      (Err((~error::ReferenceBeforeStart(4, 258, 1), offset)), events, ()) => {
+        assert_eq!(offset, 3 + 8 + 13);
+        assert_eq!(events, ~[BlockBegin(0, true, 0b01), Literal(1)]);
+      },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+
+    match disassemble(bytes.slice(0, 2), false, ~[], ()) {
+      (Err((~error::TruncatedInput(2), _)), _, ()) => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+
+    match disassemble(&[0b110u8], false, ~[], ()) {
+      (Err((~error::BadBlockType(0b11), 3)), _, ()) => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_header_lens() {
+    let lens = dynamic::HeaderLens {
+      meta_lens: ~[0, ..6], litlen_lens: ~[8, ..260], dist_lens: ~[5, ..2],
+    };
+    assert_eq!((lens.hlit(), lens.hdist(), lens.hclen()), (3, 1, 2));
+  }
+}
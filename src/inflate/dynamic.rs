@@ -9,11 +9,12 @@ pub struct HeaderState {
   priv phase: HeaderPhase,
   priv meta_count: uint,
   priv meta_lens: ~[u8],
-  priv meta_tree: ~huff::Tree,
+  priv meta_table: ~huff::Table,
   priv litlen_count: uint,
   priv dist_count: uint,
   priv code_count: uint, // litlen_count + dist_count
   priv code_lens: ~[u8],
+  priv keep_lens: bool,
 }
 
 enum HeaderPhase {
@@ -50,11 +51,20 @@ impl HeaderState {
   pub fn new() -> HeaderState {
     HeaderState {
       phase: NumbersPhase,
-      meta_count: 0, meta_lens: ~[], meta_tree: ~huff::Tree::new_empty(),
+      meta_count: 0, meta_lens: ~[], meta_table: ~huff::Table::new_empty(),
       litlen_count: 0, dist_count: 0, code_count: 0, code_lens: ~[],
+      keep_lens: false,
     }
   }
 
+  /// Like `new`, but the decoded coder keeps a copy of the code lengths (see
+  /// `DynamicCoder::lens`).
+  pub fn new_keeping_lens() -> HeaderState {
+    let mut st = HeaderState::new();
+    st.keep_lens = true;
+    st
+  }
+
   pub fn input(self, bit_reader: &mut bits::BitReader)
     -> Either<HeaderState, Result<DynamicCoder, ~error::Error>>
   {
@@ -64,30 +74,40 @@ impl HeaderState {
       let (continue, next_phase) = match st.phase {
         NumbersPhase() => {
           if bit_reader.has_bits(14) {
-            let hlit = bit_reader.read_bits8(5);
-            let hdist = bit_reader.read_bits8(5);
-            let hclen = bit_reader.read_bits8(4);
-
-            st.meta_count = hclen as uint + 4;
-            st.litlen_count = hlit as uint + 257;
-            st.dist_count = hdist as uint + 1;
-            st.code_count = st.litlen_count + st.dist_count;
-
-            st.meta_lens.reserve(st.meta_count);
-            st.code_lens.reserve(st.code_count);
-            (true, MetaLensPhase)
+            match bit_reader.read_bits16(14) {
+              Ok(numbers) => {
+                let hlit = numbers & 0b11111;
+                let hdist = (numbers >> 5) & 0b11111;
+                let hclen = numbers >> 10;
+
+                st.meta_count = hclen as uint + 4;
+                st.litlen_count = hlit as uint + 257;
+                st.dist_count = hdist as uint + 1;
+                st.code_count = st.litlen_count + st.dist_count;
+
+                st.meta_lens.reserve(st.meta_count);
+                st.code_lens.reserve(st.code_count);
+                (true, MetaLensPhase)
+              },
+              Err(err) =>
+                (true, ErrorPhase(~error::InternalError(err))),
+            }
           } else {
             (false, NumbersPhase)
           }
         },
         MetaLensPhase() => {
-          while st.meta_lens.len() < st.meta_count && bit_reader.has_bits(3) {
-            let len = bit_reader.read_bits8(3);
-            st.meta_lens.push(len);
-          }
-
           if st.meta_lens.len() >= st.meta_count {
             (true, MetaPhase)
+          } else if bit_reader.has_bits(3) {
+            match bit_reader.read_bits8(3) {
+              Ok(len) => {
+                st.meta_lens.push(len);
+                (true, MetaLensPhase)
+              },
+              Err(err) =>
+                (true, ErrorPhase(~error::InternalError(err))),
+            }
           } else {
             (false, MetaLensPhase)
           }
@@ -99,9 +119,9 @@ impl HeaderState {
             meta_code_lens[code] = len;
           }
 
-          match huff::Tree::new_from_lens(meta_code_lens) {
-            Ok(tree) => {
-              st.meta_tree = ~tree;
+          match huff::Table::new_from_lens(meta_code_lens) {
+            Ok(table) => {
+              st.meta_table = ~table;
               (true, CodeLensPhase)
             },
             Err(err) =>
@@ -110,8 +130,8 @@ impl HeaderState {
         },
         CodeLensPhase() => 
           if st.code_lens.len() < st.code_count {
-            match read_huff_code(bit_reader, st.meta_tree) {
-              Some(code) => match decode_meta(code) {
+            match st.meta_table.read_code(bit_reader) {
+              Ok(Some(code)) => match decode_meta(code) {
                 Ok(LiteralMetaCode(len)) => {
                   st.code_lens.push(len);
                   (true, CodeLensPhase)
@@ -127,26 +147,32 @@ impl HeaderState {
                   (true, CodeLensRepeatPhase(0, count_base, count_extra_bits)),
                 Err(err) => (true, ErrorPhase(err)),
               },
-              None => (false, CodeLensPhase),
+              Ok(None) => (false, CodeLensPhase),
+              Err(err) => (true, ErrorPhase(err)),
             }
           } else {
             (true, EndPhase)
           },
         CodeLensRepeatPhase(len_to_repeat, count_base, count_extra_bits) =>
           if bit_reader.has_bits(count_extra_bits) {
-            let extra = bit_reader.read_bits8(count_extra_bits);
-            let repeat_count = count_base + extra as uint;
-
-            if st.code_lens.len() + repeat_count <= st.code_count {
-              for repeat_count.times() {
-                st.code_lens.push(len_to_repeat);
-              }
-              (true, CodeLensPhase)
-            } else {
-              let err = ~error::MetaRepeatTooLong(
-                  len_to_repeat, repeat_count, st.code_count - st.code_lens.len()
-                );
-              (true, ErrorPhase(err))
+            match bit_reader.read_bits8(count_extra_bits) {
+              Ok(extra) => {
+                let repeat_count = count_base + extra as uint;
+
+                if st.code_lens.len() + repeat_count <= st.code_count {
+                  for repeat_count.times() {
+                    st.code_lens.push(len_to_repeat);
+                  }
+                  (true, CodeLensPhase)
+                } else {
+                  let err = ~error::MetaRepeatTooLong(
+                      len_to_repeat, repeat_count, st.code_count - st.code_lens.len()
+                    );
+                  (true, ErrorPhase(err))
+                }
+              },
+              Err(err) =>
+                (true, ErrorPhase(~error::InternalError(err))),
             }
           } else {
             (false, CodeLensRepeatPhase(len_to_repeat, count_base, count_extra_bits))
@@ -156,15 +182,25 @@ impl HeaderState {
           let dist_slice = st.code_lens.slice(st.litlen_count,
               st.litlen_count + st.dist_count);
 
-          match huff::Tree::new_from_lens(litlen_slice) {
+          match huff::Table::new_from_lens(litlen_slice) {
             Err(err) => (true, ErrorPhase(err)),
-            Ok(litlen_tree) => {
-              match huff::Tree::new_from_lens(dist_slice) {
+            Ok(litlen_table) => {
+              match huff::Table::new_from_lens(dist_slice) {
                 Err(err) => (true, ErrorPhase(err)),
-                Ok(dist_tree) => {
+                Ok(dist_table) => {
+                  let lens = if st.keep_lens {
+                      Some(HeaderLens {
+                        meta_lens: st.meta_lens.clone(),
+                        litlen_lens: litlen_slice.to_owned(),
+                        dist_lens: dist_slice.to_owned(),
+                      })
+                    } else {
+                      None
+                    };
                   let coder = DynamicCoder {
-                    litlen_tree: ~litlen_tree,
-                    dist_tree: ~dist_tree,
+                    litlen_table: ~litlen_table,
+                    dist_table: ~dist_table,
+                    lens: lens,
                   };
                   return Right(Ok(coder));
                 }
@@ -186,51 +222,51 @@ impl HeaderState {
 }
 
 pub struct DynamicCoder {
-  priv litlen_tree: ~huff::Tree,
-  priv dist_tree: ~huff::Tree,
+  priv litlen_table: ~huff::Table,
+  priv dist_table: ~huff::Table,
+  priv lens: Option<HeaderLens>,
+}
+
+/// Code lengths from the header of a dynamic block.
+#[deriving(Clone, Eq)]
+pub struct HeaderLens {
+  /// Lengths of the code length codes, in the order they are stored.
+  meta_lens: ~[u8],
+  litlen_lens: ~[u8],
+  dist_lens: ~[u8],
+}
+
+impl HeaderLens {
+  pub fn hlit(&self) -> uint { self.litlen_lens.len() - 257 }
+  pub fn hdist(&self) -> uint { self.dist_lens.len() - 1 }
+  pub fn hclen(&self) -> uint { self.meta_lens.len() - 4 }
+}
+
+impl DynamicCoder {
+  /// The code lengths, if the header was decoded by a state from
+  /// `HeaderState::new_keeping_lens`.
+  pub fn lens<'a>(&'a self) -> Option<&'a HeaderLens> {
+    match self.lens {
+      Some(ref lens) => Some(lens),
+      None => None,
+    }
+  }
 }
 
 impl compressed::Coder for DynamicCoder {
   fn read_litlen_code(&self, bit_reader: &mut bits::BitReader)
-    -> Option<uint>
+    -> Result<Option<uint>, ~error::Error>
   {
-    read_huff_code(bit_reader, self.litlen_tree)
+    self.litlen_table.read_code(bit_reader)
   }
 
   fn read_dist_code(&self, bit_reader: &mut bits::BitReader) 
-    -> Option<uint>
+    -> Result<Option<uint>, ~error::Error>
   {
-    read_huff_code(bit_reader, self.dist_tree)
+    self.dist_table.read_code(bit_reader)
   }
 }
 
-pub fn read_huff_code(bit_reader: &mut bits::BitReader, huff_tree: &huff::Tree)
-  -> Option<uint>
-{
-  let mut read_data: u16 = 0;
-  let mut read_bits = 0;
-  let mut node = huff_tree.root();
-
-  while !huff_tree.is_leaf(node) {
-    if bit_reader.has_bits(1) {
-      let bit = bit_reader.read_bits8(1);
-      read_data = read_data | (bit as u16 << read_bits);
-      read_bits = read_bits + 1;
-
-      node = if bit == 0 {
-          huff_tree.zero_child(node)
-        } else {
-          huff_tree.one_child(node)
-        };
-    } else {
-      bit_reader.unread_bits16(read_bits, read_data);
-      return None;
-    }
-  }
-
-  Some(huff_tree.leaf_value(node) as uint)
-}
-
 #[cfg(test)]
 mod test {
   use extra::test;
@@ -239,7 +275,6 @@ mod test {
 
   use bits;
   use inflate::huff;
-  use inflate::dynamic;
   use inflate::test_helpers::*;
 
   #[test]
@@ -290,7 +325,7 @@ mod test {
 
   #[bench]
   fn bench_read_huff_code(b: &mut test::BenchHarness) {
-    let tree = huff::Tree::new_from_lens(
+    let table = huff::Table::new_from_lens(
       &[3, 6, 6, 4, 5, 4, 7, 7, 7, 6, 5, 6, 4, 
         5, 4, 6, 7, 6, 6, 6, 7, 5, 5, 6, 5, 6, 
         6, 7, 3, 5, 5]).unwrap();
@@ -300,7 +335,7 @@ mod test {
     do b.iter {
       let mut reader = bits::BitReader::new(bits::BitBuf::new(), bytes);
       for 1000.times {
-        dynamic::read_huff_code(&mut reader, &tree);
+        table.read_code(&mut reader);
       }
     };
   }
@@ -1,3 +1,4 @@
+use bits;
 use std::num::{ToStrRadix};
 
 #[deriving(Clone,Eq)]
@@ -12,6 +13,14 @@ pub enum Error {
   MetaCopyAtStart(),
   MetaRepeatTooLong(u8, uint, uint),
   TooManyHuffCodesError(uint),
+  OutputLimitExceeded(u64),
+  RatioLimitExceeded(u64),
+  BadIndexHeader(),
+  BadIndexVersion(uint),
+  BadIndexPoint(uint),
+  TruncatedIndex(),
+  TruncatedInput(u64),
+  InternalError(bits::error::Error),
 }
 
 impl ToStr for Error {
@@ -42,6 +51,25 @@ impl ToStr for Error {
           len_to_repeat as uint, repeat_count, max_repeat_count),
       TooManyHuffCodesError(code_len) =>
         fmt!("Too many %u-bit huffman codes", code_len),
+      OutputLimitExceeded(max_output) =>
+        fmt!("Output is longer than the limit of %s bytes",
+          max_output.to_str()),
+      RatioLimitExceeded(max_ratio) =>
+        fmt!("Output is more than %s times longer than the input",
+          max_ratio.to_str()),
+      BadIndexHeader() =>
+        fmt!("Bad index header"),
+      BadIndexVersion(version) =>
+        fmt!("Unsupported index version %u", version),
+      BadIndexPoint(idx) =>
+        fmt!("Bad access point %u in the index", idx),
+      TruncatedIndex() =>
+        fmt!("Index is truncated"),
+      TruncatedInput(offset) =>
+        fmt!("Input ended at offset %s before the end of the stream",
+          offset.to_str()),
+      InternalError(ref err) =>
+        fmt!("Internal error: %s", err.to_str()),
     }
   }
 }
@@ -1,112 +1,181 @@
+use bits;
 use inflate::error;
-use std::uint;
+use std::{vec, uint, cmp};
+
+/// Lookup table for decoding Huffman codes, similar to the tables in zlib. The
+/// root table is indexed by the next `root_bits` bits of the input, the longer
+/// codes continue in sub tables that are linked from the root table.
+pub struct Table {
+  priv entries: ~[Entry],
+  priv root_bits: uint,
+  priv max_len: uint,
+}
 
-pub struct Tree {
-  priv nodes: ~[u16],
+#[deriving(Clone)]
+enum Entry {
+  LeafEntry(u16, u8), /* (symbol, code length) */
+  LinkEntry(u16, u8), /* (offset of the sub table, sub table bits) */
+  InvalidEntry(u8), /* (bits needed to detect the invalid code) */
 }
 
-struct Node(u16);
+static max_root_bits: uint = 9;
+
+/// The code returned for bit sequences that are not assigned to any symbol.
+pub static undefined_code: uint = 0xffff >> 1;
 
-impl Tree {
-  pub fn new_empty() -> Tree {
-    Tree { nodes: ~[] }
+impl Table {
+  pub fn new_empty() -> Table {
+    Table { entries: ~[InvalidEntry(0)], root_bits: 0, max_len: 0 }
   }
 
-  pub fn new_from_lens(bit_lens: &[u8]) -> Result<Tree, ~error::Error> {
-    let mut bl_count: ~[int] = ~[0];
-    let mut bl_syms: ~[~[u16]] = ~[~[]];
-    let mut max_bl: uint = 0;
-
-    for bit_lens.iter().enumerate().advance |(sym, &bl8)| {
-      let bl = bl8 as uint;
-      if bl > 0 {
-        if bl > max_bl {
-          bl_count.grow(bl - max_bl, &0);
-          bl_syms.grow(bl - max_bl, &~[]);
-          max_bl = bl;
-        }
-        bl_count[bl] += 1;
-        bl_syms[bl].push(sym as u16);
+  pub fn new_from_lens(bit_lens: &[u8]) -> Result<Table, ~error::Error> {
+    let max_len = do bit_lens.iter().fold(0u) |m, &len| { cmp::max(m, len as uint) };
+    let mut bl_count = vec::from_elem(max_len + 1, 0u);
+    for bit_lens.iter().advance |&len| {
+      if len > 0 {
+        bl_count[len] += 1;
       }
     }
 
-    let mut nodes: ~[u16] = ~[0xffff];
-    let mut front: ~[u16] = ~[0];
-    let mut front_offset: uint = 0;
-
-    for uint::range(1, max_bl+1) |level| {
-      let mut new_front: ~[u16] = ~[];
+    let mut left = 1u;
+    for uint::range(1, max_len + 1) |len| {
+      left = left << 1;
+      if bl_count[len] > left {
+        return Err(~error::TooManyHuffCodesError(len));
+      }
+      left = left - bl_count[len];
+    }
 
-      for uint::range(front_offset, front.len()) |i| {
-        let front_n: u16 = front[i];
-        let zero_n: u16 = nodes.len() as u16;
-        let one_n: u16 = zero_n + 1;
-        nodes[front_n] = zero_n << 1;
+    // canonical codes (RFC 1951, 3.2.2), reversed because the input is read
+    // from the least significant bit
+    let mut next_code = vec::from_elem(max_len + 1, 0u);
+    let mut code = 0u;
+    for uint::range(1, max_len + 1) |len| {
+      code = (code + bl_count[len - 1]) << 1;
+      next_code[len] = code;
+    }
 
-        nodes.grow(2, &0xffff);
-        new_front.push(zero_n);
-        new_front.push(one_n);
+    let mut rev_codes = vec::from_elem(bit_lens.len(), 0u);
+    for bit_lens.iter().enumerate().advance |(sym, &len)| {
+      if len > 0 {
+        rev_codes[sym] = reverse_bits(next_code[len], len as uint);
+        next_code[len] += 1;
       }
+    }
 
-      front = new_front;
-      front_offset = 0;
+    let root_bits = cmp::min(max_len, max_root_bits);
+    let root_size = 1u << root_bits;
+    let root_mask = root_size - 1;
+
+    // the size of every sub table is given by the longest code with its prefix
+    let mut sub_bits = vec::from_elem(root_size, 0u);
+    for bit_lens.iter().enumerate().advance |(sym, &len)| {
+      let len = len as uint;
+      if len > root_bits {
+        let prefix = rev_codes[sym] & root_mask;
+        sub_bits[prefix] = cmp::max(sub_bits[prefix], len - root_bits);
+      }
+    }
 
-      if bl_syms[level].len() <= front.len() {
-        for bl_syms[level].iter().advance |&sym| {
-          let sym_n = front[front_offset];
-          nodes[sym_n] = (sym << 1) | 0b1;
+    let mut entries = vec::from_elem(root_size, InvalidEntry(root_bits as u8));
+    for uint::range(0, root_size) |prefix| {
+      if sub_bits[prefix] > 0 {
+        let bits = sub_bits[prefix];
+        entries[prefix] = LinkEntry(entries.len() as u16, bits as u8);
+        entries.grow(1 << bits, &InvalidEntry((root_bits + bits) as u8));
+      }
+    }
 
-          front_offset = front_offset + 1;
+    for bit_lens.iter().enumerate().advance |(sym, &len8)| {
+      let len = len8 as uint;
+      let rev_code = rev_codes[sym];
+
+      if len == 0 {
+        loop;
+      } else if len <= root_bits {
+        let mut idx = rev_code;
+        while idx < root_size {
+          entries[idx] = LeafEntry(sym as u16, len8);
+          idx = idx + (1 << len);
         }
       } else {
-        return Err(~error::TooManyHuffCodesError(level));
+        let (offset, bits) = match entries[rev_code & root_mask] {
+          LinkEntry(offset, bits) => (offset as uint, bits as uint),
+          _ => fail!(~"unreachable"),
+        };
+        let rest_len = len - root_bits;
+        let mut idx = rev_code >> root_bits;
+        while idx < (1 << bits) {
+          entries[offset + idx] = LeafEntry(sym as u16, len8);
+          idx = idx + (1 << rest_len);
+        }
       }
     }
 
-    Ok(Tree { nodes: nodes })
-  }
-
-  pub fn root(&self) -> Node {
-    Node(0)
-  }
-
-  pub fn zero_child(&self, Node(n): Node) -> Node {
-    Node(self.nodes[n] >> 1)
-  }
-
-  pub fn one_child(&self, Node(n): Node) -> Node {
-    Node((self.nodes[n] >> 1) + 1)
-  }
-
-  pub fn is_leaf(&self, Node(n): Node) -> bool {
-    self.nodes[n] & 0b1 != 0
+    Ok(Table { entries: entries, root_bits: root_bits, max_len: max_len })
   }
 
-  pub fn leaf_value(&self, Node(n): Node) -> u16 {
-    self.nodes[n] >> 1
+  /// Decodes one code. If there are not enough bits in the reader to decide,
+  /// returns `Ok(None)` and leaves the reader untouched.
+  pub fn read_code(&self, bit_reader: &mut bits::BitReader)
+    -> Result<Option<uint>, ~error::Error>
+  {
+    let bits = cmp::min(bit_reader.available_bits(), self.max_len);
+    let data = match bit_reader.peek_bits16(bits) {
+      Ok(data) => data as uint,
+      Err(err) => return Err(~error::InternalError(err)),
+    };
+
+    let entry = match self.entries[data & ((1 << self.root_bits) - 1)] {
+      LinkEntry(offset, sub_bits) if bits > self.root_bits => {
+        let sub_idx = (data >> self.root_bits) & ((1 << sub_bits as uint) - 1);
+        self.entries[offset as uint + sub_idx]
+      },
+      LinkEntry(*) => return Ok(None),
+      entry => entry,
+    };
+
+    let (code, len) = match entry {
+      LeafEntry(sym, len) => (sym as uint, len as uint),
+      InvalidEntry(len) => (undefined_code, len as uint),
+      LinkEntry(*) => fail!(~"unreachable"),
+    };
+
+    if len <= bits {
+      match bit_reader.read_bits16(len) {
+        Ok(_) => Ok(Some(code)),
+        Err(err) => Err(~error::InternalError(err)),
+      }
+    } else {
+      Ok(None)
+    }
   }
+}
 
-  pub fn is_defined(&self, Node(n): Node) -> bool {
-    self.nodes[n] != 0xffff
+fn reverse_bits(code: uint, len: uint) -> uint {
+  let mut rev = 0;
+  let mut code = code;
+  for len.times {
+    rev = (rev << 1) | (code & 1);
+    code = code >> 1;
   }
+  rev
 }
 
-pub static undefined_leaf_value: u16 = 0xffff >> 1;
-
 #[cfg(test)]
 mod test {
+  use bits;
   use inflate::huff;
   use inflate::error;
 
   #[test]
-  fn test_tree_from_bit_lengths() {
-    // example from RFC 1951 with zero-length codes 
+  fn test_table_from_bit_lengths() {
+    // example from RFC 1951 with zero-length codes
 
     let (_a,_b,_c,_x,d,_e,f,_y,g,_h) = (0,1,2,3,4,5,6,7,8,9);
     let bit_lengths = ~[3,3,3,0,3,3,2,0,4,4];
 
     /*
-
     A 3  010
     B 3  011
     C 3  100
@@ -115,71 +184,76 @@ mod test {
     F 2   00
     G 4 1110
     H 4 1111
-
-               . 
-           ---- ----
-          /         \
-         .           .
-        / \         / \
-       /   \       /   \
-      F     .     .     .
-           / \   / \   / \
-          A   B C   D E   .
-                         / \
-                        G   H
      */
 
-    let tree = ~huff::Tree::new_from_lens(bit_lengths).unwrap();
+    let table = ~huff::Table::new_from_lens(bit_lengths).unwrap();
+    // F, D, G
+    let mut reader = bits::BitReader::new(bits::BitBuf::new(),
+      &[0b1111_0100, 0b0000_0000]);
 
-    let n00 = tree.zero_child(tree.zero_child(tree.root()));
-    assert!(tree.is_leaf(n00));
-    assert_eq!(tree.leaf_value(n00), f);
-
-    let n10 = tree.zero_child(tree.one_child(tree.root()));
-    assert!(!tree.is_leaf(n10));
-
-    let n101 = tree.one_child(n10);
-    assert!(tree.is_leaf(n101));
-    assert_eq!(tree.leaf_value(n101), d);
-
-    let n111 = tree.one_child(tree.one_child(tree.one_child(tree.root())));
-    assert!(!tree.is_leaf(n111));
-
-    let n1110 = tree.zero_child(n111);
-    assert!(tree.is_leaf(n1110));
-    assert_eq!(tree.leaf_value(n1110), g);
+    assert_eq!(table.read_code(&mut reader), Ok(Some(f)));
+    assert_eq!(table.read_code(&mut reader), Ok(Some(d)));
+    assert_eq!(table.read_code(&mut reader), Ok(Some(g)));
+    assert!(reader.has_bits(7) && !reader.has_bits(8));
   }
 
   #[test]
-  fn test_tree_from_invalid_bit_lengths() {
+  fn test_table_from_invalid_bit_lengths() {
     /* too many 2's */
     assert_eq!(
-      huff::Tree::new_from_lens([2,2,0,3,3,3,2,0]).get_err(),
+      huff::Table::new_from_lens([2,2,0,3,3,3,2,0]).get_err(),
       ~error::TooManyHuffCodesError(3)
     );
   }
 
   #[test]
-  fn test_unsaturated_tree() {
+  fn test_unsaturated_table() {
     /* two 3-bit codes aren't defined:
-          .        
-         / \       
-        /   \      
-       .     .     
-      / \   / \    
-     2   3 /   \   
-          .     .  
-         / \   / \ 
-        0   1 ?   ?
+      2  00
+      3  01
+      0 100
+      1 101
+      ? 11.
       */
 
-    let tree = ~huff::Tree::new_from_lens([3,3,2,2]).unwrap();
+    let table = ~huff::Table::new_from_lens([3,3,2,2]).unwrap();
+    let mut reader = bits::BitReader::new(bits::BitBuf::new(),
+      &[0b00_011_001]);
 
-    let n01 = tree.one_child(tree.zero_child(tree.root()));
-    assert!(tree.is_defined(n01));
+    assert_eq!(table.read_code(&mut reader), Ok(Some(0)));
+    assert_eq!(table.read_code(&mut reader), Ok(Some(huff::undefined_code)));
+    assert_eq!(table.read_code(&mut reader), Ok(Some(2)));
+  }
 
-    let n110 = tree.zero_child(tree.one_child(tree.one_child(tree.root())));
-    assert!(!tree.is_defined(n110));
-    assert_eq!(tree.leaf_value(n110), huff::undefined_leaf_value);
+  #[test]
+  fn test_long_codes() {
+    // code lengths 1, 2, ..., 15, 15 need sub tables
+    let lens = ~[1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,15];
+    let table = ~huff::Table::new_from_lens(lens).unwrap();
+
+    // 15 (fifteen ones), 0 (zero), 11 (eleven ones and zero)
+    let mut reader = bits::BitReader::new(bits::BitBuf::new(),
+      &[0b1111_1111, 0b0111_1111, 0b1111_1111, 0b0000_0111]);
+    assert_eq!(table.read_code(&mut reader), Ok(Some(15)));
+    assert_eq!(table.read_code(&mut reader), Ok(Some(0)));
+    assert_eq!(table.read_code(&mut reader), Ok(Some(11)));
+  }
+
+  #[test]
+  fn test_read_code_suspends() {
+    let lens = ~[1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,15];
+    let table = ~huff::Table::new_from_lens(lens).unwrap();
+
+    let bit_buf = {
+      let mut reader = bits::BitReader::new(bits::BitBuf::new(), &[0b1111_1111]);
+      assert_eq!(table.read_code(&mut reader), Ok(None));
+      assert!(reader.has_bits(8));
+      reader.close_to_buf().unwrap()
+    };
+
+    let mut reader = bits::BitReader::new(bit_buf, &[0b0111_1111]);
+    assert_eq!(table.read_code(&mut reader), Ok(Some(15)));
+    assert_eq!(table.read_code(&mut reader), Ok(Some(0)));
+    assert_eq!(table.read_code(&mut reader), Ok(None));
   }
 }
@@ -0,0 +1,580 @@
+use bits;
+use inflate::error;
+use inflate::inflater;
+use std::{vec, uint, util, cmp};
+
+// Unwraps the result of a read from a `ByteReader`, an error is returned.
+macro_rules! try_read(
+  ($res:expr) => (
+    match $res {
+      Ok(x) => x,
+      Err(err) => return Err(~error::InternalError(err)),
+    }
+  )
+)
+
+/// A place in a deflate stream where decoding can start without decoding the
+/// preceding data: the start of a block together with the window of output
+/// before it.
+#[deriving(Clone, Eq)]
+pub struct AccessPoint {
+  /// Position of the start of the block in the input, in bits.
+  bit_offset: u64,
+  /// If the block does not start at a byte boundary, the remaining bits of the
+  /// byte where it starts (that is, the byte shifted right by
+  /// `bit_offset % 8`).
+  bits_data: u8,
+  /// Position of the block in the output.
+  out_offset: u64,
+  /// Up to `inflater::window_size` bytes of output preceding the block.
+  window: ~[u8],
+}
+
+impl AccessPoint {
+  /// Offset of the first input byte that must be passed to an inflater
+  /// resumed at this point.
+  pub fn in_offset(&self) -> u64 {
+    (self.bit_offset + 7) / 8
+  }
+}
+
+/// Access points into a deflate stream, spaced by at least `span` bytes of
+/// output. The first point is always at the start of the stream.
+#[deriving(Clone, Eq)]
+pub struct Index {
+  priv span: u64,
+  priv points: ~[AccessPoint],
+}
+
+/// The serialized index starts with "CZIX" and the format version, followed
+/// by the span and the number of points (all integers are little-endian):
+///
+///   magic (4 bytes), version (1), span (8), count (4)
+///
+/// Each point is then stored as:
+///
+///   bit_offset (8), bits_data (1), out_offset (8), window length (2), window
+static index_magic: u32 = 0x58_49_5a_43;
+static index_version: u8 = 1;
+
+impl Index {
+  pub fn span(&self) -> u64 {
+    self.span
+  }
+
+  pub fn points<'a>(&'a self) -> &'a [AccessPoint] {
+    self.points.slice(0, self.points.len())
+  }
+
+  /// Returns the last access point at or before `out_offset`.
+  pub fn find<'a>(&'a self, out_offset: u64) -> &'a AccessPoint {
+    // invariant: points[lo].out_offset <= out_offset < points[hi].out_offset
+    let mut lo = 0;
+    let mut hi = self.points.len();
+    while hi - lo > 1 {
+      let mid = (lo + hi) / 2;
+      if self.points[mid].out_offset <= out_offset {
+        lo = mid;
+      } else {
+        hi = mid;
+      }
+    }
+    &self.points[lo]
+  }
+
+  pub fn write<R: bits::recv::Recv<u8>>(&self, recv: R) -> R {
+    let mut recv = recv;
+    recv = recv.receive(u32_le(index_magic));
+    recv = recv.receive(&[index_version]);
+    recv = recv.receive(u64_le(self.span));
+    recv = recv.receive(u32_le(self.points.len() as u32));
+
+    for self.points.iter().advance |point| {
+      recv = recv.receive(u64_le(point.bit_offset));
+      recv = recv.receive(&[point.bits_data]);
+      recv = recv.receive(u64_le(point.out_offset));
+      let len = point.window.len();
+      recv = recv.receive(&[len as u8, (len >> 8) as u8]);
+      recv = recv.receive(point.window);
+    }
+    recv
+  }
+
+  pub fn read(bytes: &[u8]) -> Result<Index, ~error::Error> {
+    let mut reader = bits::ByteReader::new(bits::ByteBuf::new(), bytes);
+
+    if !reader.has_bytes(17) {
+      return Err(~error::TruncatedIndex);
+    }
+    if try_read!(reader.read_u32_le()) != index_magic {
+      return Err(~error::BadIndexHeader);
+    }
+    let version = try_read!(reader.read_byte());
+    if version != index_version {
+      return Err(~error::BadIndexVersion(version as uint));
+    }
+    let span = try_read!(read_u64_le(&mut reader));
+    let count = try_read!(reader.read_u32_le()) as uint;
+    if span == 0 || count == 0 {
+      return Err(~error::BadIndexHeader);
+    }
+    // each point takes at least 19 bytes, so a count that cannot fit is
+    // rejected before allocating for it
+    if count > reader.available_bytes() / 19 {
+      return Err(~error::TruncatedIndex);
+    }
+
+    let mut points = vec::with_capacity(count);
+    for uint::range(0, count) |i| {
+      if !reader.has_bytes(19) {
+        return Err(~error::TruncatedIndex);
+      }
+      let bit_offset = try_read!(read_u64_le(&mut reader));
+      let bits_data = try_read!(reader.read_byte());
+      let out_offset = try_read!(read_u64_le(&mut reader));
+      let len = try_read!(reader.read_u16_le()) as uint;
+
+      let ordered = if i == 0 {
+          out_offset == 0
+        } else {
+          points[i - 1].out_offset < out_offset
+            && points[i - 1].bit_offset < bit_offset
+        };
+      if !ordered || len > inflater::window_size {
+        return Err(~error::BadIndexPoint(i));
+      }
+      if !reader.has_bytes(len) {
+        return Err(~error::TruncatedIndex);
+      }
+
+      let mut window = vec::with_capacity(len);
+      for len.times {
+        window.push(try_read!(reader.read_byte()));
+      }
+      points.push(AccessPoint {
+        bit_offset: bit_offset,
+        bits_data: bits_data,
+        out_offset: out_offset,
+        window: window,
+      });
+    }
+
+    Ok(Index { span: span, points: points })
+  }
+}
+
+fn u32_le(x: u32) -> ~[u8] {
+  ~[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8]
+}
+
+fn u64_le(x: u64) -> ~[u8] {
+  vec::append(u32_le(x as u32), u32_le((x >> 32) as u32))
+}
+
+fn read_u64_le(reader: &mut bits::ByteReader)
+  -> Result<u64, bits::error::Error>
+{
+  match reader.read_u32_le() {
+    Ok(lo) => match reader.read_u32_le() {
+      Ok(hi) => Ok((hi as u64 << 32) | lo as u64),
+      Err(err) => Err(err),
+    },
+    Err(err) => Err(err),
+  }
+}
+
+/// Builds an index while decoding a stream, like zlib's `zran` example. The
+/// decoded data is passed through to the receiver.
+pub struct Builder {
+  priv inflater: inflater::Inflater,
+  priv in_start: u64,
+  priv span: u64,
+  priv points: ~[AccessPoint],
+}
+
+impl Builder {
+  /// Creates a builder that records an access point at the first block
+  /// boundary after each `span` bytes of output. A span of zero is taken as
+  /// 1, which records a point at every block boundary that follows some
+  /// output.
+  pub fn new(span: u64) -> Builder {
+    Builder {
+      inflater: inflater::Inflater::new(),
+      in_start: 0,
+      span: cmp::max(span, 1),
+      points: ~[AccessPoint {
+        bit_offset: 0, bits_data: 0, out_offset: 0, window: ~[],
+      }],
+    }
+  }
+
+  /// Sets the offset of the deflate stream in the input, so that the offsets
+  /// in the index point into the whole file. For zlib this is the length of
+  /// the zlib header, for gzip the length of the member header (only the
+  /// first member is indexed). Must be called before any input.
+  pub fn with_start(self, in_start: u64) -> Builder {
+    let mut builder = self;
+    builder.in_start = in_start;
+    builder.points[0].bit_offset = in_start * 8;
+    builder
+  }
+
+  pub fn input<'a, R: bits::recv::Recv<u8>>
+    (self, chunk: &'a [u8], recv: R)
+    -> (Either<Builder, (Result<Index, ~error::Error>, &'a [u8])>, R)
+  {
+    let Builder { inflater, in_start, span, points } = self;
+    let mut inflater = inflater;
+    let mut points = points;
+    let mut rest = chunk;
+    let mut recv = recv;
+
+    loop {
+      let (res, new_recv) = inflater.input_block(rest, recv);
+      recv = new_recv;
+
+      match res {
+        Left((new_inflater, new_rest)) => {
+          inflater = new_inflater;
+          rest = new_rest;
+          match inflater.checkpoint() {
+            Some(point) =>
+              if point.out_offset >= points.last().out_offset + span {
+                let mut point = point;
+                point.bit_offset = point.bit_offset + in_start * 8;
+                points.push(point);
+              },
+            None => { },
+          }
+
+          if rest.is_empty() {
+            let builder = Builder {
+              inflater: inflater, in_start: in_start,
+              span: span, points: points,
+            };
+            return (Left(builder), recv)
+          }
+        },
+        Right((Ok(()), rest)) =>
+          return (Right((Ok(Index { span: span, points: points }), rest)), recv),
+        Right((Err(err), rest)) =>
+          return (Right((Err(err), rest)), recv),
+      }
+    }
+  }
+}
+
+/// Random access to the input of an index.
+pub trait Source {
+  /// Reads bytes starting at `offset` into `buf` and returns their number,
+  /// which is zero only at the end of the input.
+  fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> uint;
+}
+
+impl<'self> Source for &'self [u8] {
+  fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> uint {
+    if offset >= self.len() as u64 {
+      return 0;
+    }
+    let start = offset as uint;
+    let len = cmp::min(buf.len(), self.len() - start);
+    vec::bytes::copy_memory(buf, self.slice(start, start + len), len);
+    len
+  }
+}
+
+/// Reads the decompressed data from any position, starting the decoding at
+/// the nearest access point. Sequential reads continue the decoding.
+pub struct Reader<S> {
+  priv index: Index,
+  priv source: S,
+  priv pos: u64,
+  priv state: Option<ReadState>,
+}
+
+struct ReadState {
+  inflater: Option<inflater::Inflater>, // `None` after the end of the stream
+  in_offset: u64,
+  out_offset: u64,
+  input: ~[u8],
+  input_start: uint,
+  input_end: uint,
+}
+
+static input_size: uint = 16_384;
+
+impl<S: Source> Reader<S> {
+  pub fn new(index: Index, source: S) -> Reader<S> {
+    Reader { index: index, source: source, pos: 0, state: None }
+  }
+
+  pub fn seek(&mut self, pos: u64) {
+    self.pos = pos;
+  }
+
+  pub fn tell(&self) -> u64 {
+    self.pos
+  }
+
+  /// Reads data from the current position into `buf`. Returns the number of
+  /// bytes read, which is less than `buf.len()` only at the end of the stream.
+  pub fn read(&mut self, buf: &mut [u8]) -> Result<uint, ~error::Error> {
+    let pos = self.pos;
+    let point_offset = self.index.find(pos).out_offset;
+    let mut state = match util::replace(&mut self.state, None) {
+        Some(state) =>
+          if state.out_offset <= pos && state.out_offset >= point_offset {
+            state
+          } else {
+            ReadState::new(self.index.find(pos))
+          },
+        None => ReadState::new(self.index.find(pos)),
+      };
+
+    if state.out_offset < pos {
+      // decode and throw away the data before `pos`
+      let mut skip_buf = vec::from_elem(input_size, 0u8);
+      while state.out_offset < pos {
+        let len = cmp::min(pos - state.out_offset, input_size as u64) as uint;
+        match state.decode(&mut self.source, skip_buf.mut_slice(0, len)) {
+          Ok(0) => break,
+          Ok(_) => { },
+          Err(err) => return Err(err),
+        }
+      }
+    }
+
+    let read = if state.out_offset == pos {
+        match state.decode(&mut self.source, buf) {
+          Ok(read) => read,
+          Err(err) => return Err(err),
+        }
+      } else {
+        0
+      };
+
+    self.pos = pos + read as u64;
+    self.state = Some(state);
+    Ok(read)
+  }
+}
+
+impl ReadState {
+  fn new(point: &AccessPoint) -> ReadState {
+    ReadState {
+      inflater: Some(inflater::Inflater::resume(point)),
+      in_offset: point.in_offset(),
+      out_offset: point.out_offset,
+      input: vec::from_elem(input_size, 0u8),
+      input_start: 0,
+      input_end: 0,
+    }
+  }
+
+  fn decode<S: Source>(&mut self, source: &mut S, buf: &mut [u8])
+    -> Result<uint, ~error::Error>
+  {
+    let mut written = 0;
+    while written < buf.len() {
+      let inflater = match util::replace(&mut self.inflater, None) {
+          Some(inflater) => inflater,
+          None => break,
+        };
+
+      let mut at_end = false;
+      if self.input_start == self.input_end {
+        let offset = self.in_offset;
+        let len = source.read_at(offset, self.input.mut_slice(0, input_size));
+        self.in_offset = offset + len as u64;
+        self.input_start = 0;
+        self.input_end = len;
+        at_end = len == 0;
+      }
+
+      let (res, consumed, n) = inflater.input_into(
+        self.input.slice(self.input_start, self.input_end),
+        buf.mut_slice(written, buf.len()));
+      self.input_start = self.input_start + consumed;
+      self.out_offset = self.out_offset + n as u64;
+      written = written + n;
+
+      match res {
+        Left(_) if at_end && n == 0 =>
+          return Err(~error::TruncatedInput(self.in_offset)),
+        Left(inflater) => { self.inflater = Some(inflater); },
+        Right(Ok(())) => { },
+        Right(Err(err)) => return Err(err),
+      }
+    }
+    Ok(written)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use inflate::test_helpers::*;
+  use inflate::index;
+  use std::{vec, uint, cmp, rand};
+  use std::rand::{RngUtil};
+
+  // returns the stream and its decoded data
+  fn stream(prefix: &[u8]) -> (~[u8], ~[u8]) {
+    let mut rng = rand::IsaacRng::new_seeded(&[42]);
+    let mut blocks = BlockWriter::new(prefix);
+    blocks.stored(rng.gen_bytes(2_000), false);
+    blocks.fixed(&[1, 2, 3], 20, 4, false);
+    blocks.stored(rng.gen_bytes(3_000), false);
+    blocks.fixed(&[], 30, 3, true);
+
+    let bytes = blocks.finish();
+    let data = inflate_ok(bytes.slice(prefix.len(), bytes.len()));
+    (bytes, data)
+  }
+
+  fn build(span: u64, chunk_len: uint, bytes: &[u8], start: uint)
+    -> (index::Index, ~[u8])
+  {
+    let mut builder = index::Builder::new(span).with_start(start as u64);
+    let mut out: ~[u8] = ~[];
+    for bytes.slice(start, bytes.len()).chunk_iter(chunk_len).advance |chunk| {
+      match builder.input(chunk, out) {
+        (Left(new_builder), new_out) => {
+          builder = new_builder;
+          out = new_out;
+        },
+        (Right((Ok(index), [])), new_out) => return (index, new_out),
+        other => fail!(fmt!("unexpected %?", other)),
+      }
+    }
+    fail!("the builder did not finish");
+  }
+
+  fn read_at<S: index::Source>(reader: &mut index::Reader<S>,
+    pos: u64, len: uint) -> ~[u8]
+  {
+    let mut buf = vec::from_elem(len, 0u8);
+    reader.seek(pos);
+    match reader.read(buf.mut_slice(0, len)) {
+      Ok(read) => buf.slice(0, read).to_owned(),
+      Err(err) => fail!(fmt!("read error %?", err)),
+    }
+  }
+
+  #[test]
+  fn test_build_index() {
+    let (bytes, data) = stream(&[]);
+    assert_eq!(data.len(), 17_903);
+
+    for (&[1u, 7, 100, 20_000]).iter().advance |&chunk_len| {
+      let (index, out) = build(1000, chunk_len, bytes, 0);
+      assert_eq!(out, data.clone());
+
+      let offsets = do index.points().map |point| { point.out_offset };
+      assert_eq!(offsets, ~[0, 2_000, 7_163, 10_163]);
+      for index.points().iter().advance |point| {
+        let start = point.out_offset as uint;
+        let window_start = if start > 32_768 { start - 32_768 } else { 0 };
+        assert_eq!(point.window.clone(), data.slice(window_start, start).to_owned());
+      }
+    }
+
+    let (index, _) = build(6_000, 10, bytes, 0);
+    let offsets = do index.points().map |point| { point.out_offset };
+    assert_eq!(offsets, ~[0, 7_163]);
+
+    let (index, _) = build(0, 100, bytes, 0);
+    let (every, _) = build(1, 100, bytes, 0);
+    assert_eq!(index.span(), 1);
+    assert_eq!(index.points().len(), every.points().len());
+  }
+
+  #[test]
+  fn test_resume() {
+    let (bytes, data) = stream(&[]);
+    let (index, _) = build(1000, 50, bytes, 0);
+
+    for index.points().iter().advance |point| {
+      let inflater = inflater::Inflater::resume(point);
+      let start = point.in_offset() as uint;
+      match inflater.input(bytes.slice(start, bytes.len()), ~[]) {
+        (Right((Ok(()), [])), out) =>
+          assert_eq!(out, data.slice(point.out_offset as uint, data.len()).to_owned()),
+        other => fail!(fmt!("unexpected %?", other)),
+      }
+    }
+  }
+
+  #[test]
+  fn test_reader() {
+    let prefix = [0x78u8, 0x9c];
+    let (bytes, data) = stream(prefix);
+    let (index, _) = build(1000, 100, bytes, prefix.len());
+    assert_eq!(index.points()[1].bit_offset, 8 * (2 + 5 + 2_000));
+
+    let mut reader = index::Reader::new(index, bytes.slice(0, bytes.len()));
+    let ranges = [(0u, 10u), (10, 3_000), (7_000, 500), (7_163, 100),
+      (17_000, 903), (17_800, 500), (500, 17_000), (20_000, 10)];
+    for ranges.iter().advance |&(pos, len)| {
+      let end = cmp::min(pos + len, data.len());
+      let expected = if pos < end { data.slice(pos, end).to_owned() } else { ~[] };
+      assert_eq!(read_at(&mut reader, pos as u64, len), expected);
+    }
+
+    // sequential reads
+    reader.seek(1_000);
+    let mut out = ~[];
+    let mut buf = vec::from_elem(1_234, 0u8);
+    loop {
+      match reader.read(buf.mut_slice(0, 1_234)) {
+        Ok(0) => break,
+        Ok(read) => out.push_all(buf.slice(0, read)),
+        Err(err) => fail!(fmt!("read error %?", err)),
+      }
+    }
+    assert_eq!(out, data.slice(1_000, data.len()).to_owned());
+    assert_eq!(reader.tell(), data.len() as u64);
+  }
+
+  #[test]
+  fn test_reader_truncated() {
+    let (bytes, _) = stream(&[]);
+    let (index, _) = build(1000, 100, bytes, 0);
+    let truncated = bytes.slice(0, 3_000);
+
+    let mut reader = index::Reader::new(index, truncated);
+    let mut buf = vec::from_elem(9_000, 0u8);
+    assert_eq!(reader.read(buf.mut_slice(0, 9_000)),
+      Err(~error::TruncatedInput(3_000)));
+  }
+
+  #[test]
+  fn test_serialize_index() {
+    let (bytes, _) = stream(&[]);
+    let (index, _) = build(1000, 100, bytes, 0);
+
+    let serialized = index.write(~[]);
+    assert_eq!(serialized.slice(0, 5), &[0x43, 0x5a, 0x49, 0x58, 1]);
+    assert_eq!(index::Index::read(serialized), Ok(index.clone()));
+
+    let mut bad_magic = serialized.clone();
+    bad_magic[0] = 0x44;
+    assert_eq!(index::Index::read(bad_magic), Err(~error::BadIndexHeader));
+
+    let mut bad_version = serialized.clone();
+    bad_version[4] = 2;
+    assert_eq!(index::Index::read(bad_version), Err(~error::BadIndexVersion(2)));
+
+    let truncated = serialized.slice(0, serialized.len() - 1);
+    assert_eq!(index::Index::read(truncated), Err(~error::TruncatedIndex));
+
+    let mut huge_count = serialized.slice(0, 17).to_owned();
+    for uint::range(13, 17) |i| { huge_count[i] = 0xff; }
+    assert_eq!(index::Index::read(huge_count), Err(~error::TruncatedIndex));
+
+    let mut unordered = index.write(~[]);
+    // out_offset of the second point
+    unordered[17 + 19 + 9] = 0;
+    unordered[17 + 19 + 10] = 0;
+    assert_eq!(index::Index::read(unordered), Err(~error::BadIndexPoint(1)));
+  }
+}
@@ -1,17 +1,24 @@
 use bits;
+use bits::status;
 use inflate::dynamic;
 use inflate::error;
 use inflate::fixed;
+use inflate::index;
 use inflate::inflater;
+use inflate::limits;
 use inflate::out;
 use inflate::verbatim;
 use inflate::compressed;
+use std::{uint, util};
 
 pub struct Inflater {
   priv stage: Stage,
   priv bit_buf: bits::BitBuf,
   priv output: ~out::Output,
   priv last_block: bool,
+  priv limits: limits::Limits,
+  priv total_in: u64,
+  priv total_out: u64,
 }
 
 enum Stage {
@@ -33,6 +40,62 @@ impl Inflater {
       bit_buf: bits::BitBuf::new(),
       output: ~out::Output::new(inflater::window_size),
       last_block: false,
+      limits: limits::Limits::unlimited(),
+      total_in: 0,
+      total_out: 0,
+    }
+  }
+
+  /// Creates an inflater whose window is primed with a preset dictionary
+  /// (the last `window_size` bytes of `dict` are used).
+  pub fn new_with_dict(dict: &[u8]) -> Inflater {
+    let mut output = ~out::Output::new(inflater::window_size);
+    output.preset_dict(dict);
+
+    Inflater {
+      stage: HeaderStage,
+      bit_buf: bits::BitBuf::new(),
+      output: output,
+      last_block: false,
+      limits: limits::Limits::unlimited(),
+      total_in: 0,
+      total_out: 0,
+    }
+  }
+
+  /// Creates an inflater that continues decoding from an access point. The
+  /// input must start at `point.in_offset()`, the offsets of later checkpoints
+  /// continue from the point.
+  pub fn resume(point: &index::AccessPoint) -> Inflater {
+    let mut inflater = Inflater::new_with_dict(point.window);
+    let bits = ((8 - point.bit_offset % 8) % 8) as uint;
+    // the buffer is empty, so the bits always fit
+    inflater.bit_buf.unshift_bits(bits, point.bits_data as u64 & !(!0 << bits));
+    inflater.total_in = point.in_offset();
+    inflater.total_out = point.out_offset;
+    inflater
+  }
+
+  /// Sets the limits on the output. Must be called before any input.
+  pub fn with_limits(self, limits: limits::Limits) -> Inflater {
+    let mut inflater = self;
+    inflater.limits = limits;
+    inflater
+  }
+
+  /// Decodes a chunk of input and sends the data to `recv`. Unlike `input`,
+  /// the inflater is borrowed, so it can stay in a struct between the calls.
+  pub fn feed<'a, R: bits::recv::Recv<u8>>
+    (&mut self, chunk: &'a [u8], recv: R)
+    -> (status::Status<'a, (), ~error::Error>, R)
+  {
+    let (res, recv, _) = self.input_stopping(chunk, uint::max_value, false,
+      bits::recv::Infallible(recv));
+    let recv = match recv { Ok(recv) | Err(recv) => recv.unwrap() };
+    match res {
+      Left(_) => (status::NeedInput, recv),
+      Right((Ok(()), rest)) => (status::Finished((), rest), recv),
+      Right((Err(err), rest)) => (status::Error(err, rest), recv),
     }
   }
 
@@ -40,30 +103,149 @@ impl Inflater {
     (self, chunk: &'a [u8], recv: R)
     -> (Either<Inflater, (Result<(), ~error::Error>, &'a [u8])>, R)
   {
-    let Inflater { stage, bit_buf, output, last_block } = self;
-    let mut output = output;
-    let mut last_block = last_block;
-    let mut stage = stage;
+    let mut inflater = self;
+    let (status, recv) = inflater.feed(chunk, recv);
+    match status {
+      status::NeedInput => (Left(inflater), recv),
+      status::Finished((), rest) => (Right((Ok(()), rest)), recv),
+      status::Error(err, rest) => (Right((Err(err), rest)), recv),
+    }
+  }
+
+  /// Decodes into `buf` and stops when it is full, like zlib with `avail_out`.
+  /// Returns the number of input bytes consumed and output bytes written; the
+  /// unconsumed input must be passed to the next call. Finishing may need a
+  /// call with free space even if no more bytes are written.
+  pub fn input_into(self, chunk: &[u8], buf: &mut [u8])
+    -> (Either<Inflater, Result<(), ~error::Error>>, uint, uint)
+  {
+    let avail = buf.len();
+    match self.input_limited(chunk, avail, bits::recv::SliceRecv::new(buf)) {
+      (Left((inflater, rest)), _, written) =>
+        (Left(inflater), chunk.len() - rest.len(), written),
+      (Right((res, rest)), _, written) =>
+        (Right(res), chunk.len() - rest.len(), written),
+    }
+  }
+
+  /// Like `input`, but sends at most `avail` bytes to `recv`. When the limit
+  /// is reached, the inflater stops and returns the unconsumed input together
+  /// with itself. Also returns the number of bytes sent.
+  pub fn input_limited<'a, R: bits::recv::Recv<u8>>
+    (self, chunk: &'a [u8], avail: uint, recv: R)
+    -> (Either<(Inflater, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>, R, uint)
+  {
+    match self.try_input_limited(chunk, avail, bits::recv::Infallible(recv)) {
+      (res, Ok(recv), written) | (res, Err(recv), written) =>
+        (res, recv.unwrap(), written),
+    }
+  }
+
+  /// Like `input`, but the receiver may fail. The inflater then stops and
+  /// returns the unconsumed input together with itself and the failed
+  /// receiver; the data that were not received are sent again by the next
+  /// call (which may get an empty chunk).
+  pub fn try_input<'a, R: bits::recv::TryRecv<u8>>
+    (self, chunk: &'a [u8], recv: R)
+    -> (Either<(Inflater, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>)
+  {
+    match self.try_input_limited(chunk, uint::max_value, recv) {
+      (res, recv, _) => (res, recv),
+    }
+  }
+
+  /// Combines `try_input` and `input_limited`. The number of bytes returned
+  /// includes the bytes that the failed receiver did not get yet.
+  pub fn try_input_limited<'a, R: bits::recv::TryRecv<u8>>
+    (self, chunk: &'a [u8], avail: uint, recv: R)
+    -> (Either<(Inflater, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, uint)
+  {
+    self.input_owned(chunk, avail, false, recv)
+  }
+
+  /// Like `try_input_limited`, but the inflater is borrowed, see `feed`. When
+  /// the inflater stops, only the unconsumed input is returned.
+  pub fn try_feed_limited<'a, R: bits::recv::TryRecv<u8>>
+    (&mut self, chunk: &'a [u8], avail: uint, recv: R)
+    -> (Either<&'a [u8], (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, uint)
+  {
+    self.input_stopping(chunk, avail, false, recv)
+  }
+
+  /// Like `input`, but also stops after the end of each block and returns the
+  /// unconsumed input, so that `checkpoint` can be called there.
+  pub fn input_block<'a, R: bits::recv::Recv<u8>>
+    (self, chunk: &'a [u8], recv: R)
+    -> (Either<(Inflater, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>, R)
+  {
+    match self.input_owned(chunk, uint::max_value, true,
+        bits::recv::Infallible(recv)) {
+      (res, Ok(recv), _) | (res, Err(recv), _) => (res, recv.unwrap()),
+    }
+  }
+
+  priv fn input_owned<'a, R: bits::recv::TryRecv<u8>>
+    (self, chunk: &'a [u8], avail: uint, stop_at_block: bool, recv: R)
+    -> (Either<(Inflater, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, uint)
+  {
+    let mut inflater = self;
+    let (res, recv, written) =
+      inflater.input_stopping(chunk, avail, stop_at_block, recv);
+    match res {
+      Left(rest) => (Left((inflater, rest)), recv, written),
+      Right(res) => (Right(res), recv, written),
+    }
+  }
+
+  /// Decodes the chunk and returns either the unconsumed input when the
+  /// inflater stopped, or the result when the stream ended. The stage is
+  /// taken out of the inflater for the decoding and put back before returning.
+  priv fn input_stopping<'a, R: bits::recv::TryRecv<u8>>
+    (&mut self, chunk: &'a [u8], avail: uint, stop_at_block: bool, recv: R)
+    -> (Either<&'a [u8], (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, uint)
+  {
+    let mut stage = util::replace(&mut self.stage, EndStage);
+    let bit_buf = util::replace(&mut self.bit_buf, bits::BitBuf::new());
+
+    // the output budget is the free space or what the limits allow for the
+    // input consumed so far, so the limits are not loosened by input that
+    // the inflater has not reached yet
+    let (budget, budget_err) = self.budget(0, 0, avail);
+    let mut out_avail = budget;
+    let mut limit_err = budget_err;
+    self.output.set_avail(out_avail);
 
     let mut recv = recv;
+    let mut recv_failed = false;
     let mut bit_reader = bits::BitReader::new(bit_buf, chunk);
 
     loop {
       let (continue, new_stage) = match stage {
-        HeaderStage() if last_block => 
+        HeaderStage() if self.last_block => 
           (true, EndStage),
         HeaderStage() => {
           if bit_reader.has_bits(3) {
-            let bfinal = bit_reader.read_bits8(1);
-            let btype = bit_reader.read_bits8(2);
-
-            last_block = bfinal != 0;
-            (true, match btype {
-              0b00 => VerbatimStage(verbatim::VerbState::new()),
-              0b01 => FixedStage(compressed::ComprState::new(fixed::FixedCoder::new())),
-              0b10 => DynamicHeaderStage(dynamic::HeaderState::new()),
-              _    => ErrorStage(~error::BadBlockType(btype as uint)),
-            })
+            match bit_reader.read_bits8(3) {
+              Ok(header) => {
+                let bfinal = header & 0b1;
+                let btype = header >> 1;
+
+                self.last_block = bfinal != 0;
+                (true, match btype {
+                  0b00 => VerbatimStage(verbatim::VerbState::new()),
+                  0b01 => FixedStage(compressed::ComprState::new(fixed::FixedCoder::new())),
+                  0b10 => DynamicHeaderStage(dynamic::HeaderState::new()),
+                  _    => ErrorStage(~error::BadBlockType(btype as uint)),
+                })
+              },
+              Err(err) =>
+                (true, ErrorStage(~error::InternalError(err))),
+            }
           } else {
             (false, HeaderStage)
           }
@@ -79,52 +261,216 @@ impl Inflater {
           }
         },
         VerbatimStage(verb_state) => {
-          let (res, new_recv) = verb_state.input(&mut bit_reader, output, recv);
-          recv = new_recv;
+          let (res, recv_res) =
+            verb_state.input(&mut bit_reader, &mut *self.output, recv);
+          recv = match recv_res {
+              Ok(new_recv) => new_recv,
+              Err(new_recv) => { recv_failed = true; new_recv },
+            };
           match res {
             Left(new_state) => (false, VerbatimStage(new_state)),
-            Right(Ok(()))   => (true, HeaderStage),
+            Right(Ok(()))   => (!stop_at_block, HeaderStage),
             Right(Err(err)) => (true, ErrorStage(err)),
           }
         },
         FixedStage(compr_state) => {
-          let (res, new_recv) = compr_state.input(&mut bit_reader, output, recv);
-          recv = new_recv;
+          let (res, recv_res) =
+            compr_state.input(&mut bit_reader, &mut *self.output, recv);
+          recv = match recv_res {
+              Ok(new_recv) => new_recv,
+              Err(new_recv) => { recv_failed = true; new_recv },
+            };
           match res {
             Left(new_state) => (false, FixedStage(new_state)),
-            Right(Ok(()))   => (true, HeaderStage),
+            Right(Ok(()))   => (!stop_at_block, HeaderStage),
             Right(Err(err)) => (true, ErrorStage(err)),
           }
         },
         DynamicStage(compr_state) => {
-          let (res, new_recv) = compr_state.input(&mut bit_reader, output, recv);
-          recv = new_recv;
+          let (res, recv_res) =
+            compr_state.input(&mut bit_reader, &mut *self.output, recv);
+          recv = match recv_res {
+              Ok(new_recv) => new_recv,
+              Err(new_recv) => { recv_failed = true; new_recv },
+            };
           match res {
             Left(new_state) => (false, DynamicStage(new_state)),
-            Right(Ok(()))   => (true, HeaderStage),
+            Right(Ok(()))   => (!stop_at_block, HeaderStage),
             Right(Err(err)) => (true, ErrorStage(err)),
           }
         },
         EndStage() => {
-          return (Right((Ok(()), bit_reader.close_to_rest())), recv)
+          match self.output.flush(recv) {
+            Ok(new_recv) => {
+              let written = out_avail - self.output.avail();
+              let (res, rest) = match bit_reader.close_to_rest() {
+                  Ok(rest) => (Ok(()), rest),
+                  Err(err) => {
+                    let err = ~error::InternalError(err);
+                    self.stage = ErrorStage(err.clone());
+                    (Err(err), chunk.slice(chunk.len(), chunk.len()))
+                  },
+                };
+              self.advance(chunk.len() - rest.len(), written);
+              return (Right((res, rest)), Ok(new_recv), written)
+            },
+            Err(new_recv) => {
+              recv = new_recv;
+              recv_failed = true;
+              (false, EndStage)
+            },
+          }
         },
         ErrorStage(err) => {
-          return (Right((Err(err), bit_reader.close_to_rest())), recv)
+          match self.output.flush(recv) {
+            Ok(new_recv) => {
+              let written = out_avail - self.output.avail();
+              let (_, rest) = bit_reader.close();
+              self.stage = ErrorStage(err.clone());
+              self.advance(chunk.len() - rest.len(), written);
+              return (Right((Err(err), rest)), Ok(new_recv), written)
+            },
+            Err(new_recv) => {
+              recv = new_recv;
+              recv_failed = true;
+              (false, ErrorStage(err))
+            },
+          }
         },
       };
 
       stage = new_stage;
       if !continue { 
-        return (Left(Inflater {
-          stage: stage,
-          bit_buf: bit_reader.close_to_buf(),
-          output: output,
-          last_block: last_block
-        }), recv)
+        let written = out_avail - self.output.avail();
+        if !recv_failed {
+          recv = match self.output.flush(recv) {
+              Ok(new_recv) => new_recv,
+              Err(new_recv) => { recv_failed = true; new_recv },
+            };
+        }
+
+        if recv_failed {
+          // the rest of the input is left to the caller, who continues when
+          // the receiver is ready again
+          let (bit_buf, rest) = bit_reader.close();
+          self.stage = stage;
+          self.bit_buf = bit_buf;
+          self.advance(chunk.len() - rest.len(), written);
+          return (Left(rest), Err(recv), written)
+        }
+
+        let output_full = self.output.avail() == 0 && match stage {
+            VerbatimStage(ref st) => st.has_pending_output(),
+            FixedStage(ref st) => st.has_pending_output(),
+            DynamicStage(ref st) => st.has_pending_output(),
+            _ => false,
+          };
+
+        if output_full && limit_err.is_some() {
+          // the input consumed since the budget was set may allow more output
+          let consumed = bit_reader.consumed();
+          let (more, err) = self.budget(consumed, written, avail);
+          if more > 0 {
+            out_avail = out_avail + more;
+            limit_err = err;
+            self.output.set_avail(more);
+            loop;
+          }
+
+          // more output is needed, but the limits do not allow it (without
+          // an error, the free space of the call is used up instead)
+          match err {
+            Some(err) => {
+              let (_, rest) = bit_reader.close();
+              self.stage = ErrorStage(err.clone());
+              self.advance(chunk.len() - rest.len(), written);
+              return (Right((Err(err), rest)), Ok(recv), written)
+            },
+            None => { },
+          }
+        }
+
+        // when the output is full or a block has ended, the rest of the input
+        // is left to the caller
+        let at_block = stop_at_block && match stage {
+            HeaderStage => true,
+            _ => false,
+          };
+        let (bit_buf, rest) = if output_full || at_block {
+            bit_reader.close()
+          } else {
+            let rest = chunk.slice(chunk.len(), chunk.len());
+            match bit_reader.close_to_buf() {
+              Ok(bit_buf) => (bit_buf, rest),
+              Err(err) => {
+                let err = ~error::InternalError(err);
+                self.stage = ErrorStage(err.clone());
+                self.advance(chunk.len(), written);
+                return (Right((Err(err), rest)), Ok(recv), written)
+              },
+            }
+          };
+
+        self.stage = stage;
+        self.bit_buf = bit_buf;
+        self.advance(chunk.len() - rest.len(), written);
+        return (Left(rest), Ok(recv), written)
       }
     }
   }
 
+  // Returns the output budget after `consumed` bytes of the chunk and
+  // `written` bytes of output in the current call, with the free space
+  // `avail` of the whole call, with the error to report if the limits are
+  // what constrains it.
+  priv fn budget(&self, consumed: uint, written: uint, avail: uint)
+    -> (uint, Option<~error::Error>)
+  {
+    let free = avail - written;
+    let total_in = self.total_in + consumed as u64;
+    let total_out = self.total_out + written as u64;
+    match self.limits.allowance(total_in, total_out) {
+      Some((allowed, err)) =>
+        if allowed < free as u64 {
+          (allowed as uint, Some(err))
+        } else {
+          (free, None)
+        },
+      None => (free, None),
+    }
+  }
+
+  priv fn advance(&mut self, consumed: uint, written: uint) {
+    self.total_in = self.total_in + consumed as u64;
+    self.total_out = self.total_out + written as u64;
+  }
+
+  /// Whether the inflater is between two blocks of the stream.
+  pub fn at_block_boundary(&self) -> bool {
+    match self.stage {
+      HeaderStage => !self.last_block,
+      _ => false,
+    }
+  }
+
+  /// Returns an access point from which another inflater can continue (see
+  /// `resume`), if the inflater is between two blocks. The offsets count from
+  /// the start of the input.
+  pub fn checkpoint(&self) -> Option<index::AccessPoint> {
+    if !self.at_block_boundary() {
+      return None;
+    }
+
+    let bit_offset = self.total_in * 8 - self.bit_buf.bits as u64;
+    let bits = ((8 - bit_offset % 8) % 8) as uint;
+    Some(index::AccessPoint {
+      bit_offset: bit_offset,
+      bits_data: (self.bit_buf.buf & !(!0 << bits)) as u8,
+      out_offset: self.total_out,
+      window: self.output.window(),
+    })
+  }
+
   pub fn has_finished(&self) -> bool {
     match self.stage {
       EndStage      => true,
@@ -132,18 +478,76 @@ impl Inflater {
       _ => false,
     }
   }
+
+  /// Checks that the input did not end before the end of the stream.
+  pub fn finish(self) -> Result<(), ~error::Error> {
+    let Inflater { stage, last_block, total_in, _ } = self;
+    match stage {
+      EndStage => Ok(()),
+      HeaderStage if last_block => Ok(()),
+      ErrorStage(err) => Err(err),
+      _ => Err(~error::TruncatedInput(total_in)),
+    }
+  }
+}
+
+impl bits::io::StreamDecoder<~error::Error> for Inflater {
+  pub fn end_input(self) -> Result<(), ~error::Error> {
+    self.finish()
+  }
+}
+
+impl bits::io::BufDecoder<~error::Error> for Inflater {
+  pub fn decode_into(self, chunk: &[u8], buf: &mut [u8])
+    -> (Either<Inflater, Result<(), ~error::Error>>, uint, uint)
+  {
+    self.input_into(chunk, buf)
+  }
+}
+
+impl bits::io::RecvDecoder<~error::Error> for Inflater {
+  pub fn decode<'a, R: bits::recv::TryRecv<u8>>(self, chunk: &'a [u8], recv: R)
+    -> (Either<(Inflater, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>)
+  {
+    self.try_input(chunk, recv)
+  }
 }
 
 #[cfg(test)]
 mod test {
   use inflate::test_helpers::*;
-  use std::uint;
+  use inflate::limits;
+  use bits;
+  use bits::status;
+  use std::{vec, uint};
+
+  // fixed block with a zero literal followed by `refs` references with len
+  // 258 and dist 1
+  fn zeros_block(refs: uint) -> ~[u8] {
+    let mut writer = BlockWriter::new(&[]);
+    writer.fixed(&[0], refs, 1, true);
+    writer.finish()
+  }
 
   #[test]
   fn test_inflate_bad_block_type() {
     assert_eq!(inflate_err(&[0b110]), (~error::BadBlockType(0b11), &[]));
   }
 
+  #[test]
+  fn test_inflate_with_dict() {
+    // fixed block with a single reference (len 3, dist 3) into the dictionary
+    let inflater = inflater::Inflater::new_with_dict(&[7, 1, 2, 3]);
+    match inflater.input(&[0b0000_0011, 0b0010_0010, 0b0000_0000], ~[]) {
+      (Right((Ok(()), [])), inflated) => assert_eq!(inflated, ~[1, 2, 3]),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+
+    assert_eq!(inflate_err(&[0b0000_0011, 0b0010_0010, 0b0000_0000]),
+      (~error::ReferenceBeforeStart(3, 3, 0), &[0b0000_0000]));
+  }
+
   #[test]
   fn test_inflate_chunked() {
     for uint::range(1, 10) |chunk_len| {
@@ -190,4 +594,217 @@ mod test {
     }
 
   }
+
+  #[test]
+  fn test_inflate_into() {
+    let verbatim = [
+        0b00000_000u8, 0b0000_0110, 0b0000_0000, 0b1111_1001, 0b1111_1111,
+        11, 22, 33, 44, 55, 66,
+        0b00000_001, 0b0000_0100, 0b0000_0000, 0b1111_1011, 0b1111_1111,
+        77, 88, 99, 110
+      ];
+    let fixed = [
+        0b11100011u8, 0b00010010, 0b01010011,
+        0b11000100, 0b00001101, 0b10111001,
+        0b11000100, 0b00010100, 0b00000001
+      ];
+
+    let buf_lens = [1u, 2, 3, 7, 100];
+    for uint::range(1, 10) |chunk_len| {
+      for buf_lens.iter().advance |&buf_len| {
+        assert_eq!(inflate_into_ok(chunk_len, buf_len, verbatim),
+          inflate_ok(verbatim));
+        assert_eq!(inflate_into_ok(chunk_len, buf_len, fixed),
+          inflate_ok(fixed));
+      }
+    }
+  }
+
+  #[test]
+  fn test_inflate_into_stops() {
+    // the fixed block from `test_inflate_into`, 32 bytes of output mostly
+    // produced by one long back-reference
+    let fixed = [
+        0b11100011u8, 0b00010010, 0b01010011,
+        0b11000100, 0b00001101, 0b10111001,
+        0b11000100, 0b00010100, 0b00000001
+      ];
+    let mut buf = vec::from_elem(10, 0u8);
+    let inflater = inflater::Inflater::new();
+
+    let (inflater, rest) = match inflater.input_into(fixed, buf.mut_slice(0, 10)) {
+      (Left(inflater), consumed, 10) => {
+        assert!(consumed < fixed.len());
+        assert_eq!(buf.clone(), ~[10, 22, 33, 22, 33, 22, 33, 22, 33, 22]);
+        (inflater, fixed.slice(consumed, fixed.len()))
+      },
+      other => fail!(fmt!("unexpected %?", other)),
+    };
+
+    // continue in the middle of the reference, no input is needed
+    match inflater.input_into(rest, buf.mut_slice(0, 10)) {
+      (Left(_), 0, 10) =>
+        assert_eq!(buf.clone(), ~[33, 22, 33, 22, 33, 22, 33, 22, 33, 22]),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_inflate_limits() {
+    fn inflate_limited(limits: limits::Limits, bytes: &[u8])
+      -> (Result<(), ~error::Error>, u32)
+    {
+      let inflater = inflater::Inflater::new().with_limits(limits);
+      match inflater.input(bytes, 0u32) {
+        (Right((res, _)), count) => (res, count),
+        other => fail!(fmt!("unexpected %?", other)),
+      }
+    }
+
+    let bytes = zeros_block(1000);
+    assert_eq!(inflate_ok(bytes).len(), 258_001);
+
+    assert_eq!(inflate_limited(
+        limits::Limits { max_output: Some(258_001), max_ratio: None }, bytes),
+      (Ok(()), 258_001));
+    assert_eq!(inflate_limited(
+        limits::Limits { max_output: Some(258_000), max_ratio: None }, bytes),
+      (Err(~error::OutputLimitExceeded(258_000)), 258_000));
+
+    assert_eq!(inflate_limited(
+        limits::Limits { max_output: None, max_ratio: Some(200) }, bytes),
+      (Ok(()), 258_001));
+    match inflate_limited(
+        limits::Limits { max_output: None, max_ratio: Some(100) }, bytes) {
+      (Err(~error::RatioLimitExceeded(100)), count) =>
+        assert!(count as uint <= 100 * bytes.len()),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_inflate_ratio_limit_in_one_chunk() {
+    // the references expand about 160 times, but the stored blocks after them
+    // bring the ratio of the whole chunk down to about 3
+    let mut writer = BlockWriter::new(&[]);
+    writer.fixed(&[0], 1000, 1, false);
+    writer.stored(vec::from_elem(60_000, 1u8), false);
+    writer.stored(vec::from_elem(60_000, 2u8), true);
+    let bytes = writer.finish();
+
+    let limits = limits::Limits { max_output: None, max_ratio: Some(100) };
+    let inflater = inflater::Inflater::new().with_limits(limits);
+    match inflater.input(bytes, 0u32) {
+      (Right((Err(~error::RatioLimitExceeded(100)), _)), count) =>
+        assert!(count < 258_001),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+
+    let limits = limits::Limits { max_output: None, max_ratio: Some(200) };
+    let inflater = inflater::Inflater::new().with_limits(limits);
+    match inflater.input(bytes, 0u32) {
+      (Right((Ok(()), [])), count) => assert_eq!(count, 378_001),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_feed() {
+    let bytes = zeros_block(100);
+    let mut inflater = inflater::Inflater::new();
+    let mut out = ~[];
+    let mut finished = false;
+    let mut iter = bytes.chunk_iter(7);
+    loop {
+      match iter.next() {
+        Some(chunk) => {
+          assert!(!finished);
+          let (status, new_out) = inflater.feed(chunk, out);
+          out = new_out;
+          match status {
+            status::NeedInput => { },
+            status::Finished((), []) => { finished = true },
+            other => fail!(fmt!("unexpected %?", other)),
+          }
+        },
+        None => break,
+      }
+    }
+    assert!(finished);
+    assert_eq!(out, inflate_ok(bytes));
+
+    // the input after the end is returned by all later calls
+    match inflater.feed(&[1, 2], ()) {
+      (status::Finished((), [1, 2]), ()) => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+    assert!(inflater.finish().is_ok());
+
+    // the error is returned by all later calls
+    let mut inflater = inflater::Inflater::new();
+    match inflater.feed(&[0b110, 42], ()) {
+      (status::Error(~error::BadBlockType(0b11), [42]), ()) => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+    match inflater.feed(&[43], ()) {
+      (status::Error(~error::BadBlockType(0b11), [43]), ()) => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
+
+  #[test]
+  fn test_try_input() {
+    // the receiver gets more room after each failure; returns the output and
+    // the number of failures
+    fn try_inflate(bytes: &[u8], room: uint) -> (~[u8], uint) {
+      let mut inflater = inflater::Inflater::new();
+      let mut recv = FailingRecv::new(0);
+      let mut rest = bytes;
+      let mut failures = 0u;
+      loop {
+        match inflater.try_input(rest, recv) {
+          (Left((new_inflater, new_rest)), Err(new_recv)) => {
+            inflater = new_inflater;
+            rest = new_rest;
+            recv = new_recv;
+            recv.room = room;
+            failures = failures + 1;
+          },
+          (Right((Ok(()), [])), Ok(new_recv)) =>
+            return (new_recv.buf, failures),
+          other => fail!(fmt!("unexpected %?", other)),
+        }
+      }
+    }
+
+    let bytes = zeros_block(1000);
+    let (out, failures) = try_inflate(bytes, 40_000);
+    assert_eq!(out, inflate_ok(bytes));
+    assert!(failures > 1);
+
+    let verbatim = [
+        0b00000_001,
+        0b0000_0110, 0b0000_0000,
+        0b1111_1001, 0b1111_1111,
+        11, 22, 33, 44, 55, 66,
+      ];
+    assert_eq!(try_inflate(verbatim, 6), (~[11, 22, 33, 44, 55, 66], 1));
+  }
+
+  #[test]
+  fn test_decompress_reader() {
+    fn read_all(bytes: ~[u8]) -> (~[u8], Option<~error::Error>, ~[u8]) {
+      decompress_bytes(inflater::Inflater::new(), bytes, 100)
+    }
+
+    let bytes = zeros_block(10);
+    let (out, err, rest) = read_all(vec::append(bytes.clone(), [42, 43]));
+    assert_eq!(out, inflate_ok(bytes));
+    assert_eq!(err, None);
+    assert_eq!(rest, ~[42, 43]);
+
+    let len = bytes.len() - 1;
+    let (_, err, _) = read_all(bytes.slice(0, len).to_owned());
+    assert_eq!(err, Some(~error::TruncatedInput(len as u64)));
+  }
 }
@@ -0,0 +1,91 @@
+use inflate::error;
+use std::{cmp, u64};
+
+/// Limits that protect against decompression bombs. Going over a limit stops
+/// the decoding with an error before the excess output is sent anywhere.
+#[deriving(Clone, Eq)]
+pub struct Limits {
+  /// Maximal number of bytes of output.
+  max_output: Option<u64>,
+  /// Maximal ratio of output bytes to input bytes.
+  max_ratio: Option<u64>,
+}
+
+/// The ratio limit is not applied to the first `ratio_min_output` bytes of
+/// output, because short inputs can legitimately expand a lot.
+pub static ratio_min_output: u64 = 65_536;
+
+impl Limits {
+  pub fn unlimited() -> Limits {
+    Limits { max_output: None, max_ratio: None }
+  }
+
+  /// Returns how many more bytes of output are allowed after `input` bytes
+  /// of input and `output` bytes of output, with the error to report when
+  /// more output is needed. Returns `None` if there is no limit.
+  pub fn allowance(&self, input: u64, output: u64)
+    -> Option<(u64, ~error::Error)>
+  {
+    let by_output = match self.max_output {
+        Some(max) => Some((saturating_sub(max, output),
+          ~error::OutputLimitExceeded(max))),
+        None => None,
+      };
+    let by_ratio = match self.max_ratio {
+        Some(ratio) => {
+          let max = cmp::max(saturating_mul(input, ratio), ratio_min_output);
+          Some((saturating_sub(max, output), ~error::RatioLimitExceeded(ratio)))
+        },
+        None => None,
+      };
+
+    match (by_output, by_ratio) {
+      (Some((a, err_a)), Some((b, err_b))) =>
+        if a <= b { Some((a, err_a)) } else { Some((b, err_b)) },
+      (Some(x), None) | (None, Some(x)) => Some(x),
+      (None, None) => None,
+    }
+  }
+}
+
+fn saturating_sub(a: u64, b: u64) -> u64 {
+  if a > b { a - b } else { 0 }
+}
+
+fn saturating_mul(a: u64, b: u64) -> u64 {
+  if a != 0 && b > u64::max_value / a { u64::max_value } else { a * b }
+}
+
+#[cfg(test)]
+mod test {
+  use inflate::limits;
+  use inflate::error;
+  use std::u64;
+
+  #[test]
+  fn test_allowance() {
+    let unlimited = limits::Limits::unlimited();
+    assert_eq!(unlimited.allowance(10, 1_000_000), None);
+
+    let max_output = limits::Limits { max_output: Some(100), max_ratio: None };
+    assert_eq!(max_output.allowance(10, 30),
+      Some((70, ~error::OutputLimitExceeded(100))));
+    assert_eq!(max_output.allowance(10, 130),
+      Some((0, ~error::OutputLimitExceeded(100))));
+
+    let max_ratio = limits::Limits { max_output: Some(200_000), max_ratio: Some(10) };
+    assert_eq!(max_ratio.allowance(10, 30),
+      Some((limits::ratio_min_output - 30, ~error::RatioLimitExceeded(10))));
+    assert_eq!(max_ratio.allowance(10_000, 30_000),
+      Some((70_000, ~error::RatioLimitExceeded(10))));
+    assert_eq!(max_ratio.allowance(100_000, 30_000),
+      Some((170_000, ~error::OutputLimitExceeded(200_000))));
+
+    let huge_ratio = limits::Limits {
+        max_output: None,
+        max_ratio: Some(u64::max_value),
+      };
+    assert_eq!(huge_ratio.allowance(10, 30),
+      Some((u64::max_value - 30, ~error::RatioLimitExceeded(u64::max_value))));
+  }
+}
@@ -1,12 +1,17 @@
 use bits;
 use inflate::error;
-use std::{vec, uint};
+use std::{vec, uint, cmp};
 
+/// The window of the last output bytes. The bytes are written to the window
+/// and sent to the receiver in bulk by `flush`, or when the window is full of
+/// bytes that were not sent yet. If the receiver fails, these bytes stay in
+/// the window and are sent by the next flush.
 pub struct Output {
   priv window: ~[u8],
   priv wrapped: bool,
   priv pos: uint,
-  priv cache_pos: uint,
+  priv pending: uint,
+  priv avail: uint,
 }
 
 impl Output {
@@ -14,20 +19,85 @@ impl Output {
     Output {
       window: vec::from_elem(window_size, 77u8),
       wrapped: false,
-      pos: 0, cache_pos: 0,
+      pos: 0, pending: 0,
+      avail: uint::max_value,
     }
   }
 
-  pub fn send_literal_chunk<R: bits::recv::Recv<u8>>
-    (&mut self, chunk: &[u8], recv: R) -> R 
+  /// Sets how many more bytes may be written (the output budget). Literals
+  /// must not be sent when the budget is exhausted, back-references are cut
+  /// short.
+  pub fn set_avail(&mut self, avail: uint) {
+    self.avail = avail;
+  }
+
+  pub fn avail(&self) -> uint {
+    self.avail
+  }
+
+  /// The longest literal chunk that can be sent after `reserve`.
+  pub fn chunk_avail(&self) -> uint {
+    cmp::min(self.avail, self.window.len())
+  }
+
+  /// Fills the window with a preset dictionary, so that back-references can
+  /// point into it. The dictionary is not sent to the output. Must be called
+  /// before any output.
+  pub fn preset_dict(&mut self, dict: &[u8]) {
+    if self.pos != 0 || self.wrapped {
+      fail!("Output::preset_dict: some output was already written");
+    }
+    let start = if dict.len() > self.window.len() {
+        dict.len() - self.window.len()
+      } else {
+        0
+      };
+    self.write_window(dict.slice(start, dict.len()));
+  }
+
+  /// Returns the last bytes of output (up to the window size) in order, which
+  /// can be passed to `preset_dict` to continue from this point.
+  pub fn window(&self) -> ~[u8] {
+    if self.wrapped {
+      let mut window = self.window.slice(self.pos, self.window.len()).to_owned();
+      window.push_all(self.window.slice(0, self.pos));
+      window
+    } else {
+      self.window.slice(0, self.pos).to_owned()
+    }
+  }
+
+  /// Makes room for `len` bytes (at most `chunk_avail`) in the window,
+  /// flushing it if needed.
+  pub fn reserve<R: bits::recv::TryRecv<u8>>
+    (&mut self, len: uint, recv: R) -> Result<R, R>
   {
-    let recv = self.flush_cache(recv);
+    if len > self.window.len() - self.pending {
+      self.flush(recv)
+    } else {
+      Ok(recv)
+    }
+  }
 
+  /// Sends a chunk of literals, for which there must be room (see `reserve`).
+  pub fn send_literal_chunk(&mut self, chunk: &[u8]) {
+    if chunk.len() > self.avail {
+      fail!("Output::send_literal_chunk: output budget exceeded");
+    }
+    if chunk.len() > self.window.len() - self.pending {
+      fail!("Output::send_literal_chunk: no room in the window");
+    }
+    self.write_window(chunk);
+    self.pending = self.pending + chunk.len();
+    self.avail = self.avail - chunk.len();
+  }
+
+  priv fn write_window(&mut self, chunk: &[u8]) {
     let mut chunk_rest = chunk;
     loop {
       let window_free = self.window.len() - self.pos;
 
-      if chunk_rest.len() <= window_free {
+      if chunk_rest.len() < window_free {
         for uint::iterate(0, chunk_rest.len()) |i| {
           self.window[self.pos + i] = chunk_rest[i];
         }
@@ -42,38 +112,39 @@ impl Output {
         chunk_rest = chunk_rest.slice(window_free, chunk_rest.len());
       }
     }
-
-    self.cache_pos = self.pos;
-    recv.receive(chunk)
   }
 
-  pub fn send_literal<R: bits::recv::Recv<u8>>
-    (&mut self, byte: u8, recv: R) -> R 
+  /// Sends a literal. If the window must be flushed and the receiver fails,
+  /// the literal is not sent.
+  pub fn send_literal<R: bits::recv::TryRecv<u8>>
+    (&mut self, byte: u8, recv: R) -> Result<R, R>
   {
-    let mut recv = recv;
-
-    if self.pos >= self.window.len() {
-      recv = self.flush_cache(recv);
-      self.pos = 0;
-      self.cache_pos = 0;
-      self.wrapped = true;
+    if self.avail == 0 {
+      fail!("Output::send_literal: output budget exceeded");
     }
-
-    self.window[self.pos] = byte;
-    self.pos = self.pos + 1;
-    recv
+    let recv = match self.reserve(1, recv) {
+        Ok(recv) => recv,
+        Err(recv) => return Err(recv),
+      };
+    self.push_byte(byte);
+    self.avail = self.avail - 1;
+    Ok(recv)
   }
 
-  pub fn back_reference<R: bits::recv::Recv<u8>>
+  /// Copies `len` bytes from `dist` bytes back, or as many as the budget
+  /// allows. Returns the number of bytes copied, which is smaller if the
+  /// window had to be flushed and the receiver failed.
+  pub fn back_reference<R: bits::recv::TryRecv<u8>>
     (&mut self, dist: uint, len: uint, recv: R)
-    -> (Result<(),~error::Error>, R)
+    -> (Result<uint,~error::Error>, Result<R, R>)
   {
     if !self.wrapped && dist > self.pos {
-      (Err(~error::ReferenceBeforeStart(dist, len, self.pos)), recv)
+      (Err(~error::ReferenceBeforeStart(dist, len, self.pos)), Ok(recv))
     } else if dist > self.window.len() {
-      (Err(~error::ReferenceOutOfWindow(dist, len, self.window.len())), recv)
+      (Err(~error::ReferenceOutOfWindow(dist, len, self.window.len())), Ok(recv))
     } else {
       let mut recv = recv;
+      let len = cmp::min(len, self.avail);
 
       let mut back_pos = if dist > self.pos {
           self.window.len() + self.pos - dist
@@ -85,40 +156,73 @@ impl Output {
       //for len.times {
       let mut i = 0;
       while i < len {
-        i = i + 1;
-
-        if self.pos >= self.window.len() {
-          recv = self.flush_cache(recv);
-          self.pos = 0;
-          self.cache_pos = 0;
-          self.wrapped = true;
+        if self.pending == self.window.len() {
+          recv = match self.flush(recv) {
+              Ok(recv) => recv,
+              Err(recv) => {
+                self.avail = self.avail - i;
+                return (Ok(i), Err(recv))
+              },
+            };
         }
 
         if back_pos >= self.window.len() {
           back_pos = 0;
         }
 
-        self.window[self.pos] = self.window[back_pos];
-        self.pos = self.pos + 1;
+        let byte = self.window[back_pos];
+        self.push_byte(byte);
         back_pos = back_pos + 1;
+        i = i + 1;
       }
 
-      (Ok(()), recv)
+      self.avail = self.avail - len;
+      (Ok(len), Ok(recv))
     }
   }
 
-  pub fn flush<R: bits::recv::Recv<u8>>
-    (&mut self, recv: R) -> R 
+  /// Sends the bytes that were not sent yet.
+  pub fn flush<R: bits::recv::TryRecv<u8>>
+    (&mut self, recv: R) -> Result<R, R>
   {
-    self.flush_cache(recv)
+    if self.pending == 0 {
+      return Ok(recv);
+    }
+
+    let start = (self.pos + self.window.len() - self.pending) % self.window.len();
+    let recv = if start >= self.pos {
+        // the bytes wrap around the end of the window
+        match recv.try_receive(self.window.slice(start, self.window.len())) {
+          Ok(recv) => {
+            self.pending = self.pos;
+            recv
+          },
+          Err(recv) => return Err(recv),
+        }
+      } else {
+        recv
+      };
+
+    if self.pending == 0 {
+      return Ok(recv);
+    }
+    match recv.try_receive(self.window.slice(self.pos - self.pending, self.pos)) {
+      Ok(recv) => {
+        self.pending = 0;
+        Ok(recv)
+      },
+      Err(recv) => Err(recv),
+    }
   }
 
-  priv fn flush_cache<R: bits::recv::Recv<u8>>
-    (&mut self, recv: R) -> R 
-  {
-    let recv = recv.receive(self.window.slice(self.cache_pos, self.pos));
-    self.cache_pos = self.pos;
-    recv
+  priv fn push_byte(&mut self, byte: u8) {
+    self.window[self.pos] = byte;
+    self.pending = self.pending + 1;
+    self.pos = self.pos + 1;
+    if self.pos == self.window.len() {
+      self.pos = 0;
+      self.wrapped = true;
+    }
   }
 }
 
@@ -126,18 +230,41 @@ impl Output {
 mod test {
   use inflate::test_helpers::*;
   use inflate::out::*;
+  use bits::recv::{Infallible};
+  use std::uint;
+
+  fn recv() -> Infallible<~[u8]> {
+    Infallible(~[])
+  }
+
+  // sends a chunk of any length in pieces that fit into the window
+  fn send_chunk(out: &mut Output, chunk: &[u8], recv: Infallible<~[u8]>)
+    -> Infallible<~[u8]>
+  {
+    let mut recv = recv;
+    let mut iter = chunk.chunk_iter(out.chunk_avail());
+    loop {
+      match iter.next() {
+        Some(piece) => {
+          recv = out.reserve(piece.len(), recv).unwrap();
+          out.send_literal_chunk(piece);
+        },
+        None => return recv,
+      }
+    }
+  }
 
   #[test]
   fn test_send_literal() {
     let mut out = Output::new(10);
 
-    let buf: ~[u8] = ~[];
-    let buf = out.send_literal(10, buf);
-    let buf = out.send_literal(20, buf);
-    let buf = out.send_literal(30, buf);
-    let buf = out.flush(buf);
+    let buf = recv();
+    let buf = out.send_literal(10, buf).unwrap();
+    let buf = out.send_literal(20, buf).unwrap();
+    let buf = out.send_literal(30, buf).unwrap();
+    let buf = out.flush(buf).unwrap();
 
-    assert_eq!(buf, ~[10, 20, 30]);
+    assert_eq!(buf.unwrap(), ~[10, 20, 30]);
   }
 
   #[test]
@@ -145,26 +272,27 @@ mod test {
     {
       let mut out = Output::new(10);
 
-      let buf: ~[u8] = ~[];
-      let buf = out.send_literal_chunk(&[1,2,3], buf);
-      let buf = out.send_literal_chunk(&[4,5], buf);
-      let buf = out.send_literal_chunk(&[6,7,8,9], buf);
-      let buf = out.flush(buf);
+      let buf = recv();
+      let buf = send_chunk(&mut out, &[1,2,3], buf);
+      let buf = send_chunk(&mut out, &[4,5], buf);
+      let buf = send_chunk(&mut out, &[6,7,8,9], buf);
+      let buf = out.flush(buf).unwrap();
 
-      assert_eq!(buf, ~[1,2,3,4,5,6,7,8,9]);
+      assert_eq!(buf.unwrap(), ~[1,2,3,4,5,6,7,8,9]);
     }
 
     { // wrap the window
       let mut out = Output::new(5);
 
-      let buf: ~[u8] = ~[];
-      let buf = out.send_literal_chunk(&[1,2,3], buf);
-      let buf = out.send_literal_chunk(&[4,5,6,7,8], buf);
-      let buf = out.send_literal_chunk(
+      let buf = recv();
+      let buf = send_chunk(&mut out, &[1,2,3], buf);
+      let buf = send_chunk(&mut out, &[4,5,6,7,8], buf);
+      let buf = send_chunk(&mut out,
         &[9,10,11,12,13,14,15,16,17,18,19,20], buf);
-      let buf = out.flush(buf);
+      let buf = out.flush(buf).unwrap();
 
-      assert_eq!(buf, ~[1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20]);
+      assert_eq!(buf.unwrap(),
+        ~[1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20]);
     }
   }
 
@@ -173,69 +301,188 @@ mod test {
     {
       let mut out = Output::new(8);
 
-      let buf: ~[u8] = ~[];
-      let buf = out.send_literal_chunk(&[2,3,5,7], buf);
-      let buf = out.send_literal(11, buf);
+      let buf = recv();
+      let buf = send_chunk(&mut out, &[2,3,5,7], buf);
+      let buf = out.send_literal(11, buf).unwrap();
       let (res, buf) = out.back_reference(2, 5, buf);
-      assert_eq!(res, Ok(()));
-      let buf = out.flush(buf);
+      assert_eq!(res, Ok(5));
+      let buf = out.flush(buf.unwrap()).unwrap();
 
-      assert_eq!(buf, ~[2,3,5,7,11,7,11,7,11,7]);
+      assert_eq!(buf.unwrap(), ~[2,3,5,7,11,7,11,7,11,7]);
     };
 
     { // window wrapped
       let mut out = Output::new(8);
 
-      let buf: ~[u8] = ~[];
-      let buf = out.send_literal_chunk(&[2,3,5], buf);
-      let buf = out.send_literal_chunk(&[7,11,13,17], buf);
-      let buf = out.send_literal_chunk(&[19,23,29,31], buf);
+      let buf = recv();
+      let buf = send_chunk(&mut out, &[2,3,5], buf);
+      let buf = send_chunk(&mut out, &[7,11,13,17], buf);
+      let buf = send_chunk(&mut out, &[19,23,29,31], buf);
       let (res, buf) = out.back_reference(5, 4, buf);
-      assert_eq!(res, Ok(()));
-      let buf = out.flush(buf);
+      assert_eq!(res, Ok(4));
+      let buf = out.flush(buf.unwrap()).unwrap();
 
-      assert_eq!(buf, ~[2,3,5,7,11,13,17,19,23,29,31,17,19,23,29]);
+      assert_eq!(buf.unwrap(), ~[2,3,5,7,11,13,17,19,23,29,31,17,19,23,29]);
     };
 
     { // maximal distance
       let mut out = Output::new(4);
 
-      let buf: ~[u8] = ~[];
-      let buf = out.send_literal_chunk(&[2,3,5,7], buf);
+      let buf = recv();
+      let buf = send_chunk(&mut out, &[2,3,5,7], buf);
       let (res, buf) = out.back_reference(4, 6, buf);
-      assert_eq!(res, Ok(()));
-      let buf = out.flush(buf);
+      assert_eq!(res, Ok(6));
+      let buf = out.flush(buf.unwrap()).unwrap();
 
-      assert_eq!(buf, ~[2,3,5,7,2,3,5,7,2,3]);
+      assert_eq!(buf.unwrap(), ~[2,3,5,7,2,3,5,7,2,3]);
     };
   }
 
+  #[test]
+  fn test_preset_dict() {
+    { // short dictionary
+      let mut out = Output::new(8);
+      out.preset_dict(&[2,3,5]);
+
+      let buf = recv();
+      let buf = out.send_literal(7, buf).unwrap();
+      let (res, buf) = out.back_reference(4, 3, buf);
+      assert_eq!(res, Ok(3));
+      let buf = out.flush(buf.unwrap()).unwrap();
+
+      assert_eq!(buf.unwrap(), ~[7,2,3,5]);
+    }
+
+    { // dictionary longer than the window
+      let mut out = Output::new(4);
+      out.preset_dict(&[1,2,3,4,5,6]);
+
+      let buf = recv();
+      let (res, buf) = out.back_reference(4, 2, buf);
+      assert_eq!(res, Ok(2));
+      let (res, buf) = out.back_reference(5, 1, buf.unwrap());
+      assert_eq!(res, Err(~error::ReferenceOutOfWindow(5, 1, 4)));
+      let buf = out.flush(buf.unwrap()).unwrap();
+
+      assert_eq!(buf.unwrap(), ~[3,4]);
+    }
+  }
+
+  #[test]
+  fn test_window() {
+    let mut out = Output::new(5);
+    out.preset_dict(&[1,2]);
+    assert_eq!(out.window(), ~[1,2]);
+
+    let buf = recv();
+    let buf = send_chunk(&mut out, &[3,4,5], buf);
+    assert_eq!(out.window(), ~[1,2,3,4,5]);
+    let buf = out.send_literal(6, buf).unwrap();
+    let (_, buf) = out.back_reference(2, 2, buf);
+    assert_eq!(out.window(), ~[4,5,6,5,6]);
+    let _ = out.flush(buf.unwrap());
+
+    let mut resumed = Output::new(5);
+    resumed.preset_dict(out.window());
+    let (res, buf) = resumed.back_reference(5, 3, recv());
+    assert_eq!(res, Ok(3));
+    assert_eq!(resumed.flush(buf.unwrap()).unwrap().unwrap(), ~[4,5,6]);
+  }
+
+  #[test]
+  fn test_avail() {
+    let mut out = Output::new(8);
+    out.set_avail(7);
+
+    let buf = recv();
+    let buf = send_chunk(&mut out, &[2,3,5], buf);
+    let buf = out.send_literal(7, buf).unwrap();
+    let (res, buf) = out.back_reference(3, 5, buf);
+    assert_eq!(res, Ok(3));
+    assert_eq!(out.avail(), 0);
+    let (res, buf) = out.back_reference(3, 2, buf.unwrap());
+    assert_eq!(res, Ok(0));
+
+    out.set_avail(10);
+    let (res, buf) = out.back_reference(3, 2, buf.unwrap());
+    assert_eq!(res, Ok(2));
+    assert_eq!(out.avail(), 8);
+    let buf = out.flush(buf.unwrap()).unwrap();
+
+    assert_eq!(buf.unwrap(), ~[2,3,5,7,3,5,7,3,5]);
+  }
+
   #[test]
   fn test_back_reference_errors() {
     { // dist too long (window not full)
       let mut out = Output::new(5);
 
-      let buf: ~[u8] = ~[];
-      let buf = out.send_literal_chunk(&[1,2,3], buf);
+      let buf = recv();
+      let buf = send_chunk(&mut out, &[1,2,3], buf);
       let (res, buf) = out.back_reference(4, 2, buf);
       assert_eq!(res, Err(~error::ReferenceBeforeStart(4, 2, 3)));
-      let buf = out.send_literal_chunk(&[4,5,6], buf);
-      let buf = out.flush(buf);
+      let buf = send_chunk(&mut out, &[4,5,6], buf.unwrap());
+      let buf = out.flush(buf).unwrap();
 
-      assert_eq!(buf, ~[1,2,3,4,5,6]);
+      assert_eq!(buf.unwrap(), ~[1,2,3,4,5,6]);
     }
 
     { // dist too long (longer than the window)
       let mut out = Output::new(5);
 
-      let buf: ~[u8] = ~[];
-      let buf = out.send_literal_chunk(&[1,2,3,4,5,6,7,8], buf);
+      let buf = recv();
+      let buf = send_chunk(&mut out, &[1,2,3,4,5,6,7,8], buf);
       let (res, buf) = out.back_reference(8, 2, buf);
       assert_eq!(res, Err(~error::ReferenceOutOfWindow(8, 2, 5)));
-      let buf = out.send_literal_chunk(&[9,10,11], buf);
-      let buf = out.flush(buf);
+      let buf = send_chunk(&mut out, &[9,10,11], buf.unwrap());
+      let buf = out.flush(buf).unwrap();
 
-      assert_eq!(buf, ~[1,2,3,4,5,6,7,8,9,10,11]);
+      assert_eq!(buf.unwrap(), ~[1,2,3,4,5,6,7,8,9,10,11]);
     }
   }
+
+  #[test]
+  fn test_failing_recv() {
+    let mut out = Output::new(4);
+    let recv = FailingRecv::new(0);
+
+    let recv = send_literal_ok(&mut out, [1,2,3,4], recv);
+    let mut recv = match out.send_literal(5, recv) {
+        Err(recv) => recv,
+        Ok(_) => fail!(~"the receiver did not fail"),
+      };
+    assert_eq!(out.avail(), uint::max_value - 4);
+
+    recv.room = uint::max_value;
+    let mut recv = out.send_literal(5, recv).unwrap();
+    recv.room = 0;
+    let (res, recv) = out.back_reference(1, 5, recv);
+    assert_eq!(res, Ok(3));
+    let mut recv = match recv {
+        Err(recv) => recv,
+        Ok(_) => fail!(~"the receiver did not fail"),
+      };
+    assert_eq!(recv.buf, ~[1,2,3,4]);
+
+    recv.room = uint::max_value;
+    let (res, recv) = out.back_reference(1, 2, recv);
+    assert_eq!(res, Ok(2));
+    let recv = out.flush(recv.unwrap()).unwrap();
+    assert_eq!(recv.buf, ~[1,2,3,4,5,5,5,5,5,5]);
+  }
+
+  fn send_literal_ok(out: &mut Output, bytes: &[u8], recv: FailingRecv)
+    -> FailingRecv
+  {
+    let mut recv = recv;
+    let mut i = 0;
+    while i < bytes.len() {
+      recv = match out.send_literal(bytes[i], recv) {
+          Ok(recv) => recv,
+          Err(_) => fail!(~"the receiver failed"),
+        };
+      i = i + 1;
+    }
+    recv
+  }
 }
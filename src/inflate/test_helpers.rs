@@ -1,6 +1,10 @@
 #[cfg(test)];
 pub use inflate::inflater;
 pub use inflate::error;
+use bits;
+use std::rt::io::{Reader, io_error};
+use std::rt::io::mem::{MemReader};
+use std::{vec, cmp};
 
 pub fn inflate_ok(bytes: &[u8]) -> ~[u8] {
   let inflater = inflater::Inflater::new();
@@ -39,4 +43,119 @@ pub fn inflate_chunked_ok(chunk_len: uint, bytes: &[u8]) -> ~[u8] {
   }
 }
 
+pub fn inflate_into_ok(chunk_len: uint, buf_len: uint, bytes: &[u8]) -> ~[u8] {
+  let mut inflater = inflater::Inflater::new();
+  let mut buf = vec::from_elem(buf_len, 0u8);
+  let mut out: ~[u8] = ~[];
+  let mut pos = 0;
+
+  loop {
+    let end = cmp::min(pos + chunk_len, bytes.len());
+    let (result, consumed, written) =
+      inflater.input_into(bytes.slice(pos, end), buf.mut_slice(0, buf_len));
+    out.push_all(buf.slice(0, written));
+    pos = pos + consumed;
+
+    match result {
+      Left(_) if pos == bytes.len() && consumed == 0 && written == 0 =>
+        fail!("inflate_into_ok: inflater did not finish"),
+      Left(new_inflater) => { inflater = new_inflater },
+      Right(Ok(())) if pos == bytes.len() => { return out; },
+      other => fail!(fmt!("inflate_into_ok: unexpected %?", other)),
+    }
+  }
+}
+
+/// Receiver that collects the data and fails on the data that do not fit
+/// into `room`, which the collected data use up.
+pub struct FailingRecv {
+  buf: ~[u8],
+  room: uint,
+}
+
+impl FailingRecv {
+  pub fn new(room: uint) -> FailingRecv {
+    FailingRecv { buf: ~[], room: room }
+  }
+}
+
+impl bits::recv::TryRecv<u8> for FailingRecv {
+  pub fn try_receive(self, xs: &[u8]) -> Result<FailingRecv, FailingRecv> {
+    let mut recv = self;
+    if xs.len() > recv.room {
+      Err(recv)
+    } else {
+      recv.buf.push_all(xs);
+      recv.room = recv.room - xs.len();
+      Ok(recv)
+    }
+  }
+}
 
+/// Decodes `bytes` with a `bits::io::DecompressReader` that reads `buf_size`
+/// bytes at a time. Returns the decoded data, the error that stopped the
+/// decoding (which is not raised) and the input after the end of the data.
+pub fn decompress_bytes<E: Clone + ToStr, D: bits::io::BufDecoder<E>>
+  (decoder: D, bytes: ~[u8], buf_size: uint) -> (~[u8], Option<E>, ~[u8])
+{
+  let mut reader = bits::io::DecompressReader::new(MemReader::new(bytes),
+    decoder);
+  let mut out = ~[];
+  let mut buf = vec::from_elem(buf_size, 0u8);
+  do io_error::cond.trap(|_| { }).in {
+    loop {
+      match reader.read(buf.mut_slice(0, buf_size)) {
+        Some(len) => out.push_all(buf.slice(0, len)),
+        None => break,
+      }
+    }
+  }
+  let err = reader.error();
+  let (_, rest) = reader.close();
+  (out, err, rest)
+}
+
+/// Writes deflate blocks bit by bit.
+pub struct BlockWriter {
+  priv out: bits::BitWriter,
+}
+
+impl BlockWriter {
+  /// The `prefix` is written as is before the first block.
+  pub fn new(prefix: &[u8]) -> BlockWriter {
+    let mut out = bits::BitWriter::new();
+    out.write_bytes(prefix);
+    BlockWriter { out: out }
+  }
+
+  pub fn stored(&mut self, data: &[u8], last: bool) {
+    self.out.write_bits8(1, if last { 1 } else { 0 });
+    self.out.write_bits8(2, 0b00);
+    self.out.align();
+    self.out.write_u16(data.len() as u16);
+    self.out.write_u16(!data.len() as u16);
+    self.out.write_bytes(data);
+  }
+
+  /// Writes a block with fixed codes with `literals` (each less than 144)
+  /// followed by `refs` references with length 258 and distance `dist` (at
+  /// most 4).
+  pub fn fixed(&mut self, literals: &[u8], refs: uint, dist: uint, last: bool) {
+    self.out.write_bits8(1, if last { 1 } else { 0 });
+    self.out.write_bits8(2, 0b01);
+    for literals.iter().advance |&lit| {
+      self.out.write_rev_bits8(8, 0b0011_0000 + lit);
+    }
+    for refs.times {
+      self.out.write_rev_bits8(8, 0b1100_0101);
+      self.out.write_rev_bits8(5, (dist - 1) as u8);
+    }
+    self.out.write_rev_bits8(7, 0b000_0000);
+  }
+
+  pub fn finish(self) -> ~[u8] {
+    let mut out = self.out;
+    out.align();
+    out.send(~[])
+  }
+}
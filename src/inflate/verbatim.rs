@@ -1,6 +1,7 @@
 use bits;
 use inflate::error;
 use inflate::out;
+use std::cmp;
 
 pub struct VerbState {
   priv phase: VerbPhase,
@@ -23,13 +24,15 @@ impl VerbState {
     VerbState { phase: BeginPhase, len: 0, nlen: 0 }
   }
 
-  pub fn input <R: bits::recv::Recv<u8>> (
+  /// Stops also when the receiver fails, the data are then sent again when
+  /// the input continues.
+  pub fn input <R: bits::recv::TryRecv<u8>> (
     self,
     bit_reader: &mut bits::BitReader,
     out: &mut out::Output,
     recv: R
   )
-    -> (Either<VerbState, Result<(), ~error::Error>>, R)
+    -> (Either<VerbState, Result<(), ~error::Error>>, Result<R, R>)
   {
     let mut st = self;
     let mut recv = recv;
@@ -42,16 +45,26 @@ impl VerbState {
         },
         LenPhase() => {
           if bit_reader.has_bytes(2) { 
-            st.len = bit_reader.read_u16();
-            (true, NLenPhase)
+            match bit_reader.read_u16() {
+              Ok(len) => {
+                st.len = len;
+                (true, NLenPhase)
+              },
+              Err(err) => (true, ErrorPhase(~error::InternalError(err))),
+            }
           } else { 
             (false, LenPhase)
           }
         }
         NLenPhase() => {
           if bit_reader.has_bytes(2) {
-            st.nlen = bit_reader.read_u16();
-            (true, BeginDataPhase)
+            match bit_reader.read_u16() {
+              Ok(nlen) => {
+                st.nlen = nlen;
+                (true, BeginDataPhase)
+              },
+              Err(err) => (true, ErrorPhase(~error::InternalError(err))),
+            }
           } else {
             (false, NLenPhase) 
           }
@@ -64,31 +77,58 @@ impl VerbState {
           }
         },
         DataPhase(remaining) => {
-          let chunk = bit_reader.read_byte_chunk(remaining);
-          recv = out.send_literal_chunk(chunk, recv);
-
-          if chunk.len() < remaining {
-            (false, DataPhase(remaining - chunk.len()))
-          } else {
-            (true, EndPhase)
+          let limit = cmp::min(remaining, out.chunk_avail());
+          recv = match out.reserve(limit, recv) {
+              Ok(recv) => recv,
+              Err(recv) => {
+                st.phase = DataPhase(remaining);
+                return (Left(st), Err(recv))
+              },
+            };
+
+          match bit_reader.read_byte_chunk(limit) {
+            Ok(chunk) => {
+              out.send_literal_chunk(chunk);
+
+              if chunk.len() == remaining {
+                (true, EndPhase)
+              } else if chunk.len() == limit && limit > 0 {
+                // the window was full, there may be more input
+                (true, DataPhase(remaining - chunk.len()))
+              } else {
+                (false, DataPhase(remaining - chunk.len()))
+              }
+            },
+            Err(err) => (true, ErrorPhase(~error::InternalError(err))),
           }
         },
         EndPhase() => {
-          return (Right(Ok(())), recv)
+          return (Right(Ok(())), Ok(recv))
         },
         ErrorPhase(err) => {
-          return (Right(Err(err)), recv)
+          return (Right(Err(err)), Ok(recv))
         }
       };
 
       st.phase = next_phase;
       if !continue {
-        return (Left(st), recv)
+        return (Left(st), Ok(recv))
       }
     }
   }
 }
 
+impl VerbState {
+  /// Whether the state is stopped only because there is no space for output
+  /// (or no input for the data).
+  pub fn has_pending_output(&self) -> bool {
+    match self.phase {
+      DataPhase(remaining) => remaining > 0,
+      _ => false,
+    }
+  }
+}
+
 #[cfg(test)]
 mod test {
   use extra::test;
@@ -1,71 +1,259 @@
 use bits;
+use bits::status;
 use inflate::inflater;
+use inflate::limits;
 use checksums::adler32;
+use checksums::checksum;
 use zlib::error;
-
-struct Decoder {
-  priv stage: Stage,
+use std::{uint, util};
+
+// Unwraps the result of a read from a `ByteReader`, an error ends the stage.
+macro_rules! try_read(
+  ($res:expr) => (
+    match $res {
+      Ok(x) => x,
+      Err(err) => return (true, ErrorStage(~error::InternalError(err))),
+    }
+  )
+)
+
+/// Decodes a zlib stream. The Adler32 of the data is verified by a
+/// `checksum::Checksum`, which is `adler32::Adler32` unless replaced by
+/// `with_checksum`.
+struct Decoder<C> {
+  priv stage: Stage<C>,
   priv byte_buf: bits::ByteBuf,
+  priv dict: Option<~[u8]>,
+  priv limits: limits::Limits,
+  priv checksum: C,
 }
 
-enum Stage {
+enum Stage<C> {
   HeaderStage,
-  DataStage(inflater::Inflater, adler32::Adler32),
-  Adler32Stage(u32),
+  DictIdStage,
+  DataStage(inflater::Inflater, C),
+  Adler32Stage(C),
   ErrorStage(~error::Error),
   EndStage,
 }
 
-impl Decoder {
-  pub fn new() -> Decoder {
+impl Decoder<adler32::Adler32> {
+  pub fn new() -> Decoder<adler32::Adler32> {
+    Decoder { 
+      stage: HeaderStage,
+      byte_buf: bits::ByteBuf::new(),
+      dict: None,
+      limits: limits::Limits::unlimited(),
+      checksum: adler32::Adler32::new(),
+    }
+  }
+
+  /// Creates a decoder for streams compressed with a preset dictionary. The
+  /// Adler32 of `dict` must match the DICTID in the stream header. Streams
+  /// without a dictionary are decoded as usual.
+  pub fn new_with_dict(dict: &[u8]) -> Decoder<adler32::Adler32> {
     Decoder { 
       stage: HeaderStage,
       byte_buf: bits::ByteBuf::new(),
+      dict: Some(dict.to_owned()),
+      limits: limits::Limits::unlimited(),
+      checksum: adler32::Adler32::new(),
+    }
+  }
+}
+
+impl<C: checksum::Checksum> Decoder<C> {
+  /// Sets the limits on the decompressed data. Must be called before any
+  /// input.
+  pub fn with_limits(self, limits: limits::Limits) -> Decoder<C> {
+    let mut decoder = self;
+    decoder.limits = limits;
+    decoder
+  }
+
+  /// Replaces the checksum that verifies the data, for example with
+  /// `checksum::NoCheck` for trusted data. Must be called before the data
+  /// starts, fails otherwise.
+  pub fn with_checksum<D: checksum::Checksum>(self, checksum: D) -> Decoder<D> {
+    let Decoder { stage, byte_buf, dict, limits, checksum: _ } = self;
+    let stage = match stage {
+        HeaderStage => HeaderStage,
+        DictIdStage => DictIdStage,
+        _ => fail!("Decoder::with_checksum: the data has already started"),
+      };
+    Decoder {
+      stage: stage,
+      byte_buf: byte_buf,
+      dict: dict,
+      limits: limits,
+      checksum: checksum,
+    }
+  }
+
+  /// Decodes a chunk of input and sends the data to `recv`, see
+  /// `inflater::Inflater::feed`.
+  pub fn feed<'a, R: bits::recv::Recv<u8>>
+    (&mut self, chunk: &'a [u8], recv: R)
+    -> (status::Status<'a, (), ~error::Error>, R)
+  {
+    let (res, recv, _) = self.decode_chunk(chunk, uint::max_value,
+      bits::recv::Infallible(recv));
+    let recv = match recv { Ok(recv) | Err(recv) => recv.unwrap() };
+    match res {
+      Left(_) => (status::NeedInput, recv),
+      Right((Ok(()), rest)) => (status::Finished((), rest), recv),
+      Right((Err(err), rest)) => (status::Error(err, rest), recv),
     }
   }
 
   pub fn input<'a, R: bits::recv::Recv<u8>>
     (self, chunk: &'a [u8], recv: R) 
-    -> (Either<Decoder, (Result<(), ~error::Error>, &'a [u8])>, R)
+    -> (Either<Decoder<C>, (Result<(), ~error::Error>, &'a [u8])>, R)
+  {
+    let mut decoder = self;
+    let (status, recv) = decoder.feed(chunk, recv);
+    match status {
+      status::NeedInput => (Left(decoder), recv),
+      status::Finished((), rest) => (Right((Ok(()), rest)), recv),
+      status::Error(err, rest) => (Right((Err(err), rest)), recv),
+    }
+  }
+
+  /// Decodes into `buf` and stops when it is full, see
+  /// `inflater::Inflater::input_into`.
+  pub fn input_into(self, chunk: &[u8], buf: &mut [u8])
+    -> (Either<Decoder<C>, Result<(), ~error::Error>>, uint, uint)
+  {
+    let avail = buf.len();
+    match self.input_limited(chunk, avail, bits::recv::SliceRecv::new(buf)) {
+      (Left((decoder, rest)), _, written) =>
+        (Left(decoder), chunk.len() - rest.len(), written),
+      (Right((res, rest)), _, written) =>
+        (Right(res), chunk.len() - rest.len(), written),
+    }
+  }
+
+  /// Like `input`, but sends at most `avail` bytes to `recv`, see
+  /// `inflater::Inflater::input_limited`.
+  pub fn input_limited<'a, R: bits::recv::Recv<u8>>
+    (self, chunk: &'a [u8], avail: uint, recv: R) 
+    -> (Either<(Decoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      R, uint)
+  {
+    match self.try_input_limited(chunk, avail, bits::recv::Infallible(recv)) {
+      (res, Ok(recv), written) | (res, Err(recv), written) =>
+        (res, recv.unwrap(), written),
+    }
+  }
+
+  /// Like `input`, but the receiver may fail, see
+  /// `inflater::Inflater::try_input`.
+  pub fn try_input<'a, R: bits::recv::TryRecv<u8>>
+    (self, chunk: &'a [u8], recv: R)
+    -> (Either<(Decoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>)
+  {
+    match self.try_input_limited(chunk, uint::max_value, recv) {
+      (res, recv, _) => (res, recv),
+    }
+  }
+
+  /// Combines `try_input` and `input_limited`, see
+  /// `inflater::Inflater::try_input_limited`.
+  pub fn try_input_limited<'a, R: bits::recv::TryRecv<u8>>
+    (self, chunk: &'a [u8], avail: uint, recv: R) 
+    -> (Either<(Decoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, uint)
+  {
+    let mut decoder = self;
+    let (res, recv, written) = decoder.decode_chunk(chunk, avail, recv);
+    match res {
+      Left(rest) => (Left((decoder, rest)), recv, written),
+      Right(res) => (Right(res), recv, written),
+    }
+  }
+
+  // returns either the unconsumed input when the decoder stopped, or the
+  // result when the stream ended
+  priv fn decode_chunk<'a, R: bits::recv::TryRecv<u8>>
+    (&mut self, chunk: &'a [u8], avail: uint, recv: R) 
+    -> (Either<&'a [u8], (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>, uint)
   {
-    let Decoder { stage, byte_buf } = self;
-    let mut stage = stage;
+    let mut stage = util::replace(&mut self.stage, EndStage);
+    let byte_buf = util::replace(&mut self.byte_buf, bits::ByteBuf::new());
     let mut recv = recv;
+    let mut recv_failed = false;
+    let mut avail_left = avail;
     let mut reader = bits::ByteReader::new(byte_buf, chunk);
 
     loop {
       let (continue, new_stage) = match stage {
         HeaderStage() => 
-          Decoder::header_stage(&mut reader),
+          Decoder::header_stage(self.dict.is_some(), &self.limits,
+            &self.checksum, &mut reader),
+        DictIdStage() =>
+          Decoder::dict_id_stage(&self.dict, &self.limits, &self.checksum,
+            &mut reader),
         DataStage(inflater, a32) => {
-          let (new_recv, continue, stage) = Decoder::data_stage(
-            inflater, a32, &mut reader, recv);
-          recv = new_recv;
-          (continue, stage)
+          let mut inflater = inflater;
+          let (recv_res, new_avail, continue, next) = Decoder::data_stage(
+            &mut inflater, a32, avail_left, &mut reader, recv);
+          recv = match recv_res {
+              Ok(new_recv) => new_recv,
+              Err(new_recv) => { recv_failed = true; new_recv },
+            };
+          avail_left = new_avail;
+          (continue, match next {
+              Left(a32) => DataStage(inflater, a32),
+              Right(stage) => stage,
+            })
+        },
+        Adler32Stage(computed) => 
+          Decoder::adler32_stage(computed, &mut reader),
+        EndStage() => {
+          let res = match reader.close_to_rest() {
+              Ok(rest) => (Ok(()), rest),
+              Err(err) => {
+                let err = ~error::InternalError(err);
+                self.stage = ErrorStage(err.clone());
+                (Err(err), chunk.slice(chunk.len(), chunk.len()))
+              },
+            };
+          return (Right(res), Ok(recv), avail - avail_left)
+        },
+        ErrorStage(err) => {
+          let (_, rest) = reader.close();
+          self.stage = ErrorStage(err.clone());
+          return (Right((Err(err), rest)), Ok(recv), avail - avail_left)
         },
-        Adler32Stage(computed_checksum) => 
-          Decoder::adler32_stage(computed_checksum, &mut reader),
-        EndStage() => 
-          return (Right((Ok(()), reader.close_to_rest())), recv),
-        ErrorStage(err) => 
-          return (Right((Err(err), reader.close_to_rest())), recv),
       };
 
       if continue {
         stage = new_stage;
       } else {
-        let decoder = Decoder { stage: new_stage, byte_buf: reader.close_to_buf() };
-        return (Left(decoder), recv)
+        // when the output is full or the receiver failed, the rest of the
+        // input is left to the caller
+        let (byte_buf, rest) = if avail_left == 0 || recv_failed {
+            reader.close()
+          } else {
+            (reader.close_to_buf(), chunk.slice(chunk.len(), chunk.len()))
+          };
+        self.stage = new_stage;
+        self.byte_buf = byte_buf;
+        let recv = if recv_failed { Err(recv) } else { Ok(recv) };
+        return (Left(rest), recv, avail - avail_left)
       }
     }
   }
 
-  fn header_stage(reader: &mut bits::ByteReader)
-    -> (bool, Stage)
+  fn header_stage(has_dict: bool, limits: &limits::Limits, checksum: &C,
+    reader: &mut bits::ByteReader)
+    -> (bool, Stage<C>)
   {
     if reader.has_bytes(2) {
-      let cmf = reader.read_byte();
-      let flg = reader.read_byte();
+      let header = try_read!(reader.read_u16_be());
+      let (cmf, flg) = ((header >> 8) as u8, header as u8);
 
       let cm = cmf & 0b1111;
       let cinfo = (cmf >> 4) & 0b1111;
@@ -82,64 +270,155 @@ impl Decoder {
         (true, ErrorStage(~error::WindowTooLong(win_size)))
       } else if (cmf as uint * 256 + flg as uint) % 31 != 0 {
         (true, ErrorStage(~error::BadHeaderChecksum(cmf, flg)))
-      } else if fdict != 0 {
+      } else if fdict != 0 && !has_dict {
         (true, ErrorStage(~error::DictionaryUsed))
+      } else if fdict != 0 {
+        (true, DictIdStage)
       } else {
-        (true, DataStage(inflater::Inflater::new(), adler32::Adler32::new()))
+        let inflater = inflater::Inflater::new().with_limits(limits.clone());
+        (true, DataStage(inflater, checksum.clone().reset()))
       }
     } else {
       (false, HeaderStage)
     }
   }
 
-  fn data_stage<R: bits::recv::Recv<u8>>(
-    inflater: inflater::Inflater, a32: adler32::Adler32,
+  fn dict_id_stage(dict: &Option<~[u8]>, limits: &limits::Limits,
+    checksum: &C, reader: &mut bits::ByteReader)
+    -> (bool, Stage<C>)
+  {
+    if reader.has_bytes(4) {
+      let dict_id = try_read!(reader.read_u32_be());
+      let dict = match *dict {
+          Some(ref dict) => dict.slice(0, dict.len()),
+          None => fail!(~"unreachable"),
+        };
+      let dict_checksum = adler32::Adler32::new().update(dict).adler32();
+
+      if dict_id == dict_checksum {
+        let inflater = inflater::Inflater::new_with_dict(dict)
+          .with_limits(limits.clone());
+        (true, DataStage(inflater, checksum.clone().reset()))
+      } else {
+        (true, ErrorStage(~error::DictionaryMismatch(dict_id, dict_checksum)))
+      }
+    } else {
+      (false, DictIdStage)
+    }
+  }
+
+  // the checksum gets only the data that the receiver accepted; returns the
+  // checksum while the data continue, or the next stage
+  fn data_stage<R: bits::recv::TryRecv<u8>>(
+    inflater: &mut inflater::Inflater, a32: C, avail: uint,
     reader: &mut bits::ByteReader, recv: R)
-    -> (R, bool, Stage)
+    -> (Result<R, R>, uint, bool, Either<C, Stage<C>>)
   {
     if reader.has_some_bytes() {
-      do reader.consume_chunk((inflater, a32, recv)) 
-        |(inflater, a32, recv), chunk| {
-
-        let (res, (new_recv, new_a32)) = inflater.input(chunk, (recv, a32));
+      let ((n_recv, n_avail, continue, next), consumed) =
+        do reader.consume_chunk((inflater, a32, recv))
+          |(inflater, a32, recv), chunk|
+      {
+        let (res, recv_res, written) = inflater.try_feed_limited(chunk, avail,
+          (recv, a32));
+        let (new_recv, new_a32) = match recv_res {
+            Ok((recv, a32)) => (Ok(recv), a32),
+            Err((recv, a32)) => (Err(recv), a32),
+          };
+        let new_avail = avail - written;
 
         match res {
-          Left(new_inflater) => 
-            ((new_recv, true, DataStage(new_inflater, new_a32)), None),
+          Left(rest) => {
+            let continue = new_avail > 0 && new_recv.is_ok();
+            ((new_recv, new_avail, continue, Left(new_a32)), Some(rest))
+          },
           Right((Ok(()), rest)) =>
-            ((new_recv, true, Adler32Stage(new_a32.adler32())), Some(rest)),
+            ((new_recv, new_avail, true,
+              Right(Adler32Stage(new_a32))), Some(rest)),
           Right((Err(err), rest)) =>
-            ((new_recv, true, ErrorStage(~error::InflateError(err))), Some(rest)),
+            ((new_recv, new_avail, true,
+              Right(ErrorStage(~error::InflateError(err)))), Some(rest)),
         }
+      };
+      match consumed {
+        Ok(()) => (n_recv, n_avail, continue, next),
+        Err(err) => (n_recv, n_avail, true,
+          Right(ErrorStage(~error::InternalError(err)))),
       }
     } else {
-      (recv, false, DataStage(inflater, a32))
+      (Ok(recv), avail, false, Left(a32))
     }
   }
 
-  fn adler32_stage(computed_checksum: u32, reader: &mut bits::ByteReader)
-    -> (bool, Stage)
+  fn adler32_stage(computed: C, reader: &mut bits::ByteReader)
+    -> (bool, Stage<C>)
   {
     if reader.has_bytes(4) {
-      let read_checksum = reader.read_u32_be();
-      if computed_checksum == read_checksum {
+      let read_checksum = try_read!(reader.read_u32_be());
+      if computed.verify(read_checksum as u64) {
         (true, EndStage)
       } else {
         (true, ErrorStage(~error::BadDataChecksum
-          (computed_checksum, read_checksum)))
+          (computed.value(), read_checksum as u64)))
       }
     } else {
-      (false, Adler32Stage(computed_checksum))
+      (false, Adler32Stage(computed))
     }
   }
+
+  /// Checks that the input did not end inside the stream.
+  pub fn finish(self) -> Result<(), ~error::Error> {
+    match self.stage {
+      EndStage => Ok(()),
+      ErrorStage(err) => Err(err),
+      _ => Err(~error::UnexpectedEnd),
+    }
+  }
+}
+
+impl<C: checksum::Checksum> bits::io::StreamDecoder<~error::Error>
+  for Decoder<C>
+{
+  pub fn end_input(self) -> Result<(), ~error::Error> {
+    self.finish()
+  }
+}
+
+impl<C: checksum::Checksum> bits::io::BufDecoder<~error::Error>
+  for Decoder<C>
+{
+  pub fn decode_into(self, chunk: &[u8], buf: &mut [u8])
+    -> (Either<Decoder<C>, Result<(), ~error::Error>>, uint, uint)
+  {
+    self.input_into(chunk, buf)
+  }
+}
+
+impl<C: checksum::Checksum> bits::io::RecvDecoder<~error::Error>
+  for Decoder<C>
+{
+  pub fn decode<'a, R: bits::recv::TryRecv<u8>>(self, chunk: &'a [u8], recv: R)
+    -> (Either<(Decoder<C>, &'a [u8]), (Result<(), ~error::Error>, &'a [u8])>,
+      Result<R, R>)
+  {
+    self.try_input(chunk, recv)
+  }
 }
 
 #[cfg(test)]
 mod test {
-  use std::uint;
+  use std::{vec, uint, cmp, util};
   use zlib::decoder;
+  use zlib::encoder;
   use zlib::error;
   use inflate;
+  use inflate::limits;
+  use checksums::adler32;
+  use checksums::checksum;
+  use bits;
+  use bits::status;
+  use std::rt::io::{Reader, Writer, Decorator, io_error};
+  use std::rt::io::mem::{MemReader, MemWriter};
 
   fn decode_ok(bytes: &[u8]) -> ~[u8] {
     let decoder = decoder::Decoder::new();
@@ -180,6 +459,29 @@ mod test {
     };
   }
 
+  fn decode_into_ok(chunk_len: uint, buf_len: uint, bytes: &[u8]) -> ~[u8] {
+    let mut decoder = decoder::Decoder::new();
+    let mut buf = vec::from_elem(buf_len, 0u8);
+    let mut out: ~[u8] = ~[];
+    let mut pos = 0;
+
+    loop {
+      let end = cmp::min(pos + chunk_len, bytes.len());
+      let (result, consumed, written) =
+        decoder.input_into(bytes.slice(pos, end), buf.mut_slice(0, buf_len));
+      out.push_all(buf.slice(0, written));
+      pos = pos + consumed;
+
+      match result {
+        Left(_) if pos == bytes.len() && consumed == 0 && written == 0 =>
+          fail!("decode_into_ok: decoder did not finish"),
+        Left(new_decoder) => { decoder = new_decoder },
+        Right(Ok(())) if pos == bytes.len() => { return out },
+        x => fail!(fmt!("decode_into_ok: unexpected %?", x)),
+      }
+    }
+  }
+
   #[test]
   fn test_decode_ok() {
     assert_eq!(decode_ok(&[
@@ -261,6 +563,57 @@ mod test {
     );
   }
 
+  #[test]
+  fn test_decode_no_check() {
+    // the same stream as in `test_decode_err`, with a bad Adler32
+    let bytes = [
+        0b01111000, 0b10011100, 0b01100011, 0b01100010, 0b01100110,
+        0b01100101, 0b11100111, 0b00000110, 0b00000000, 0b00000000,
+        0b01000011, 0b11100000, 0b00011101, 7, 8, 9,
+      ];
+    let decoder = decoder::Decoder::new().with_checksum(checksum::NoCheck);
+    match decoder.input(bytes, ~[]) {
+      (Right((Ok(()), rest)), buf) => {
+        assert_eq!(rest, &[7, 8, 9]);
+        assert_eq!(buf, ~[2, 3, 5, 7, 11]);
+      },
+      x => fail!(fmt!("unexpected %?", x)),
+    }
+  }
+
+  #[test]
+  fn test_decode_dict() {
+    fn decode_dict<'a>(dict: &[u8], bytes: &'a [u8])
+      -> (Result<(), ~error::Error>, &'a [u8], ~[u8])
+    {
+      match decoder::Decoder::new_with_dict(dict).input(bytes, ~[]) {
+        (Right((res, rest)), buf) => (res, rest, buf),
+        (Left(_), _) => fail!("decode_dict: decoder did not finish"),
+      }
+    }
+
+    // FDICT set, DICTID is the Adler32 of [1, 2, 3] and the data is a
+    // single reference (len 3, dist 3) into the dictionary
+    let bytes = [
+        0b01111000, 0b10111011, 0x00, 0x0d, 0x00, 0x07,
+        0b00000011, 0b00100010, 0b00000000, 0x00, 0x0d, 0x00, 0x07
+      ];
+
+    assert_eq!(decode_dict(&[1, 2, 3], bytes), (Ok(()), &[], ~[1, 2, 3]));
+    assert_eq!(decode_dict(&[1, 2, 4], bytes),
+      (Err(~error::DictionaryMismatch(0x000d0007, 0x000e0008)),
+        &[0b00000011, 0b00100010, 0b00000000, 0x00, 0x0d, 0x00, 0x07],
+        ~[]));
+
+    // the dictionary is not needed
+    assert_eq!(decode_dict(&[1, 2, 3], &[
+        0b01111000, 0b10011100, 0b01100011, 0b01100100, 0b01100010,
+        0b00000110, 0b00000000, 0b00000000, 0b00001101, 0b00000000,
+        0b00000111
+      ]),
+      (Ok(()), &[], ~[1, 2, 3]));
+  }
+
   #[test]
   fn test_decode_chunked() {
     for uint::range(1, 10) |chunk_len| {
@@ -299,4 +652,202 @@ mod test {
     }
   }
 
+  #[test]
+  fn test_decode_into() {
+    let bytes = [
+        0x78u8, 0x9c, 0x25, 0x8c, 0xc1, 0x09, 0x00, 0x40, 0x0c, 0xc2,
+        0x66, 0x15, 0x1f, 0x2e, 0x90, 0xfd, 0xb9, 0x94, 0xb3, 0x0f,
+        0xc1, 0x46, 0xbb, 0x6e, 0x19, 0x04, 0x2d, 0x25, 0x99, 0x6a,
+        0x83, 0x0f, 0xe8, 0x45, 0x8c, 0x6a, 0x05, 0x21, 0x35, 0x44,
+        0x85, 0x92, 0x2e, 0x87, 0x5c, 0xf4, 0xaf, 0xd7, 0xd2, 0x32,
+        0x0b, 0xce, 0xc8, 0xef, 0x01, 0xfe, 0xbb, 0x28, 0x78
+      ];
+    let data = decode_ok(bytes);
+
+    let buf_lens = [1u, 2, 5, 13, 1000];
+    for uint::range(1, 10) |chunk_len| {
+      for buf_lens.iter().advance |&buf_len| {
+        assert_eq!(decode_into_ok(chunk_len, buf_len, bytes), data.clone());
+      }
+    }
+  }
+
+  // decodes `bytes` with a `DecompressReader`, the raised errors are ignored
+  fn decompress_bytes<E: Clone + ToStr, D: bits::io::BufDecoder<E>>
+    (decoder: D, bytes: ~[u8], buf_size: uint) -> (~[u8], Option<E>, ~[u8])
+  {
+    let mut reader = bits::io::DecompressReader::new(MemReader::new(bytes),
+      decoder);
+    let mut out = ~[];
+    let mut buf = vec::from_elem(buf_size, 0u8);
+    do io_error::cond.trap(|_| { }).in {
+      loop {
+        match reader.read(buf.mut_slice(0, buf_size)) {
+          Some(len) => out.push_all(buf.slice(0, len)),
+          None => break,
+        }
+      }
+    }
+    let err = reader.error();
+    let (_, rest) = reader.close();
+    (out, err, rest)
+  }
+
+  #[test]
+  fn test_decompress_reader() {
+    fn read_all(bytes: ~[u8]) -> (~[u8], Option<~error::Error>, ~[u8]) {
+      decompress_bytes(decoder::Decoder::new(), bytes, 13)
+    }
+
+    let zeros = vec::from_elem(50_000, 0u8);
+    let (encoder, bytes) = encoder::Encoder::new(6).unwrap().input(zeros, ~[]);
+    let bytes = encoder.finish(bytes);
+
+    let (out, err, rest) = read_all(vec::append(bytes.clone(), [1, 2, 3]));
+    assert_eq!(out, zeros);
+    assert_eq!(err, None);
+    assert_eq!(rest, ~[1, 2, 3]);
+
+    let (_, err, _) = read_all(bytes.slice(0, bytes.len() - 2).to_owned());
+    assert_eq!(err, Some(~error::UnexpectedEnd));
+  }
+
+  #[test]
+  fn test_decompress_writer() {
+    let zeros = vec::from_elem(50_000, 0u8);
+    let (encoder, bytes) = encoder::Encoder::new(6).unwrap().input(zeros, ~[]);
+    let bytes = vec::append(encoder.finish(bytes), [1, 2, 3]);
+
+    let mut writer = bits::io::DecompressWriter::new(
+      MemWriter::new(), decoder::Decoder::new());
+    for bytes.chunk_iter(7).advance |chunk| {
+      writer.write(chunk);
+    }
+    let (res, inner, rest) = writer.finish();
+    assert_eq!(res, Ok(()));
+    assert_eq!(inner.inner(), zeros);
+    assert_eq!(rest, ~[1, 2, 3]);
+  }
+
+  // keeps the decoder between the chunks
+  struct Unpacker {
+    decoder: decoder::Decoder<adler32::Adler32>,
+    out: ~[u8],
+  }
+
+  impl Unpacker {
+    fn push(&mut self, chunk: &[u8]) -> bool {
+      let out = util::replace(&mut self.out, ~[]);
+      let (status, out) = self.decoder.feed(chunk, out);
+      self.out = out;
+      match status {
+        status::NeedInput => false,
+        status::Finished((), []) => true,
+        other => fail!(fmt!("unexpected %?", other)),
+      }
+    }
+  }
+
+  #[test]
+  fn test_feed() {
+    let data = vec::from_fn(10_000, |i| (i % 13) as u8);
+    let (encoder, bytes) = encoder::Encoder::new(6).unwrap().input(data, ~[]);
+    let bytes = encoder.finish(bytes);
+
+    let mut unpacker = Unpacker { decoder: decoder::Decoder::new(), out: ~[] };
+    let mut finished = false;
+    for bytes.chunk_iter(10).advance |chunk| {
+      assert!(!finished);
+      finished = unpacker.push(chunk);
+    }
+    assert!(finished);
+    assert_eq!(unpacker.out, data);
+
+    let mut decoder = decoder::Decoder::new();
+    match decoder.feed(&[0x78, 0x9c, 0xff], ()) {
+      (status::Error(~error::InflateError(_), []), ()) => { },
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+    assert!(decoder.finish().is_err());
+  }
+
+  // fails every other call
+  struct FlakyRecv {
+    buf: ~[u8],
+    fail: bool,
+  }
+
+  impl bits::recv::TryRecv<u8> for FlakyRecv {
+    pub fn try_receive(self, xs: &[u8]) -> Result<FlakyRecv, FlakyRecv> {
+      let mut recv = self;
+      recv.fail = !recv.fail;
+      if recv.fail {
+        Err(recv)
+      } else {
+        recv.buf.push_all(xs);
+        Ok(recv)
+      }
+    }
+  }
+
+  #[test]
+  fn test_try_input() {
+    let data = vec::from_fn(100_000, |i| (i % 251) as u8);
+    let (encoder, bytes) = encoder::Encoder::new(6).unwrap().input(data, ~[]);
+    let bytes = encoder.finish(bytes);
+
+    let mut decoder = decoder::Decoder::new();
+    let mut recv = FlakyRecv { buf: ~[], fail: false };
+    let mut rest = bytes.slice(0, bytes.len());
+    let mut failures = 0u;
+    loop {
+      match decoder.try_input(rest, recv) {
+        (Left((new_decoder, new_rest)), Err(new_recv)) => {
+          decoder = new_decoder;
+          rest = new_rest;
+          recv = new_recv;
+          failures = failures + 1;
+        },
+        (Right((Ok(()), [])), Ok(new_recv)) => {
+          // the checksum was verified, so no data were lost or repeated
+          assert_eq!(new_recv.buf, data);
+          break;
+        },
+        other => fail!(fmt!("unexpected %?", other)),
+      }
+    }
+    assert!(failures > 0);
+  }
+
+  #[test]
+  fn test_decode_limits() {
+    fn decode_limited(limits: limits::Limits, bytes: &[u8])
+      -> (Result<(), ~error::Error>, u32)
+    {
+      let decoder = decoder::Decoder::new().with_limits(limits);
+      match decoder.input(bytes, 0u32) {
+        (Right((res, _)), count) => (res, count),
+        other => fail!(fmt!("unexpected %?", other)),
+      }
+    }
+
+    let zeros = vec::from_elem(300_000, 0u8);
+    let (encoder, bytes) = encoder::Encoder::new(9).unwrap().input(zeros, ~[]);
+    let bytes = encoder.finish(bytes);
+    assert!(bytes.len() < 1000);
+
+    assert_eq!(decode_limited(limits::Limits::unlimited(), bytes),
+      (Ok(()), 300_000));
+    assert_eq!(decode_limited(
+        limits::Limits { max_output: Some(100_000), max_ratio: None }, bytes),
+      (Err(~error::InflateError(~inflate::OutputLimitExceeded(100_000))),
+        100_000));
+
+    match decode_limited(
+        limits::Limits { max_output: None, max_ratio: Some(100) }, bytes) {
+      (Err(~error::InflateError(~inflate::RatioLimitExceeded(100))), count) =>
+        assert!(count < 300_000),
+      other => fail!(fmt!("unexpected %?", other)),
+    }
+  }
 }
@@ -0,0 +1,141 @@
+use bits;
+use checksums::adler32;
+use deflate;
+
+/// Compresses data into a zlib stream (RFC 1950) with a 32K window and no
+/// preset dictionary. The header is written before the first compressed byte,
+/// the Adler32 trailer by `finish`.
+pub struct Encoder {
+  priv header: Option<(u8, u8)>,
+  priv deflater: deflate::Deflater,
+  priv adler32: adler32::Adler32,
+}
+
+impl Encoder {
+  /// Creates an encoder with the given compression level (see
+  /// `deflate::Deflater::new`), which is also recorded in FLEVEL. Returns
+  /// `None` if the level is out of range.
+  pub fn new(level: uint) -> Option<Encoder> {
+    do deflate::Deflater::new(level).map_consume |deflater| {
+      Encoder {
+        header: Some(Encoder::header(level)),
+        deflater: deflater,
+        adler32: adler32::Adler32::new(),
+      }
+    }
+  }
+
+  pub fn input<R: bits::recv::Recv<u8>>(self, chunk: &[u8], recv: R)
+    -> (Encoder, R)
+  {
+    let Encoder { header, deflater, adler32 } = self;
+    let recv = Encoder::write_header(header, recv);
+    let (deflater, recv) = deflater.input(chunk, recv);
+
+    let encoder = Encoder {
+      header: None,
+      deflater: deflater,
+      adler32: adler32.update(chunk),
+    };
+    (encoder, recv)
+  }
+
+  pub fn finish<R: bits::recv::Recv<u8>>(self, recv: R) -> R {
+    let Encoder { header, deflater, adler32 } = self;
+    let recv = Encoder::write_header(header, recv);
+    let recv = deflater.finish(recv);
+
+    let mut trailer = bits::ByteWriter::new();
+    trailer.write_u32_be(adler32.adler32());
+    trailer.send(recv)
+  }
+
+  fn header(level: uint) -> (u8, u8) {
+    let cmf = 0x78u8; // deflate with 32K window
+    let flevel = match level {
+        0..1 => 0u8,
+        2..5 => 1,
+        6 => 2,
+        _ => 3,
+      };
+
+    let flg = flevel << 6;
+    let fcheck = (31 - (cmf as uint * 256 + flg as uint) % 31) % 31;
+    (cmf, flg | fcheck as u8)
+  }
+
+  fn write_header<R: bits::recv::Recv<u8>>(header: Option<(u8, u8)>, recv: R)
+    -> R
+  {
+    match header {
+      Some((cmf, flg)) => recv.receive(&[cmf, flg]),
+      None => recv,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::{vec, uint, rand};
+  use std::rand::{RngUtil};
+  use zlib::decoder;
+  use zlib::encoder;
+
+  fn encode(level: uint, bytes: &[u8]) -> ~[u8] {
+    let encoder = encoder::Encoder::new(level).unwrap();
+    let (encoder, out) = encoder.input(bytes, ~[]);
+    encoder.finish(out)
+  }
+
+  fn decode_ok(bytes: &[u8]) -> ~[u8] {
+    let decoder = decoder::Decoder::new();
+
+    match decoder.input(bytes, ~[]) {
+      (Right((Ok(()), [])), buf) => buf,
+      x => fail!(fmt!("decode_ok: unexpected %?", x)),
+    }
+  }
+
+  #[test]
+  fn test_encode_header() {
+    assert_eq!(encode(6, &[]), ~[0x78, 0x9c, 0b011, 0, 0, 0, 0, 1]);
+    assert_eq!(encode(1, &[]).slice(0, 2), &[0x78, 0x01]);
+    assert_eq!(encode(4, &[]).slice(0, 2), &[0x78, 0x5e]);
+    assert_eq!(encode(9, &[]).slice(0, 2), &[0x78, 0xda]);
+  }
+
+  #[test]
+  fn test_encode_round_trip() {
+    let mut rng = rand::IsaacRng::new_seeded(&[42]);
+    let random = rng.gen_bytes(50_000);
+    let text = do vec::from_fn(50_000) |i| { (i % 251 + i / 1000) as u8 };
+
+    for uint::range(0, 10) |level| {
+      assert_eq!(decode_ok(encode(level, random)), random.clone());
+      assert_eq!(decode_ok(encode(level, text)), text.clone());
+    }
+  }
+
+  #[test]
+  fn test_encode_chunked() {
+    let text = do vec::from_fn(30_000) |i| { (i % 251 + i / 1000) as u8 };
+    let whole = encode(6, text);
+
+    let chunk_lens = [1u, 100, 4096];
+    for chunk_lens.iter().advance |&chunk_len| {
+      let mut encoder = encoder::Encoder::new(6).unwrap();
+      let mut out = ~[];
+      for text.chunk_iter(chunk_len).advance |chunk| {
+        let (new_encoder, new_out) = encoder.input(chunk, out);
+        encoder = new_encoder;
+        out = new_out;
+      }
+      assert_eq!(encoder.finish(out), whole.clone());
+    }
+  }
+
+  #[test]
+  fn test_encode_bad_level() {
+    assert!(encoder::Encoder::new(10).is_none());
+  }
+}
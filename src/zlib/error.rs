@@ -1,13 +1,18 @@
+use bits;
 use inflate::error;
+use std::u64;
 
 #[deriving(Clone,Eq)]
 pub enum Error {
   BadCompressionMethod(uint),
   WindowTooLong(uint),
   BadHeaderChecksum(u8, u8),
-  BadDataChecksum(u32, u32),
+  BadDataChecksum(u64, u64),
   DictionaryUsed(),
+  DictionaryMismatch(u32, u32),
   InflateError(~error::Error),
+  UnexpectedEnd(),
+  InternalError(bits::error::Error),
 }
 
 impl ToStr for Error {
@@ -21,13 +26,21 @@ impl ToStr for Error {
       BadHeaderChecksum(cmf, flg) =>
         fmt!("Bad header: 0x%02x 0x%02x", cmf as uint, flg as uint),
       BadDataChecksum(expected, got) =>
-        fmt!("Bad Adler32 checksum of the data, \
-            computed 0x%08x but in file there is 0x%08x",
-          expected as uint, got as uint),
+        fmt!("Bad checksum of the data, \
+            computed 0x%s but in file there is 0x%s",
+          u64::to_str_radix(expected, 16), u64::to_str_radix(got, 16)),
       DictionaryUsed() =>
         fmt!("Preset dictionary used"),
+      DictionaryMismatch(dict_id, dict_checksum) =>
+        fmt!("Stream needs preset dictionary 0x%08x, \
+            but the given one has Adler32 0x%08x",
+          dict_id as uint, dict_checksum as uint),
       InflateError(ref err) =>
         fmt!("Inflate error: %s", err.to_str()),
+      UnexpectedEnd() =>
+        fmt!("Unexpected end of input inside a zlib stream"),
+      InternalError(ref err) =>
+        fmt!("Internal error: %s", err.to_str()),
     }
   }
 }
@@ -0,0 +1,242 @@
+// Lists the blocks of a raw deflate, zlib or gzip stream, similar to infgen.
+//
+// usage: disasm [-v] [--raw | --zlib | --gzip] FILE
+//
+// With -v every literal and match is listed. The format is guessed from the
+// first bytes unless given.
+
+extern mod comprsr_inflate (vers = "0.0.1", author = "github.com/honzasp");
+extern mod comprsr_gzip (vers = "0.0.1", author = "github.com/honzasp");
+extern mod comprsr_checksums (vers = "0.0.1", author = "github.com/honzasp");
+
+use comprsr_inflate::inflate::disasm;
+use comprsr_inflate::bits::recv;
+use comprsr_gzip::gzip::{hdr_decoder, header};
+use comprsr_checksums::checksums::{adler32, crc32};
+use std::{io, os};
+
+enum Format {
+  Raw,
+  Zlib,
+  Gzip,
+}
+
+fn main() {
+  let args = os::args();
+  let mut details = false;
+  let mut format = None;
+  let mut path = None;
+
+  for args.tail().iter().advance |arg| {
+    if *arg == ~"-v" {
+      details = true;
+    } else if *arg == ~"--raw" {
+      format = Some(Raw);
+    } else if *arg == ~"--zlib" {
+      format = Some(Zlib);
+    } else if *arg == ~"--gzip" {
+      format = Some(Gzip);
+    } else {
+      path = Some(arg.clone());
+    }
+  }
+
+  let path = match path {
+      Some(path) => path,
+      None => {
+        io::stderr().write_line("usage: disasm [-v] [--raw | --zlib | --gzip] FILE");
+        os::set_exit_status(2);
+        return;
+      },
+    };
+
+  let bytes = match io::read_whole_file(&Path(path)) {
+      Ok(bytes) => bytes,
+      Err(err) => {
+        io::stderr().write_line(err);
+        os::set_exit_status(2);
+        return;
+      },
+    };
+
+  let format = match format {
+      Some(format) => format,
+      None => guess_format(bytes),
+    };
+
+  let ok = match format {
+      Raw => disasm_raw(bytes, details),
+      Zlib => disasm_zlib(bytes, details),
+      Gzip => disasm_gzip(bytes, details),
+    };
+  if !ok {
+    os::set_exit_status(1);
+  }
+}
+
+fn guess_format(bytes: &[u8]) -> Format {
+  if bytes.len() >= 2 && bytes[0] == 0x1f && bytes[1] == 0x8b {
+    Gzip
+  } else if bytes.len() >= 2 && bytes[0] & 0x0f == 8
+      && (bytes[0] as uint * 256 + bytes[1] as uint) % 31 == 0 {
+    Zlib
+  } else {
+    Raw
+  }
+}
+
+// Prints the blocks and returns the length of the deflate stream in bytes.
+fn disasm_deflate<R: recv::Recv<u8>>(bytes: &[u8], details: bool, recv: R)
+  -> Option<(uint, R)>
+{
+  let print: &fn(&[disasm::Event]) = |events| {
+    for events.iter().advance |event| {
+      io::println(event.to_str());
+    }
+  };
+
+  match disasm::disassemble(bytes, details, print, recv) {
+    (Ok(end), _, recv) => Some((((end + 7) / 8) as uint, recv)),
+    (Err((err, offset)), _, _) => {
+      io::println(fmt!("error at bit %s: %s", offset.to_str(), err.to_str()));
+      None
+    },
+  }
+}
+
+fn disasm_raw(bytes: &[u8], details: bool) -> bool {
+  match disasm_deflate(bytes, details, ()) {
+    Some((len, ())) => {
+      if len < bytes.len() {
+        io::println(fmt!("%u bytes after the end of the stream",
+          bytes.len() - len));
+      }
+      true
+    },
+    None => false,
+  }
+}
+
+fn disasm_zlib(bytes: &[u8], details: bool) -> bool {
+  if bytes.len() < 2 {
+    io::println("truncated zlib header");
+    return false;
+  }
+
+  let (cmf, flg) = (bytes[0], bytes[1]);
+  let fcheck_ok = (cmf as uint * 256 + flg as uint) % 31 == 0;
+  io::println(fmt!("zlib header: method %u, window 2^%u, level %u, \
+      check %s",
+    (cmf & 0x0f) as uint, (cmf >> 4) as uint + 8, (flg >> 6) as uint,
+    if fcheck_ok { "ok" } else { "bad" }));
+
+  if flg & 0x20 != 0 {
+    if bytes.len() >= 6 {
+      io::println(fmt!("preset dictionary 0x%08x (not supported)",
+        be32(bytes.slice(2, 6)) as uint));
+    }
+    return false;
+  }
+
+  let data = bytes.slice(2, bytes.len());
+  match disasm_deflate(data, details, adler32::Adler32::new()) {
+    Some((len, a32)) => {
+      let trailer = data.slice(len, data.len());
+      if trailer.len() < 4 {
+        io::println("truncated adler32 trailer");
+        return false;
+      }
+
+      let read = be32(trailer.slice(0, 4));
+      let computed = a32.adler32();
+      io::println(fmt!("adler32 0x%08x, computed 0x%08x: %s",
+        read as uint, computed as uint,
+        if read == computed { "ok" } else { "mismatch" }));
+      read == computed
+    },
+    None => false,
+  }
+}
+
+fn disasm_gzip(bytes: &[u8], details: bool) -> bool {
+  let mut rest = bytes;
+  let mut ok = true;
+
+  while !rest.is_empty() {
+    io::println(fmt!("gzip member at byte %u", bytes.len() - rest.len()));
+    let data = match hdr_decoder::HeaderDecoder::new().input(rest) {
+        Left(_) => {
+          io::println("truncated gzip header");
+          return false;
+        },
+        Right((Err(err), _)) => {
+          io::println(fmt!("bad gzip header: %s", err.to_str()));
+          return false;
+        },
+        Right((Ok(header), data)) => {
+          print_gzip_header(header);
+          data
+        },
+      };
+
+    match disasm_deflate(data, details, (crc32::Crc32::new(), 0u32)) {
+      Some((len, (crc, size))) => {
+        let trailer = data.slice(len, data.len());
+        if trailer.len() < 8 {
+          io::println("truncated gzip trailer");
+          return false;
+        }
+
+        let read_crc = le32(trailer.slice(0, 4));
+        let read_size = le32(trailer.slice(4, 8));
+        io::println(fmt!("crc32 0x%08x, computed 0x%08x: %s",
+          read_crc as uint, crc.crc32() as uint,
+          if read_crc == crc.crc32() { "ok" } else { "mismatch" }));
+        io::println(fmt!("isize %u, computed %u: %s",
+          read_size as uint, size as uint,
+          if read_size == size { "ok" } else { "mismatch" }));
+
+        ok = ok && read_crc == crc.crc32() && read_size == size;
+        rest = trailer.slice(8, trailer.len());
+      },
+      None => return false,
+    }
+  }
+  ok
+}
+
+fn print_gzip_header(header: &header::Header) {
+  io::println(fmt!("  text %b, header crc %b, xfl %u, os %s",
+    header.is_text, header.has_crc, header.extra_flags as uint,
+    header::System::to_str(header.system)));
+  match header.mtime {
+    Some(mtime) => io::println(fmt!("  mtime %u", mtime as uint)),
+    None => { },
+  }
+  match header.extras {
+    Some(ref extras) => for extras.iter().advance |extra| {
+      let (id1, id2) = extra.id;
+      io::println(fmt!("  extra %c%c, %u bytes",
+        id1 as char, id2 as char, extra.data.len()));
+    },
+    None => { },
+  }
+  match header.file_name_str(header::Latin1) {
+    Some(name) => io::println(fmt!("  file name %?", name)),
+    None => { },
+  }
+  match header.comment_str(header::Latin1) {
+    Some(comment) => io::println(fmt!("  comment %?", comment)),
+    None => { },
+  }
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+  (bytes[0] as u32 << 24) | (bytes[1] as u32 << 16)
+    | (bytes[2] as u32 << 8) | bytes[3] as u32
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+  (bytes[3] as u32 << 24) | (bytes[2] as u32 << 16)
+    | (bytes[1] as u32 << 8) | bytes[0] as u32
+}